@@ -7,6 +7,12 @@ import { Signal } from './events'
 
 const reconnectTimeout = 5000
 
+// version of the WebSocket protocol spoken by this client
+const protocolVersion = 2
+
+// pause after a failed long-polling request
+const pollRetryTimeout = 1000
+
 // client
 
 export interface BaseMessageEvent {
@@ -16,12 +22,21 @@ export interface BaseMessageEvent {
 export interface WelcomeMessageEvent extends BaseMessageEvent {
     type: 'Welcome'
     player_id: string
+    protocol: number
+    features: string[]
 }
 
 export interface RejectedEvent extends BaseMessageEvent {
     type: 'Rejected'
 }
 
+export interface RoomCreatedEvent extends BaseMessageEvent {
+    type: 'RoomCreated'
+    room: string
+    code: Option<string>
+    slug: Option<string>
+}
+
 export interface JoinedEvent extends BaseMessageEvent {
     type: 'Joined'
     room: string
@@ -49,6 +64,49 @@ export interface GameChangedEvent extends BaseMessageEvent {
     game_state: GameState
 }
 
+export interface Story {
+    key?: string
+    title: string
+    link?: string
+    description?: string
+}
+
+export interface StoriesChangedEvent extends BaseMessageEvent {
+    type: 'StoriesChanged'
+    stories: Story[]
+}
+
+export interface NoticeEvent extends BaseMessageEvent {
+    type: 'Notice'
+    message: string
+}
+
+export interface ServerShuttingDownEvent extends BaseMessageEvent {
+    type: 'ServerShuttingDown'
+    reconnect_after_ms: number
+}
+
+export type ErrorCode =
+    | 'UNSUPPORTED_PROTOCOL'
+    | 'ROOM_DOES_NOT_EXIST'
+    | 'CREATE_ROOM_FAILED'
+    | 'JOIN_ROOM_FAILED'
+    | 'SERVER_SHUTTING_DOWN'
+    | 'ROOM_CLOSED'
+    | 'INVALID_SLUG'
+    | 'SLUG_TAKEN'
+    | 'NOT_IN_ROOM'
+    | 'VOTE_WHILE_OPEN'
+    | 'NON_VOTER_VOTE'
+    | 'RATE_LIMITED'
+
+export interface ErrorEvent extends BaseMessageEvent {
+    type: 'Error'
+    code: ErrorCode
+    message: string
+    context?: Record<string, string>
+}
+
 export interface PlayerInfo {
     id: string
     name: Option<string>
@@ -69,7 +127,7 @@ export interface GameState {
 export type PlayerState = 'connecting' | 'outside' | 'joining' | 'joined'
 
 export class Client {
-    _ws!: Option<WebSocket>
+    _ws!: Option<Socket>
 
     state: Writable<PlayerState>
     playerId: Writable<Option<string>>
@@ -79,12 +137,16 @@ export class Client {
     private wsService: WebSocketService
 
     welcome = new Signal<WelcomeMessageEvent>()
+    roomCreated = new Signal<RoomCreatedEvent>()
     joined = new Signal<JoinedEvent>()
     playerJoined = new Signal<PlayerJoinedEvent>()
     playerChanged = new Signal<PlayerChangedEvent>()
     playerLeft = new Signal<PlayerLeftEvent>()
     stateChanged = new Signal<GameChangedEvent>()
+    storiesChanged = new Signal<StoriesChangedEvent>()
     rejected = new Signal<RejectedEvent>()
+    notice = new Signal<NoticeEvent>()
+    error = new Signal<ErrorEvent>()
 
     constructor(wsService: WebSocketService) {
         this.state = writable('connecting')
@@ -141,7 +203,22 @@ export class Client {
         })
     }
 
-    async createRoom(deckId: string, customDeck: string): Promise<string> {
+    /** Create a room and join it without a separate HTTP request */
+    createAndJoinRoom(deck: string, slug: Option<string> = null) {
+        this.state.set('joining')
+        this.roomId.set(null)
+        this._send({
+            type: 'CreateRoom',
+            deck,
+            slug,
+        })
+    }
+
+    async createRoom(
+        deckId: string,
+        customDeck: string,
+        slug: string = '',
+    ): Promise<string> {
         const response = await fetch(`${this.wsService.backendUrl()}/mkroom`, {
             method: 'POST',
             headers: {
@@ -150,6 +227,7 @@ export class Client {
             body: new URLSearchParams({
                 deck: deckId,
                 custom_deck: customDeck,
+                slug,
             }),
             redirect: 'manual',
             mode: 'cors',
@@ -157,6 +235,10 @@ export class Client {
 
         if (response.status === 200) {
             return response.headers.get('Location') ?? ''
+        } else if (response.status === 409) {
+            throw new Error(`Room name "${slug}" is already taken`)
+        } else if (response.status === 422) {
+            throw new Error(`Room name "${slug}" is invalid`)
         } else {
             throw new Error(
                 `Failed to create room: ${response.status} (${response.type})`,
@@ -187,6 +269,10 @@ export class Client {
                 break
             }
 
+            case 'RoomCreated':
+                this.roomCreated.emit(event as RoomCreatedEvent)
+                break
+
             case 'Joined': {
                 this.state.set('joined')
 
@@ -212,10 +298,33 @@ export class Client {
                 this.stateChanged.emit(event as GameChangedEvent)
                 break
 
+            case 'StoriesChanged':
+                this.storiesChanged.emit(event as StoriesChangedEvent)
+                break
+
+            case 'Notice':
+                this.notice.emit(event as NoticeEvent)
+                break
+
+            case 'ServerShuttingDown':
+                this.wsService.reconnectAfter(
+                    (event as ServerShuttingDownEvent).reconnect_after_ms,
+                )
+                break
+
+            case 'Error':
+                console.warn('Server error', event)
+                this.lastError.set((event as ErrorEvent).message)
+                if ((event as ErrorEvent).code === 'UNSUPPORTED_PROTOCOL') {
+                    // reconnecting does not help, a reload fetches the current client
+                    this.wsService.stopReconnecting()
+                }
+                this.error.emit(event as ErrorEvent)
+                break
+
             case 'Rejected':
                 this.state.set('outside')
                 this.roomId.set(null)
-                this.lastError.set('Room does not exist')
                 this.rejected.emit(event as RejectedEvent)
                 break
 
@@ -226,13 +335,182 @@ export class Client {
     }
 }
 
+interface Envelope {
+    seq: number
+    message: unknown
+}
+
+interface PollResponse {
+    messages: Envelope[]
+    closed?: string
+}
+
+/**
+ * Connection over HTTP long polling, for networks that block WebSockets
+ *
+ * Emits the same events as a WebSocket, so it can be used in its place.
+ */
+export class LongPollSocket extends EventTarget {
+    private readonly baseUrl: string
+    private session: Option<string> = null
+    // sequence number of the last message received from the server
+    private ack = 0
+    private sentSeq = 0
+    private outbox: Envelope[] = []
+    private sending = false
+    private closed = false
+
+    constructor(baseUrl: string) {
+        super()
+        this.baseUrl = baseUrl
+        void this.open()
+    }
+
+    send(data: string) {
+        this.outbox.push({ seq: ++this.sentSeq, message: JSON.parse(data) })
+        void this.flush()
+    }
+
+    close() {
+        if (this.closed) {
+            return
+        }
+        this.closed = true
+        if (this.session !== null) {
+            const url = this.sessionUrl()
+            fetch(url, { method: 'DELETE', mode: 'cors' }).catch(() => {})
+        }
+        this.dispatchEvent(new Event('close'))
+    }
+
+    private sessionUrl() {
+        return `${this.baseUrl}/poll/${this.session}`
+    }
+
+    private fail(reason: unknown) {
+        if (this.closed) {
+            return
+        }
+        console.warn('Long polling failed', reason)
+        this.dispatchEvent(new Event('error'))
+        this.close()
+    }
+
+    private async open() {
+        try {
+            const response = await fetch(
+                `${this.baseUrl}/poll?protocol=${protocolVersion}`,
+                { method: 'POST', mode: 'cors' },
+            )
+            if (!response.ok) {
+                throw new Error(`Failed to open session: ${response.status}`)
+            }
+            this.session = (await response.json()).session
+        } catch (err) {
+            this.fail(err)
+            return
+        }
+
+        this.dispatchEvent(new Event('open'))
+        void this.flush()
+        void this.receive()
+    }
+
+    private async receive() {
+        let failures = 0
+        while (!this.closed) {
+            let poll: PollResponse
+            try {
+                const response = await fetch(
+                    `${this.sessionUrl()}?ack=${this.ack}`,
+                    { mode: 'cors' },
+                )
+                if (response.status === 404) {
+                    // session expired, a new one starts with the current state
+                    this.fail(new Error('Session expired'))
+                    return
+                }
+                if (!response.ok) {
+                    throw new Error(`Poll failed: ${response.status}`)
+                }
+                poll = await response.json()
+                failures = 0
+            } catch (err) {
+                // unacknowledged messages are sent again, so retrying is safe
+                if (++failures > 3) {
+                    this.fail(err)
+                    return
+                }
+                await new Promise((resolve) =>
+                    setTimeout(resolve, pollRetryTimeout),
+                )
+                continue
+            }
+
+            for (const envelope of poll.messages) {
+                if (envelope.seq > this.ack && !this.closed) {
+                    this.ack = envelope.seq
+                    this.dispatchEvent(
+                        new MessageEvent('message', {
+                            data: JSON.stringify(envelope.message),
+                        }),
+                    )
+                }
+            }
+            if (poll.closed) {
+                console.log('Session closed by server:', poll.closed)
+                this.close()
+            }
+        }
+    }
+
+    private async flush() {
+        if (this.sending || this.session === null || this.closed) {
+            return
+        }
+        this.sending = true
+        try {
+            while (this.outbox.length > 0 && !this.closed) {
+                const response = await fetch(this.sessionUrl(), {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({ messages: this.outbox }),
+                    mode: 'cors',
+                })
+                if (!response.ok) {
+                    throw new Error(`Send failed: ${response.status}`)
+                }
+                const { ack } = await response.json()
+                this.outbox = this.outbox.filter(
+                    (envelope) => envelope.seq > ack,
+                )
+            }
+        } catch (err) {
+            this.fail(err)
+        } finally {
+            this.sending = false
+        }
+    }
+}
+
+/** Part of a WebSocket used by the client */
+interface Socket extends EventTarget {
+    send(data: string): void
+    close(): void
+}
+
 export class WebSocketService {
-    ws!: Option<WebSocket>
-    ws_store: Writable<Option<WebSocket>>
+    ws!: Option<Socket>
+    ws_store: Writable<Option<Socket>>
     connecting_store: Writable<boolean>
     connected_store: Writable<boolean>
     error_store: Writable<boolean>
     reconnectTimer: Option<number>
+    reconnectDelay: Option<number> = null
+    reconnectDisabled = false
+    // WebSockets could not be opened, for example because of a proxy
+    useLongPolling = false
+    private opened = false
 
     message = new Signal<BaseMessageEvent>()
     connected = new Signal<undefined>()
@@ -256,13 +534,26 @@ export class WebSocketService {
 
     startReconnectTimer() {
         this.clearReconnectTimer()
-        this.reconnectTimer = Number(
-            setTimeout(() => this.connect(), reconnectTimeout),
-        )
+        if (this.reconnectDisabled) {
+            return
+        }
+        const delay = this.reconnectDelay ?? reconnectTimeout
+        this.reconnectDelay = null
+        this.reconnectTimer = Number(setTimeout(() => this.connect(), delay))
+    }
+
+    stopReconnecting() {
+        this.reconnectDisabled = true
+        this.clearReconnectTimer()
+    }
+
+    reconnectAfter(delay: number) {
+        this.reconnectDelay = delay
     }
 
     on_connected(event: Event) {
         console.log('connected', event)
+        this.opened = true
         this.connected_store.set(true)
         this.connecting_store.set(false)
         this.error_store.set(false)
@@ -271,7 +562,7 @@ export class WebSocketService {
         this.clearReconnectTimer()
     }
 
-    on_disconnected(event: CloseEvent) {
+    on_disconnected(event: Event) {
         if (this.ws == null) {
             return
         }
@@ -289,6 +580,13 @@ export class WebSocketService {
         }
 
         console.log('error', event)
+        if (!this.opened) {
+            // try the other transport, the server itself might be down
+            this.useLongPolling = !this.useLongPolling
+            if (this.useLongPolling) {
+                console.log('WebSocket failed, falling back to long polling')
+            }
+        }
         this.connected_store.set(false)
         this.connecting_store.set(false)
         this.error_store.set(true)
@@ -319,17 +617,25 @@ export class WebSocketService {
     }
 
     connect() {
-        const url = this.wsUrl()
-        console.debug('connecting to ' + url + ' ...', url)
         this.connecting_store.set(true)
+        this.opened = false
 
-        this.ws = new WebSocket(url)
-        this.ws.addEventListener('open', (evt) => this.on_connected(evt))
-        this.ws.addEventListener('message', (evt) => {
-            this.message.emit(JSON.parse(evt.data))
+        let socket: Socket
+        if (this.useLongPolling) {
+            console.debug('connecting with long polling ...')
+            socket = new LongPollSocket(this.backendUrl())
+        } else {
+            const url = this.wsUrl()
+            console.debug('connecting to ' + url + ' ...', url)
+            socket = new WebSocket(url, [`goe.v${protocolVersion}`])
+        }
+        this.ws = socket
+        socket.addEventListener('open', (evt) => this.on_connected(evt))
+        socket.addEventListener('message', (evt) => {
+            this.message.emit(JSON.parse((evt as MessageEvent).data))
         })
-        this.ws.addEventListener('close', (evt) => this.on_disconnected(evt))
-        this.ws.addEventListener('error', (evt) =>
+        socket.addEventListener('close', (evt) => this.on_disconnected(evt))
+        socket.addEventListener('error', (evt) =>
             this.on_connection_error(evt),
         )
 
@@ -0,0 +1,7 @@
+CREATE TABLE room_leases (
+    room_id UUID PRIMARY KEY NOT NULL,
+    instance_id UUID NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX room_leases_instance_idx ON room_leases(instance_id);
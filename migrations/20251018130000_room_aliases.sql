@@ -0,0 +1,7 @@
+CREATE TABLE room_aliases (
+    alias TEXT PRIMARY KEY NOT NULL,
+    room_id UUID NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX room_aliases_room_idx ON room_aliases(room_id);
@@ -0,0 +1,6 @@
+CREATE TABLE webhook_secrets (
+    room_id UUID NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    PRIMARY KEY (room_id, url)
+);
@@ -0,0 +1,16 @@
+CREATE TABLE room_events (
+    event_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    occurred_at TEXT NOT NULL,
+    room_id BLOB NOT NULL,
+    event_data TEXT NOT NULL
+);
+
+CREATE INDEX room_events_idx ON room_events(room_id);
+
+CREATE TABLE room_aliases (
+    alias TEXT PRIMARY KEY NOT NULL,
+    room_id BLOB NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX room_aliases_room_idx ON room_aliases(room_id);
@@ -0,0 +1,207 @@
+//! Behaviour every storage adapter has to provide
+//!
+//! Adapters run these checks in their tests. They only use fresh room IDs
+//! and aliases, so they can run against a shared database, except the
+//! retention checks, which purge all rooms.
+
+use std::collections::HashMap;
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+
+use crate::ports::{
+    PurgedRooms, RoomAliasRepository, RoomRepository, RoomRetentionRepository,
+    WebhookSecretRepository,
+};
+use crate::room::{RoomEvent, Round};
+use crate::room_id::RoomId;
+use crate::story::Story;
+
+fn unique_alias() -> String {
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect();
+    format!("contract-{}", suffix.to_lowercase())
+}
+
+fn all_events() -> Vec<RoomEvent> {
+    let round: Round = serde_json::from_value(json!({
+        "number": 1,
+        "revealed_at": 1700000000,
+        "state": {"deck": "fibonacci", "open": true, "votes": {"p1": "3", "p2": null}},
+        "players": [{"id": "p1", "name": "Alice", "voter": true}],
+    }))
+    .unwrap();
+    vec![
+        RoomEvent::Created {
+            deck: "fibonacci".to_string(),
+        },
+        RoomEvent::PlayerJoined {
+            player_id: "p1".to_string(),
+        },
+        RoomEvent::StoriesChanged {
+            stories: vec![Story {
+                key: Some("GOE-1".to_string()),
+                title: "Export results".to_string(),
+                link: None,
+                description: None,
+            }],
+        },
+        RoomEvent::RoundRevealed { round },
+        RoomEvent::PlayerLeaved {
+            player_id: "p1".to_string(),
+        },
+    ]
+}
+
+pub async fn check_room_repository(repo: &(dyn RoomRepository + Sync)) {
+    let room = RoomId::generate();
+    let other = RoomId::generate();
+
+    for event in all_events() {
+        repo.append_room_event(&room, event).await.unwrap();
+    }
+    repo.append_room_event(
+        &other,
+        RoomEvent::Created {
+            deck: "t-shirt-sizes".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    // events of a room in order of appending
+    assert_eq!(repo.get_room_events(&room).await.unwrap(), all_events());
+    assert_eq!(repo.get_room_events(&other).await.unwrap().len(), 1);
+    assert_eq!(
+        repo.get_room_events(&RoomId::generate()).await.unwrap(),
+        vec![]
+    );
+
+    // batches of multiple rooms
+    let third = RoomId::generate();
+    let batch = all_events()
+        .into_iter()
+        .flat_map(|event| [(third, event.clone()), (other, event)])
+        .collect();
+    repo.append_room_events(batch).await.unwrap();
+    assert_eq!(repo.get_room_events(&third).await.unwrap(), all_events());
+    assert_eq!(
+        repo.get_room_events(&other).await.unwrap().len(),
+        all_events().len() + 1
+    );
+}
+
+pub async fn check_room_alias_repository(repo: &dyn RoomAliasRepository) {
+    let room = RoomId::generate();
+    let other = RoomId::generate();
+    let alias = unique_alias();
+    let second_alias = unique_alias();
+
+    assert!(repo.create_room_alias(&alias, &room).await.unwrap());
+    assert!(!repo.create_room_alias(&alias, &other).await.unwrap());
+    assert!(repo.create_room_alias(&second_alias, &room).await.unwrap());
+    assert_eq!(repo.resolve_room_alias(&alias).await.unwrap(), Some(room));
+    assert_eq!(
+        repo.resolve_room_alias(&unique_alias()).await.unwrap(),
+        None
+    );
+
+    repo.delete_room_aliases(&room).await.unwrap();
+    assert_eq!(repo.resolve_room_alias(&alias).await.unwrap(), None);
+    assert_eq!(repo.resolve_room_alias(&second_alias).await.unwrap(), None);
+    // deleted aliases can be taken again
+    assert!(repo.create_room_alias(&alias, &other).await.unwrap());
+}
+
+pub async fn check_webhook_secret_repository(repo: &dyn WebhookSecretRepository) {
+    let room = RoomId::generate();
+    let other = RoomId::generate();
+    let url = "https://example.com/hook";
+
+    repo.set_webhook_secret(&room, url, Some("s1"))
+        .await
+        .unwrap();
+    repo.set_webhook_secret(&room, url, Some("s2"))
+        .await
+        .unwrap();
+    repo.set_webhook_secret(&room, "https://example.com/other", Some("s3"))
+        .await
+        .unwrap();
+    repo.set_webhook_secret(&other, url, Some("s4"))
+        .await
+        .unwrap();
+    let secrets = repo.get_webhook_secrets(&room).await.unwrap();
+    assert_eq!(
+        secrets,
+        HashMap::from([
+            (url.to_string(), "s2".to_string()),
+            ("https://example.com/other".to_string(), "s3".to_string()),
+        ])
+    );
+
+    // removing a secret keeps the other ones
+    repo.set_webhook_secret(&room, url, None).await.unwrap();
+    let secrets = repo.get_webhook_secrets(&room).await.unwrap();
+    assert_eq!(secrets.len(), 1);
+
+    repo.delete_webhook_secrets(&room).await.unwrap();
+    assert_eq!(
+        repo.get_webhook_secrets(&room).await.unwrap(),
+        HashMap::new()
+    );
+    assert_eq!(repo.get_webhook_secrets(&other).await.unwrap().len(), 1);
+}
+
+/// Needs a repository without other rooms
+pub async fn check_room_retention_repository(
+    repo: &dyn RoomRepository,
+    aliases: &dyn RoomAliasRepository,
+    secrets: &dyn WebhookSecretRepository,
+    retention: &dyn RoomRetentionRepository,
+) {
+    let inactive = RoomId::generate();
+    let live = RoomId::generate();
+    let alias = unique_alias();
+    for event in all_events() {
+        repo.append_room_event(&inactive, event).await.unwrap();
+    }
+    aliases.create_room_alias(&alias, &inactive).await.unwrap();
+    secrets
+        .set_webhook_secret(&inactive, "https://example.com/hook", Some("secret"))
+        .await
+        .unwrap();
+    repo.append_room_event(&live, all_events().remove(0))
+        .await
+        .unwrap();
+    let future = OffsetDateTime::now_utc() + Duration::minutes(1);
+    let expected = PurgedRooms {
+        rooms: 1,
+        events: all_events().len() as u64,
+    };
+
+    // recently active rooms are kept
+    let past = OffsetDateTime::now_utc() - Duration::days(1);
+    let purged = retention.purge_inactive_rooms(past, &[], false).await;
+    assert_eq!(purged.unwrap(), PurgedRooms::default());
+
+    // dry run only counts
+    let purged = retention.purge_inactive_rooms(future, &[live], true).await;
+    assert_eq!(purged.unwrap(), expected);
+    assert_eq!(repo.get_room_events(&inactive).await.unwrap(), all_events());
+
+    let purged = retention.purge_inactive_rooms(future, &[live], false).await;
+    assert_eq!(purged.unwrap(), expected);
+    assert_eq!(repo.get_room_events(&inactive).await.unwrap(), vec![]);
+    assert_eq!(aliases.resolve_room_alias(&alias).await.unwrap(), None);
+    assert!(secrets
+        .get_webhook_secrets(&inactive)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(repo.get_room_events(&live).await.unwrap().len(), 1);
+}
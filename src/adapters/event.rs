@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use crate::room::{RoomEvent, Round};
+use crate::story::Story;
+
+/// Stored form of a `RoomEvent`, shared by all storage adapters
+#[derive(Debug, Serialize, Deserialize)]
+pub enum DbRoomEvent {
+    Created { deck: String },
+    PlayerJoined { player_id: String },
+    PlayerLeaved { player_id: String },
+    StoriesChanged { stories: Vec<Story> },
+    RoundRevealed { round: Round },
+    WebhooksChanged { urls: Vec<String> },
+}
+
+impl From<RoomEvent> for DbRoomEvent {
+    fn from(value: RoomEvent) -> Self {
+        match value {
+            RoomEvent::Created { deck } => DbRoomEvent::Created { deck },
+            RoomEvent::PlayerJoined { player_id } => DbRoomEvent::PlayerJoined { player_id },
+            RoomEvent::PlayerLeaved { player_id } => DbRoomEvent::PlayerLeaved { player_id },
+            RoomEvent::StoriesChanged { stories } => DbRoomEvent::StoriesChanged { stories },
+            RoomEvent::RoundRevealed { round } => DbRoomEvent::RoundRevealed { round },
+            RoomEvent::WebhooksChanged { urls } => DbRoomEvent::WebhooksChanged { urls },
+        }
+    }
+}
+
+impl From<DbRoomEvent> for RoomEvent {
+    fn from(value: DbRoomEvent) -> Self {
+        match value {
+            DbRoomEvent::Created { deck } => RoomEvent::Created { deck },
+            DbRoomEvent::PlayerJoined { player_id } => RoomEvent::PlayerJoined { player_id },
+            DbRoomEvent::PlayerLeaved { player_id } => RoomEvent::PlayerLeaved { player_id },
+            DbRoomEvent::StoriesChanged { stories } => RoomEvent::StoriesChanged { stories },
+            DbRoomEvent::RoundRevealed { round } => RoomEvent::RoundRevealed { round },
+            DbRoomEvent::WebhooksChanged { urls } => RoomEvent::WebhooksChanged { urls },
+        }
+    }
+}
@@ -0,0 +1,1157 @@
+//! Append-only event log in JSONL files, for running without a database
+//!
+//! Records are appended to segment files `segment-<n>.jsonl`, a new segment
+//! is started when the current one is full. An in-memory index points to the
+//! records of every room, so reading them does not scan the log.
+//!
+//! A crash while appending can leave a truncated last line, which is cut off
+//! on startup. Compaction runs in the background, it merges the sealed
+//! segments into one and drops the joins and leaves of players that already
+//! left, while records are appended to a new segment. It writes
+//! `compact-<n>.jsonl` first, which replaces segment `n` and all older ones
+//! once it is complete, so an interrupted compaction is finished or
+//! discarded on the next start.
+//! Purged rooms are removed from the index at once and from the files by the
+//! next compaction.
+//!
+//! Webhook secrets are not part of the log, they are kept in
+//! `webhook-secrets.json`, which is only readable by the owner and replaced
+//! as a whole on every change.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::adapters::memory::{MemoryMessageBus, MemoryMigrator, MemoryRepository};
+use crate::adapters::DbRoomEvent;
+use crate::ports::{
+    DatabaseMigratorRef, DatabaseUrl, DbResult, MessageBusRef, PurgedRooms, RoomAliasRepository,
+    RoomAliasRepositoryRef, RoomLeaseRepositoryRef, RoomRepository, RoomRepositoryRef,
+    RoomRetentionRepository, RoomRetentionRepositoryRef, WebhookSecretRepository,
+    WebhookSecretRepositoryRef,
+};
+use crate::room::RoomEvent;
+use crate::room_id::RoomId;
+
+const SEGMENT_PREFIX: &str = "segment-";
+const COMPACT_PREFIX: &str = "compact-";
+const EXTENSION: &str = ".jsonl";
+const SECRETS_FILE: &str = "webhook-secrets.json";
+
+/// When appended records are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every record, nothing is lost on power failure
+    Always,
+    /// In the background, records of the last interval can be lost
+    Interval(Duration),
+    /// When the operating system decides to
+    Never,
+}
+
+impl std::str::FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            // an interval of 0 would sync without pause
+            secs => match secs.parse() {
+                Ok(0) | Err(_) => Err(format!(
+                    "expected always, never or seconds above 0, got {s}"
+                )),
+                Ok(secs) => Ok(Self::Interval(Duration::from_secs(secs))),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileStoreConfig {
+    pub fsync: FsyncPolicy,
+    /// Segment size that starts a new segment
+    pub segment_size: u64,
+    /// Sealed segments that trigger a compaction
+    pub compact_after: usize,
+}
+
+impl Default for FileStoreConfig {
+    fn default() -> Self {
+        Self {
+            fsync: FsyncPolicy::Always,
+            segment_size: 64 * 1024 * 1024,
+            compact_after: 8,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FileModule;
+
+#[chassis::module]
+impl FileModule {
+    #[chassis(singleton)]
+    pub fn provide_file_store(database_url: DatabaseUrl) -> Arc<FileEventStore> {
+        let url = &database_url.0;
+        let dir = url
+            .strip_prefix("file://")
+            .or_else(|| url.strip_prefix("file:"))
+            .expect("DATABASE_URL should be a file URL");
+        let config = FileStoreConfig {
+            fsync: std::env::var("GOE_FILE_FSYNC")
+                .map(|value| value.parse().expect("GOE_FILE_FSYNC is invalid"))
+                .unwrap_or(FsyncPolicy::Always),
+            ..FileStoreConfig::default()
+        };
+        FileEventStore::open(dir, config).expect("Unable to open event log")
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_db_migrator() -> DatabaseMigratorRef {
+        Arc::new(MemoryMigrator)
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_repo(store: Arc<FileEventStore>) -> RoomRepositoryRef {
+        store
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_alias_repo(store: Arc<FileEventStore>) -> RoomAliasRepositoryRef {
+        store
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_webhook_secret_repo(store: Arc<FileEventStore>) -> WebhookSecretRepositoryRef {
+        store
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_retention_repo(store: Arc<FileEventStore>) -> RoomRetentionRepositoryRef {
+        store
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_lease_repo() -> RoomLeaseRepositoryRef {
+        Arc::new(MemoryRepository::default())
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_message_bus() -> MessageBusRef {
+        Arc::new(MemoryMessageBus::default())
+    }
+}
+
+/// Line of the log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Event {
+        room: RoomId,
+        /// Unix timestamp in seconds
+        at: u64,
+        event: DbRoomEvent,
+    },
+    AliasCreated {
+        alias: String,
+        room: RoomId,
+    },
+    AliasesDeleted {
+        room: RoomId,
+    },
+    /// Events and aliases of the room are deleted
+    RoomPurged {
+        room: RoomId,
+    },
+}
+
+/// Position of a record in the log
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    segment: u64,
+    offset: u64,
+    len: u64,
+}
+
+fn segment_path(dir: &Path, prefix: &str, id: u64) -> PathBuf {
+    dir.join(format!("{prefix}{id:08}{EXTENSION}"))
+}
+
+fn parse_file_name(name: &str) -> Option<(&'static str, u64)> {
+    [SEGMENT_PREFIX, COMPACT_PREFIX]
+        .into_iter()
+        .find_map(|prefix| {
+            let id = name.strip_prefix(prefix)?.strip_suffix(EXTENSION)?;
+            Some((prefix, id.parse().ok()?))
+        })
+}
+
+fn read_records(dir: &Path, locations: &[Location]) -> io::Result<Vec<Vec<u8>>> {
+    let mut res = Vec::with_capacity(locations.len());
+    let mut file: Option<(u64, File)> = None;
+    for location in locations {
+        let file = match &mut file {
+            Some((segment, file)) if *segment == location.segment => file,
+            _ => {
+                let path = segment_path(dir, SEGMENT_PREFIX, location.segment);
+                &mut file.insert((location.segment, File::open(path)?)).1
+            }
+        };
+        let mut buf = vec![0; location.len as usize];
+        file.seek(SeekFrom::Start(location.offset))?;
+        file.read_exact(&mut buf)?;
+        res.push(buf);
+    }
+    Ok(res)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+struct Log {
+    dir: PathBuf,
+    config: FileStoreConfig,
+    /// All segments, the last one is appended to
+    segments: BTreeSet<u64>,
+    active: File,
+    active_len: u64,
+    /// Appended data that is not flushed to disk yet
+    dirty: bool,
+    events: HashMap<RoomId, Vec<Location>>,
+    /// Unix timestamp of the last event of every room
+    last_event_at: HashMap<RoomId, u64>,
+    aliases: HashMap<String, RoomId>,
+    /// Webhook secrets by room and URL, stored in their own file
+    secrets: HashMap<RoomId, HashMap<String, String>>,
+    compacting: bool,
+}
+
+/// Records of the sealed segments, taken when a compaction starts
+struct Compaction {
+    dir: PathBuf,
+    /// Last sealed segment, the result replaces it and all older ones
+    id: u64,
+    events: HashMap<RoomId, Vec<Location>>,
+    aliases: HashMap<String, RoomId>,
+}
+
+/// Written result of a compaction
+struct Compacted {
+    id: u64,
+    tmp_path: PathBuf,
+    events: HashMap<RoomId, Vec<Location>>,
+    len: u64,
+    dropped: usize,
+}
+
+impl Log {
+    fn open(dir: &Path, config: FileStoreConfig) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Self::finish_compaction(dir)?;
+
+        let mut segments = BTreeSet::new();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            if let Some((SEGMENT_PREFIX, id)) = name.to_str().and_then(parse_file_name) {
+                segments.insert(id);
+            }
+        }
+        if segments.is_empty() {
+            segments.insert(1);
+        }
+
+        let active_id = *segments.last().unwrap();
+        let active = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(segment_path(dir, SEGMENT_PREFIX, active_id))?;
+        let mut log = Self {
+            dir: dir.to_path_buf(),
+            config,
+            segments,
+            active,
+            active_len: 0,
+            dirty: false,
+            events: HashMap::new(),
+            last_event_at: HashMap::new(),
+            aliases: HashMap::new(),
+            secrets: Self::load_secrets(dir)?,
+            compacting: false,
+        };
+        for id in log.segments.clone() {
+            log.load_segment(id)?;
+        }
+        log.active_len = log.active.metadata()?.len();
+        Ok(log)
+    }
+
+    fn load_secrets(dir: &Path) -> io::Result<HashMap<RoomId, HashMap<String, String>>> {
+        match fs::read(dir.join(SECRETS_FILE)) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replace the secrets file, which is only readable by the owner
+    fn write_secrets(&self) -> io::Result<()> {
+        let tmp_path = self.dir.join(format!("{SECRETS_FILE}.tmp"));
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(&self.secrets)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SECRETS_FILE))?;
+        File::open(&self.dir)?.sync_all()
+    }
+
+    /// Replace older segments with a complete compaction result
+    fn finish_compaction(dir: &Path) -> io::Result<()> {
+        let mut compacted = None;
+        let mut segments = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else { continue };
+            if name.ends_with(".tmp") {
+                // compaction was interrupted before it was complete
+                fs::remove_file(entry.path())?;
+            }
+            match parse_file_name(name) {
+                Some((COMPACT_PREFIX, id)) => compacted = Some(id),
+                Some((_, id)) => segments.push(id),
+                None => {}
+            }
+        }
+
+        if let Some(compacted) = compacted {
+            for id in segments.into_iter().filter(|id| *id <= compacted) {
+                fs::remove_file(segment_path(dir, SEGMENT_PREFIX, id))?;
+            }
+            fs::rename(
+                segment_path(dir, COMPACT_PREFIX, compacted),
+                segment_path(dir, SEGMENT_PREFIX, compacted),
+            )?;
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Index the records of a segment, cut off a truncated last line
+    fn load_segment(&mut self, id: u64) -> io::Result<()> {
+        let path = segment_path(&self.dir, SEGMENT_PREFIX, id);
+        let data = fs::read(&path)?;
+
+        let mut offset = 0;
+        while let Some(end) = data[offset..].iter().position(|b| *b == b'\n') {
+            let line = &data[offset..offset + end];
+            match serde_json::from_slice(line) {
+                Ok(record) => self.index(
+                    record,
+                    Location {
+                        segment: id,
+                        offset: offset as u64,
+                        len: end as u64 + 1,
+                    },
+                ),
+                Err(err) => warn!("Skipped invalid record in {}: {}", path.display(), err),
+            }
+            offset += end + 1;
+        }
+
+        if offset < data.len() {
+            warn!(
+                "Cut off truncated last record of {} ({} bytes)",
+                path.display(),
+                data.len() - offset
+            );
+            let file = OpenOptions::new().write(true).open(&path)?;
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn index(&mut self, record: Record, location: Location) {
+        match record {
+            Record::Event { room, at, .. } => {
+                self.events.entry(room).or_default().push(location);
+                let last_event_at = self.last_event_at.entry(room).or_default();
+                *last_event_at = (*last_event_at).max(at);
+            }
+            Record::AliasCreated { alias, room } => {
+                self.aliases.insert(alias, room);
+            }
+            Record::AliasesDeleted { room } => self.aliases.retain(|_, id| *id != room),
+            Record::RoomPurged { room } => {
+                self.events.remove(&room);
+                self.last_event_at.remove(&room);
+                self.aliases.retain(|_, id| *id != room);
+            }
+        }
+    }
+
+    fn purge_inactive_rooms(
+        &mut self,
+        inactive_since: OffsetDateTime,
+        keep: &[RoomId],
+        dry_run: bool,
+    ) -> io::Result<PurgedRooms> {
+        let inactive_since = inactive_since.unix_timestamp();
+        let inactive: Vec<RoomId> = self
+            .last_event_at
+            .iter()
+            .filter(|(room, at)| (**at as i64) < inactive_since && !keep.contains(room))
+            .map(|(room, _)| *room)
+            .collect();
+
+        let mut purged = PurgedRooms::default();
+        let mut secrets_purged = false;
+        for room in inactive {
+            purged.rooms += 1;
+            purged.events += self.events.get(&room).map_or(0, Vec::len) as u64;
+            if !dry_run {
+                self.append(Record::RoomPurged { room })?;
+                secrets_purged |= self.secrets.remove(&room).is_some();
+            }
+        }
+        if secrets_purged {
+            self.write_secrets()?;
+        }
+        Ok(purged)
+    }
+
+    fn append(&mut self, record: Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        if self.active_len > 0 && self.active_len + line.len() as u64 > self.config.segment_size {
+            self.roll()?;
+        }
+        if let Err(err) = self.active.write_all(&line) {
+            // do not leave a partial record in front of the next one
+            let _ = self.active.set_len(self.active_len);
+            return Err(err);
+        }
+        match self.config.fsync {
+            FsyncPolicy::Always => self.active.sync_data()?,
+            FsyncPolicy::Interval(_) | FsyncPolicy::Never => self.dirty = true,
+        }
+
+        let location = Location {
+            segment: *self.segments.last().unwrap(),
+            offset: self.active_len,
+            len: line.len() as u64,
+        };
+        self.active_len += line.len() as u64;
+        self.index(record, location);
+        Ok(())
+    }
+
+    /// Start a new segment
+    fn roll(&mut self) -> io::Result<()> {
+        self.active.sync_all()?;
+        self.dirty = false;
+        let id = self.segments.last().unwrap() + 1;
+        self.active = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(segment_path(&self.dir, SEGMENT_PREFIX, id))?;
+        File::open(&self.dir)?.sync_all()?;
+        self.segments.insert(id);
+        self.active_len = 0;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.active.sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn sealed_segments(&self) -> usize {
+        self.segments.len() - 1
+    }
+
+    fn read(&self, locations: &[Location]) -> io::Result<Vec<Vec<u8>>> {
+        read_records(&self.dir, locations)
+    }
+
+    fn room_events(&self, room: &RoomId) -> io::Result<Vec<RoomEvent>> {
+        let Some(locations) = self.events.get(room) else {
+            return Ok(vec![]);
+        };
+        self.read(locations)?
+            .into_iter()
+            .map(|line| match serde_json::from_slice(&line)? {
+                Record::Event { event, .. } => Ok(RoomEvent::from(event)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "index points to a record of another type",
+                )),
+            })
+            .collect()
+    }
+
+    /// Seal the active segment and take the records of all sealed ones,
+    /// `None` while another compaction is running
+    fn start_compaction(&mut self) -> io::Result<Option<Compaction>> {
+        if self.compacting {
+            return Ok(None);
+        }
+        self.roll()?;
+        self.compacting = true;
+        Ok(Some(Compaction {
+            dir: self.dir.clone(),
+            id: *self.segments.last().unwrap() - 1,
+            events: self.events.clone(),
+            aliases: self.aliases.clone(),
+        }))
+    }
+
+    /// Replace the compacted segments, records appended meanwhile are kept
+    fn finish(&mut self, compacted: Compacted) -> io::Result<()> {
+        // the rename makes the compaction complete, even after a crash
+        let path = segment_path(&self.dir, COMPACT_PREFIX, compacted.id);
+        fs::rename(&compacted.tmp_path, path)?;
+        File::open(&self.dir)?.sync_all()?;
+        Self::finish_compaction(&self.dir)?;
+
+        self.segments.retain(|id| *id > compacted.id);
+        self.segments.insert(compacted.id);
+        // rooms purged meanwhile are not indexed anymore
+        let mut events = compacted.events;
+        for (room, locations) in &mut self.events {
+            let mut merged = events.remove(room).unwrap_or_default();
+            merged.extend(locations.iter().filter(|l| l.segment > compacted.id));
+            *locations = merged;
+        }
+        self.events.retain(|_, locations| !locations.is_empty());
+        info!(
+            "Compacted event log to {} bytes, dropped {} records",
+            compacted.len, compacted.dropped
+        );
+        Ok(())
+    }
+
+    /// Indexes of the events of a room that compaction keeps
+    ///
+    /// Joins and leaves of players that left are not needed to restore a
+    /// room.
+    fn keep_events(lines: &[Vec<u8>]) -> io::Result<Vec<usize>> {
+        let mut keep = vec![true; lines.len()];
+        let mut joined: HashMap<String, usize> = HashMap::new();
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_slice(line)? {
+                Record::Event {
+                    event: DbRoomEvent::PlayerJoined { player_id },
+                    ..
+                } => {
+                    joined.insert(player_id, i);
+                }
+                Record::Event {
+                    event: DbRoomEvent::PlayerLeaved { player_id },
+                    ..
+                } => {
+                    if let Some(join) = joined.remove(&player_id) {
+                        keep[join] = false;
+                        keep[i] = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((0..lines.len()).filter(|i| keep[*i]).collect())
+    }
+}
+
+impl Compaction {
+    /// Write the rooms and aliases into one file, without holding the log
+    fn write(self) -> io::Result<Compacted> {
+        let tmp_path = self
+            .dir
+            .join(format!("{COMPACT_PREFIX}{:08}{EXTENSION}.tmp", self.id));
+        let res = self.write_to(&tmp_path);
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        res
+    }
+
+    fn write_to(&self, tmp_path: &Path) -> io::Result<Compacted> {
+        let mut out = io::BufWriter::new(File::create(tmp_path)?);
+
+        let mut events = HashMap::new();
+        let mut offset = 0;
+        let mut dropped = 0;
+        for (room, locations) in &self.events {
+            let lines = read_records(&self.dir, locations)?;
+            let keep = Log::keep_events(&lines)?;
+            dropped += lines.len() - keep.len();
+
+            let mut room_locations = Vec::with_capacity(keep.len());
+            for i in keep {
+                out.write_all(&lines[i])?;
+                room_locations.push(Location {
+                    segment: self.id,
+                    offset,
+                    len: lines[i].len() as u64,
+                });
+                offset += lines[i].len() as u64;
+            }
+            if !room_locations.is_empty() {
+                events.insert(*room, room_locations);
+            }
+        }
+        for (alias, room) in &self.aliases {
+            let mut line = serde_json::to_vec(&Record::AliasCreated {
+                alias: alias.clone(),
+                room: *room,
+            })?;
+            line.push(b'\n');
+            out.write_all(&line)?;
+        }
+        out.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+
+        Ok(Compacted {
+            id: self.id,
+            tmp_path: tmp_path.to_path_buf(),
+            events,
+            len: offset,
+            dropped,
+        })
+    }
+}
+
+/// Repository of rooms and aliases in an append-only log
+pub struct FileEventStore {
+    log: Arc<Mutex<Log>>,
+}
+
+impl FileEventStore {
+    /// Open or create the log in `dir`
+    ///
+    /// With [`FsyncPolicy::Interval`] it has to be called within a Tokio
+    /// runtime, which flushes the log in the background.
+    pub fn open(dir: impl AsRef<Path>, config: FileStoreConfig) -> io::Result<Arc<Self>> {
+        let fsync = config.fsync;
+        let log = Log::open(dir.as_ref(), config)?;
+        let store = Arc::new(Self {
+            log: Arc::new(Mutex::new(log)),
+        });
+        if let FsyncPolicy::Interval(interval) = fsync {
+            tokio::spawn(Self::flush_periodically(
+                Arc::downgrade(&store.log),
+                interval,
+            ));
+        }
+        Ok(store)
+    }
+
+    async fn flush_periodically(log: Weak<Mutex<Log>>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            let Some(log) = log.upgrade() else { break };
+            let res = tokio::task::spawn_blocking(move || log.lock().unwrap().flush()).await;
+            if let Ok(Err(err)) = res {
+                warn!("Failed to flush event log: {}", err);
+            }
+        }
+    }
+
+    /// Run a blocking operation on the log
+    async fn with_log<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Log) -> io::Result<T> + Send + 'static,
+    ) -> DbResult<T> {
+        let log = self.log.clone();
+        Ok(tokio::task::spawn_blocking(move || f(&mut log.lock().unwrap())).await??)
+    }
+
+    /// Merge all sealed segments and drop records that are not needed
+    /// anymore
+    ///
+    /// Appends go on meanwhile, into a new segment. Does nothing while
+    /// another compaction is running.
+    pub async fn compact(&self) -> DbResult<()> {
+        Self::run_compaction(self.log.clone()).await?;
+        Ok(())
+    }
+
+    /// Returns whether enough segments were sealed meanwhile for another
+    /// compaction
+    async fn run_compaction(log: Arc<Mutex<Log>>) -> DbResult<bool> {
+        let started = {
+            let log = log.clone();
+            tokio::task::spawn_blocking(move || log.lock().unwrap().start_compaction()).await??
+        };
+        let Some(compaction) = started else {
+            return Ok(false);
+        };
+        let written = tokio::task::spawn_blocking(move || compaction.write()).await?;
+        let again = tokio::task::spawn_blocking(move || {
+            let mut log = log.lock().unwrap();
+            log.compacting = false;
+            log.finish(written?)?;
+            // not counting the compacted segment
+            Ok::<_, io::Error>(log.sealed_segments() > log.config.compact_after)
+        })
+        .await??;
+        Ok(again)
+    }
+
+    /// Flush appended records to disk
+    pub async fn flush(&self) -> DbResult<()> {
+        self.with_log(Log::flush).await
+    }
+
+    async fn append(&self, record: Record) -> DbResult<()> {
+        let compact = self
+            .with_log(move |log| {
+                log.append(record)?;
+                Ok(log.sealed_segments() >= log.config.compact_after && !log.compacting)
+            })
+            .await?;
+        if compact {
+            let log = self.log.clone();
+            tokio::spawn(async move {
+                loop {
+                    match Self::run_compaction(log.clone()).await {
+                        Ok(true) => continue,
+                        Ok(false) => break,
+                        Err(err) => {
+                            warn!("Failed to compact event log: {}", err);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomRepository for FileEventStore {
+    async fn append_room_event(&self, id: &RoomId, evt: RoomEvent) -> DbResult<()> {
+        self.append(Record::Event {
+            room: *id,
+            at: now(),
+            event: evt.into(),
+        })
+        .await
+    }
+
+    async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+        let id = *id;
+        self.with_log(move |log| log.room_events(&id)).await
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomAliasRepository for FileEventStore {
+    async fn create_room_alias(&self, alias: &str, room_id: &RoomId) -> DbResult<bool> {
+        let alias = alias.to_string();
+        let room = *room_id;
+        self.with_log(move |log| {
+            if log.aliases.contains_key(&alias) {
+                return Ok(false);
+            }
+            log.append(Record::AliasCreated { alias, room })?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn resolve_room_alias(&self, alias: &str) -> DbResult<Option<RoomId>> {
+        let alias = alias.to_string();
+        self.with_log(move |log| Ok(log.aliases.get(&alias).copied()))
+            .await
+    }
+
+    async fn delete_room_aliases(&self, room_id: &RoomId) -> DbResult<()> {
+        self.append(Record::AliasesDeleted { room: *room_id }).await
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookSecretRepository for FileEventStore {
+    async fn set_webhook_secret(
+        &self,
+        room_id: &RoomId,
+        url: &str,
+        secret: Option<&str>,
+    ) -> DbResult<()> {
+        let room = *room_id;
+        let url = url.to_string();
+        let secret = secret.map(str::to_string);
+        self.with_log(move |log| {
+            let changed = match secret {
+                Some(secret) => {
+                    let secrets = log.secrets.entry(room).or_default();
+                    secrets.insert(url, secret.clone()).as_ref() != Some(&secret)
+                }
+                None => log
+                    .secrets
+                    .get_mut(&room)
+                    .is_some_and(|secrets| secrets.remove(&url).is_some()),
+            };
+            if changed {
+                log.write_secrets()?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_webhook_secrets(&self, room_id: &RoomId) -> DbResult<HashMap<String, String>> {
+        let room = *room_id;
+        self.with_log(move |log| Ok(log.secrets.get(&room).cloned().unwrap_or_default()))
+            .await
+    }
+
+    async fn delete_webhook_secrets(&self, room_id: &RoomId) -> DbResult<()> {
+        let room = *room_id;
+        self.with_log(move |log| {
+            if log.secrets.remove(&room).is_some() {
+                log.write_secrets()?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomRetentionRepository for FileEventStore {
+    async fn purge_inactive_rooms(
+        &self,
+        inactive_since: OffsetDateTime,
+        keep: &[RoomId],
+        dry_run: bool,
+    ) -> DbResult<PurgedRooms> {
+        let keep = keep.to_vec();
+        self.with_log(move |log| log.purge_inactive_rooms(inactive_since, &keep, dry_run))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::contract;
+
+    fn config() -> FileStoreConfig {
+        FileStoreConfig {
+            fsync: FsyncPolicy::Never,
+            ..FileStoreConfig::default()
+        }
+    }
+
+    fn joined(player: &str) -> RoomEvent {
+        RoomEvent::PlayerJoined {
+            player_id: player.to_string(),
+        }
+    }
+
+    fn left(player: &str) -> RoomEvent {
+        RoomEvent::PlayerLeaved {
+            player_id: player.to_string(),
+        }
+    }
+
+    fn created() -> RoomEvent {
+        RoomEvent::Created {
+            deck: "fibonacci".to_string(),
+        }
+    }
+
+    fn segment_files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn parse_fsync_policy() {
+        // ACT
+        let always = "always".parse::<FsyncPolicy>();
+        let never = "never".parse::<FsyncPolicy>();
+        let interval = "5".parse::<FsyncPolicy>();
+        let zero = "0".parse::<FsyncPolicy>();
+        let invalid = "sometimes".parse::<FsyncPolicy>();
+
+        // ASSERT
+        assert_eq!(always, Ok(FsyncPolicy::Always));
+        assert_eq!(never, Ok(FsyncPolicy::Never));
+        assert_eq!(interval, Ok(FsyncPolicy::Interval(Duration::from_secs(5))));
+        assert!(zero.is_err());
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn room_repository_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        contract::check_room_repository(store.as_ref()).await;
+    }
+
+    #[tokio::test]
+    async fn room_alias_repository_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        contract::check_room_alias_repository(store.as_ref()).await;
+    }
+
+    #[tokio::test]
+    async fn webhook_secret_repository_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        contract::check_webhook_secret_repository(store.as_ref()).await;
+    }
+
+    #[tokio::test]
+    async fn room_retention_repository_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        let store = store.as_ref();
+        contract::check_room_retention_repository(store, store, store, store).await;
+    }
+
+    #[tokio::test]
+    async fn keep_webhook_secrets_out_of_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        store.append_room_event(&room, created()).await.unwrap();
+
+        // ACT
+        store
+            .set_webhook_secret(&room, "https://example.com/hook", Some("s3cr3t"))
+            .await
+            .unwrap();
+        drop(store);
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+
+        // ASSERT
+        let secrets = store.get_webhook_secrets(&room).await.unwrap();
+        assert_eq!(secrets["https://example.com/hook"], "s3cr3t");
+        let segment = fs::read(segment_path(dir.path(), SEGMENT_PREFIX, 1)).unwrap();
+        assert!(!String::from_utf8(segment).unwrap().contains("s3cr3t"));
+    }
+
+    #[tokio::test]
+    async fn keep_purged_rooms_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        store.append_room_event(&room, created()).await.unwrap();
+        store.create_room_alias("planning", &room).await.unwrap();
+        let future = OffsetDateTime::now_utc() + time::Duration::minutes(1);
+
+        // ACT
+        store
+            .purge_inactive_rooms(future, &[], false)
+            .await
+            .unwrap();
+        drop(store);
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        store.compact().await.unwrap();
+
+        // ASSERT
+        assert_eq!(store.get_room_events(&room).await.unwrap(), []);
+        assert_eq!(store.resolve_room_alias("planning").await.unwrap(), None);
+        let segment = segment_path(dir.path(), SEGMENT_PREFIX, 1);
+        assert_eq!(fs::metadata(segment).unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn restore_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        store.append_room_event(&room, created()).await.unwrap();
+        store.create_room_alias("planning", &room).await.unwrap();
+        drop(store);
+
+        // ACT
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+
+        // ASSERT
+        assert_eq!(store.get_room_events(&room).await.unwrap(), [created()]);
+        assert_eq!(
+            store.resolve_room_alias("planning").await.unwrap(),
+            Some(room)
+        );
+    }
+
+    #[tokio::test]
+    async fn cut_off_truncated_last_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        store.append_room_event(&room, created()).await.unwrap();
+        drop(store);
+        let path = segment_path(dir.path(), SEGMENT_PREFIX, 1);
+        let len = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"type":"event","room":"#).unwrap();
+
+        // ACT
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        store.append_room_event(&room, joined("p1")).await.unwrap();
+
+        // ASSERT
+        assert_eq!(
+            store.get_room_events(&room).await.unwrap(),
+            [created(), joined("p1")]
+        );
+        assert!(fs::metadata(&path).unwrap().len() > len);
+    }
+
+    #[tokio::test]
+    async fn start_new_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let config = FileStoreConfig {
+            segment_size: 100,
+            compact_after: 100,
+            ..config()
+        };
+        let store = FileEventStore::open(dir.path(), config.clone()).unwrap();
+
+        // ACT
+        store.append_room_event(&room, created()).await.unwrap();
+        for i in 0..5 {
+            store
+                .append_room_event(&room, joined(&i.to_string()))
+                .await
+                .unwrap();
+        }
+
+        // ASSERT
+        assert!(segment_files(dir.path()) > 1);
+        let store = FileEventStore::open(dir.path(), config).unwrap();
+        assert_eq!(store.get_room_events(&room).await.unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn compact_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let other = RoomId::generate();
+        let config = FileStoreConfig {
+            segment_size: 100,
+            compact_after: 100,
+            ..config()
+        };
+        let store = FileEventStore::open(dir.path(), config.clone()).unwrap();
+        for (id, event) in [
+            (room, created()),
+            (room, joined("p1")),
+            (other, created()),
+            (room, joined("p2")),
+            (room, left("p1")),
+        ] {
+            store.append_room_event(&id, event).await.unwrap();
+        }
+        store.create_room_alias("planning", &room).await.unwrap();
+        store.create_room_alias("gone", &other).await.unwrap();
+        store.delete_room_aliases(&other).await.unwrap();
+
+        // ACT
+        store.compact().await.unwrap();
+        store.append_room_event(&room, left("p2")).await.unwrap();
+
+        // ASSERT
+        let expected = [created(), joined("p2"), left("p2")];
+        assert_eq!(store.get_room_events(&room).await.unwrap(), expected);
+        assert_eq!(segment_files(dir.path()), 2);
+
+        let store = FileEventStore::open(dir.path(), config).unwrap();
+        assert_eq!(store.get_room_events(&room).await.unwrap(), expected);
+        assert_eq!(store.get_room_events(&other).await.unwrap(), [created()]);
+        assert_eq!(
+            store.resolve_room_alias("planning").await.unwrap(),
+            Some(room)
+        );
+        assert_eq!(store.resolve_room_alias("gone").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn compact_in_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let config = FileStoreConfig {
+            segment_size: 100,
+            compact_after: 2,
+            ..config()
+        };
+        let store = FileEventStore::open(dir.path(), config.clone()).unwrap();
+
+        // ACT
+        store.append_room_event(&room, created()).await.unwrap();
+        for i in 0..20 {
+            store
+                .append_room_event(&room, joined(&i.to_string()))
+                .await
+                .unwrap();
+        }
+        // every event fills a segment without compactions
+        let compacted = tokio::time::timeout(Duration::from_secs(5), async {
+            while store.log.lock().unwrap().compacting || segment_files(dir.path()) > 5 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await;
+
+        // ASSERT
+        assert!(compacted.is_ok());
+        assert_eq!(store.get_room_events(&room).await.unwrap().len(), 21);
+        drop(store);
+        let store = FileEventStore::open(dir.path(), config).unwrap();
+        assert_eq!(store.get_room_events(&room).await.unwrap().len(), 21);
+    }
+
+    #[tokio::test]
+    async fn finish_interrupted_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let room = RoomId::generate();
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+        store.append_room_event(&room, created()).await.unwrap();
+        drop(store);
+        // crash after the compaction result was complete
+        fs::copy(
+            segment_path(dir.path(), SEGMENT_PREFIX, 1),
+            segment_path(dir.path(), COMPACT_PREFIX, 2),
+        )
+        .unwrap();
+        fs::write(dir.path().join("compact-00000003.jsonl.tmp"), b"{").unwrap();
+
+        // ACT
+        let store = FileEventStore::open(dir.path(), config()).unwrap();
+
+        // ASSERT
+        assert_eq!(store.get_room_events(&room).await.unwrap(), [created()]);
+        assert_eq!(segment_files(dir.path()), 1);
+        assert!(segment_path(dir.path(), SEGMENT_PREFIX, 2).exists());
+    }
+}
@@ -0,0 +1,369 @@
+//! Storage in the memory of the process, for running without a database
+//!
+//! Rooms are lost on restart. Leases and the message bus only coordinate
+//! within one process, so cluster mode needs a database.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::ports::{
+    DatabaseMigrator, DatabaseMigratorRef, DbResult, LeaseOwner, MessageBus, MessageBusRef,
+    PurgedRooms, RoomAliasRepository, RoomAliasRepositoryRef, RoomLeaseRepository,
+    RoomLeaseRepositoryRef, RoomRepository, RoomRepositoryRef, RoomRetentionRepository,
+    RoomRetentionRepositoryRef, WebhookSecretRepository, WebhookSecretRepositoryRef,
+};
+use crate::room::RoomEvent;
+use crate::room_id::RoomId;
+
+#[derive(Default)]
+pub struct MemoryModule;
+
+#[chassis::module]
+impl MemoryModule {
+    #[chassis(singleton)]
+    pub fn provide_memory_repo() -> Arc<MemoryRepository> {
+        Arc::new(MemoryRepository::default())
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_db_migrator() -> DatabaseMigratorRef {
+        Arc::new(MemoryMigrator)
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_repo(repo: Arc<MemoryRepository>) -> RoomRepositoryRef {
+        repo
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_alias_repo(repo: Arc<MemoryRepository>) -> RoomAliasRepositoryRef {
+        repo
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_lease_repo(repo: Arc<MemoryRepository>) -> RoomLeaseRepositoryRef {
+        repo
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_webhook_secret_repo(repo: Arc<MemoryRepository>) -> WebhookSecretRepositoryRef {
+        repo
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_retention_repo(repo: Arc<MemoryRepository>) -> RoomRetentionRepositoryRef {
+        repo
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_message_bus() -> MessageBusRef {
+        Arc::new(MemoryMessageBus::default())
+    }
+}
+
+/// Nothing to migrate
+pub struct MemoryMigrator;
+
+#[async_trait::async_trait]
+impl DatabaseMigrator for MemoryMigrator {
+    async fn check_connection(&self) -> DbResult<()> {
+        Ok(())
+    }
+
+    async fn migrate(&self) -> DbResult<()> {
+        Ok(())
+    }
+}
+
+/// Events, aliases, webhook secrets and leases of rooms
+#[derive(Default)]
+pub struct MemoryRepository {
+    events: Mutex<HashMap<RoomId, StoredRoom>>,
+    aliases: Mutex<HashMap<String, RoomId>>,
+    webhook_secrets: Mutex<HashMap<RoomId, HashMap<String, String>>>,
+    leases: Mutex<HashMap<RoomId, (Uuid, Instant)>>,
+}
+
+struct StoredRoom {
+    events: Vec<RoomEvent>,
+    last_event_at: OffsetDateTime,
+}
+
+#[async_trait::async_trait]
+impl RoomRepository for MemoryRepository {
+    async fn append_room_event(&self, id: &RoomId, evt: RoomEvent) -> DbResult<()> {
+        let now = OffsetDateTime::now_utc();
+        let mut events = self.events.lock().unwrap();
+        let room = events.entry(*id).or_insert_with(|| StoredRoom {
+            events: vec![],
+            last_event_at: now,
+        });
+        room.events.push(evt);
+        room.last_event_at = now;
+        Ok(())
+    }
+
+    async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+        Ok(self
+            .events
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|room| room.events.clone())
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomRetentionRepository for MemoryRepository {
+    async fn purge_inactive_rooms(
+        &self,
+        inactive_since: OffsetDateTime,
+        keep: &[RoomId],
+        dry_run: bool,
+    ) -> DbResult<PurgedRooms> {
+        let mut events = self.events.lock().unwrap();
+        let inactive: Vec<RoomId> = events
+            .iter()
+            .filter(|(id, room)| room.last_event_at < inactive_since && !keep.contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut purged = PurgedRooms::default();
+        for id in inactive {
+            purged.rooms += 1;
+            purged.events += events[&id].events.len() as u64;
+            if !dry_run {
+                events.remove(&id);
+                self.aliases.lock().unwrap().retain(|_, room| *room != id);
+                self.webhook_secrets.lock().unwrap().remove(&id);
+            }
+        }
+        Ok(purged)
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomAliasRepository for MemoryRepository {
+    async fn create_room_alias(&self, alias: &str, room_id: &RoomId) -> DbResult<bool> {
+        let mut aliases = self.aliases.lock().unwrap();
+        if aliases.contains_key(alias) {
+            return Ok(false);
+        }
+        aliases.insert(alias.to_string(), *room_id);
+        Ok(true)
+    }
+
+    async fn resolve_room_alias(&self, alias: &str) -> DbResult<Option<RoomId>> {
+        Ok(self.aliases.lock().unwrap().get(alias).copied())
+    }
+
+    async fn delete_room_aliases(&self, room_id: &RoomId) -> DbResult<()> {
+        self.aliases.lock().unwrap().retain(|_, id| id != room_id);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookSecretRepository for MemoryRepository {
+    async fn set_webhook_secret(
+        &self,
+        room_id: &RoomId,
+        url: &str,
+        secret: Option<&str>,
+    ) -> DbResult<()> {
+        let mut secrets = self.webhook_secrets.lock().unwrap();
+        match secret {
+            Some(secret) => {
+                let room = secrets.entry(*room_id).or_default();
+                room.insert(url.to_string(), secret.to_string());
+            }
+            None => {
+                if let Some(room) = secrets.get_mut(room_id) {
+                    room.remove(url);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_webhook_secrets(&self, room_id: &RoomId) -> DbResult<HashMap<String, String>> {
+        let secrets = self.webhook_secrets.lock().unwrap();
+        Ok(secrets.get(room_id).cloned().unwrap_or_default())
+    }
+
+    async fn delete_webhook_secrets(&self, room_id: &RoomId) -> DbResult<()> {
+        self.webhook_secrets.lock().unwrap().remove(room_id);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomLeaseRepository for MemoryRepository {
+    async fn acquire_room_lease(
+        &self,
+        room_id: &RoomId,
+        instance: Uuid,
+        ttl: Duration,
+    ) -> DbResult<LeaseOwner> {
+        let now = Instant::now();
+        let mut leases = self.leases.lock().unwrap();
+        match leases.get(room_id) {
+            Some((owner, expires_at)) if *owner != instance && *expires_at >= now => {
+                Ok(LeaseOwner::Other(*owner))
+            }
+            _ => {
+                leases.insert(*room_id, (instance, now + ttl));
+                Ok(LeaseOwner::Own)
+            }
+        }
+    }
+
+    async fn renew_room_leases(
+        &self,
+        room_ids: &[RoomId],
+        instance: Uuid,
+        ttl: Duration,
+    ) -> DbResult<Vec<RoomId>> {
+        let now = Instant::now();
+        let mut leases = self.leases.lock().unwrap();
+        let mut renewed = vec![];
+        for room_id in room_ids {
+            if let Some(lease) = leases
+                .get_mut(room_id)
+                .filter(|(owner, _)| *owner == instance)
+            {
+                lease.1 = now + ttl;
+                renewed.push(*room_id);
+            }
+        }
+        Ok(renewed)
+    }
+
+    async fn release_room_lease(&self, room_id: &RoomId, instance: Uuid) -> DbResult<()> {
+        let mut leases = self.leases.lock().unwrap();
+        if leases
+            .get(room_id)
+            .is_some_and(|(owner, _)| *owner == instance)
+        {
+            leases.remove(room_id);
+        }
+        Ok(())
+    }
+
+    async fn room_lease_owners(&self, room_ids: &[RoomId]) -> DbResult<HashMap<RoomId, Uuid>> {
+        let now = Instant::now();
+        let leases = self.leases.lock().unwrap();
+        Ok(room_ids
+            .iter()
+            .filter_map(|room_id| match leases.get(room_id) {
+                Some((owner, expires_at)) if *expires_at >= now => Some((*room_id, *owner)),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+/// Message bus within the process
+#[derive(Default)]
+pub struct MemoryMessageBus {
+    subscribers: Mutex<HashMap<String, Vec<mpsc::Sender<String>>>>,
+}
+
+#[async_trait::async_trait]
+impl MessageBus for MemoryMessageBus {
+    async fn publish(&self, channel: &str, payload: &str) -> DbResult<()> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(senders) = subscribers.get_mut(channel) {
+            // like notifications, messages are lost for slow subscribers
+            senders.retain(|tx| {
+                !matches!(
+                    tx.try_send(payload.to_string()),
+                    Err(mpsc::error::TrySendError::Closed(_))
+                )
+            });
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> DbResult<mpsc::Receiver<String>> {
+        let (tx, rx) = mpsc::channel(64);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .entry(channel.to_string())
+            .or_default()
+            .push(tx);
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::contract;
+
+    #[tokio::test]
+    async fn room_repository_contract() {
+        contract::check_room_repository(&MemoryRepository::default()).await;
+    }
+
+    #[tokio::test]
+    async fn room_alias_repository_contract() {
+        contract::check_room_alias_repository(&MemoryRepository::default()).await;
+    }
+
+    #[tokio::test]
+    async fn webhook_secret_repository_contract() {
+        contract::check_webhook_secret_repository(&MemoryRepository::default()).await;
+    }
+
+    #[tokio::test]
+    async fn room_retention_repository_contract() {
+        let repo = MemoryRepository::default();
+        contract::check_room_retention_repository(&repo, &repo, &repo, &repo).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn take_over_expired_lease() {
+        let repo = MemoryRepository::default();
+        let room = RoomId::generate();
+        let (a, b) = (Uuid::now_v7(), Uuid::now_v7());
+        let ttl = Duration::from_secs(30);
+
+        // ACT
+        let first = repo.acquire_room_lease(&room, a, ttl).await.unwrap();
+        let second = repo.acquire_room_lease(&room, b, ttl).await.unwrap();
+        tokio::time::advance(ttl * 2).await;
+        let third = repo.acquire_room_lease(&room, b, ttl).await.unwrap();
+        let renewed = repo.renew_room_leases(&[room], a, ttl).await.unwrap();
+
+        // ASSERT
+        assert_eq!(first, LeaseOwner::Own);
+        assert_eq!(second, LeaseOwner::Other(a));
+        assert_eq!(third, LeaseOwner::Own);
+        assert_eq!(renewed, []);
+        let owners = repo.room_lease_owners(&[room]).await.unwrap();
+        assert_eq!(owners.get(&room), Some(&b));
+    }
+
+    #[tokio::test]
+    async fn deliver_published_messages() {
+        let bus = MemoryMessageBus::default();
+        let mut rx = bus.subscribe("room").await.unwrap();
+
+        // ACT
+        bus.publish("room", "hello").await.unwrap();
+        bus.publish("other", "ignored").await.unwrap();
+
+        // ASSERT
+        assert_eq!(rx.recv().await.as_deref(), Some("hello"));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1 +1,11 @@
+#[cfg(test)]
+mod contract;
+mod event;
+pub mod file;
+pub mod memory;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "postgres")]
 pub mod sqlx;
+
+pub use event::DbRoomEvent;
@@ -0,0 +1,362 @@
+//! Storage in a single SQLite database file
+//!
+//! Leases and the message bus are kept in memory, because only one instance
+//! can use a database file.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures_util::TryStreamExt;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::types::Json;
+use sqlx::{Connection, QueryBuilder, Row, Sqlite, SqlitePool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::adapters::memory::{MemoryMessageBus, MemoryRepository};
+use crate::adapters::DbRoomEvent;
+use crate::ports::{
+    DatabaseMigrator, DatabaseMigratorRef, DatabasePoolConfig, DatabaseUrl, DbResult,
+    MessageBusRef, PurgedRooms, RoomAliasRepository, RoomAliasRepositoryRef,
+    RoomLeaseRepositoryRef, RoomRepository, RoomRepositoryRef, RoomRetentionRepository,
+    RoomRetentionRepositoryRef, WebhookSecretRepository, WebhookSecretRepositoryRef,
+};
+use crate::room::RoomEvent;
+use crate::room_id::RoomId;
+
+#[derive(Default)]
+pub struct SqliteModule;
+
+#[chassis::module]
+impl SqliteModule {
+    #[chassis(singleton)]
+    pub fn provide_pool(database_url: DatabaseUrl, config: DatabasePoolConfig) -> SqlitePool {
+        let options = SqliteConnectOptions::from_str(&database_url.0)
+            .expect("Database configuration should be valid")
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect_lazy_with(options)
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_db_migrator(pool: SqlitePool) -> DatabaseMigratorRef {
+        Arc::new(SqliteMigrator::new(pool))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_repo(pool: SqlitePool) -> RoomRepositoryRef {
+        Arc::new(SqliteRoomRepository::new(pool))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_alias_repo(pool: SqlitePool) -> RoomAliasRepositoryRef {
+        Arc::new(SqliteRoomAliasRepository::new(pool))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_webhook_secret_repo(pool: SqlitePool) -> WebhookSecretRepositoryRef {
+        Arc::new(SqliteWebhookSecretRepository::new(pool))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_retention_repo(pool: SqlitePool) -> RoomRetentionRepositoryRef {
+        Arc::new(SqliteRoomRetentionRepository::new(pool))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_lease_repo() -> RoomLeaseRepositoryRef {
+        Arc::new(MemoryRepository::default())
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_message_bus() -> MessageBusRef {
+        Arc::new(MemoryMessageBus::default())
+    }
+}
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+pub struct SqliteMigrator {
+    pool: SqlitePool,
+}
+
+impl SqliteMigrator {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl DatabaseMigrator for SqliteMigrator {
+    async fn check_connection(&self) -> DbResult<()> {
+        let conn = SqliteConnection::connect_with(&self.pool.connect_options()).await?;
+        conn.close().await?;
+        Ok(())
+    }
+
+    async fn migrate(&self) -> DbResult<()> {
+        Ok(MIGRATOR.run(&self.pool).await?)
+    }
+}
+
+/// Rows of a multi-row insert, older SQLite versions allow 999 parameters
+const INSERT_CHUNK_SIZE: usize = 300;
+
+pub struct SqliteRoomRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRoomRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomRepository for SqliteRoomRepository {
+    async fn append_room_event(&self, room_id: &RoomId, event: RoomEvent) -> DbResult<()> {
+        sqlx::query("INSERT INTO room_events (occurred_at, room_id, event_data) VALUES (?, ?, ?)")
+            .bind(OffsetDateTime::now_utc())
+            .bind(room_id.as_uuid())
+            .bind(Json(DbRoomEvent::from(event)))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+        let mut rows =
+            sqlx::query("SELECT event_data FROM room_events WHERE room_id = ? ORDER BY event_id")
+                .bind(id.as_uuid())
+                .fetch(&self.pool);
+
+        let mut res = vec![];
+        while let Some(row) = rows.try_next().await? {
+            let json: Json<DbRoomEvent> = row.get(0);
+            res.push(RoomEvent::from(json.0));
+        }
+        Ok(res)
+    }
+
+    async fn append_room_events(&self, events: Vec<(RoomId, RoomEvent)>) -> DbResult<()> {
+        let occurred_at = OffsetDateTime::now_utc();
+        let mut tx = self.pool.begin().await?;
+        for chunk in events.chunks(INSERT_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO room_events (occurred_at, room_id, event_data) ",
+            );
+            query.push_values(chunk, |mut row, (room_id, event)| {
+                row.push_bind(occurred_at)
+                    .push_bind(room_id.as_uuid())
+                    .push_bind(Json(DbRoomEvent::from(event.clone())));
+            });
+            query.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+pub struct SqliteRoomAliasRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRoomAliasRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomAliasRepository for SqliteRoomAliasRepository {
+    async fn create_room_alias(&self, alias: &str, room_id: &RoomId) -> DbResult<bool> {
+        let result = sqlx::query(
+            "INSERT INTO room_aliases (alias, room_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        )
+        .bind(alias)
+        .bind(room_id.as_uuid())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn resolve_room_alias(&self, alias: &str) -> DbResult<Option<RoomId>> {
+        let row = sqlx::query("SELECT room_id FROM room_aliases WHERE alias = ?")
+            .bind(alias)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| RoomId::from_uuid(row.get::<Uuid, _>(0))))
+    }
+
+    async fn delete_room_aliases(&self, room_id: &RoomId) -> DbResult<()> {
+        sqlx::query("DELETE FROM room_aliases WHERE room_id = ?")
+            .bind(room_id.as_uuid())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct SqliteWebhookSecretRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteWebhookSecretRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookSecretRepository for SqliteWebhookSecretRepository {
+    async fn set_webhook_secret(
+        &self,
+        room_id: &RoomId,
+        url: &str,
+        secret: Option<&str>,
+    ) -> DbResult<()> {
+        let query = match secret {
+            Some(secret) => sqlx::query(
+                "INSERT INTO webhook_secrets (room_id, url, secret) VALUES (?, ?, ?) \
+                 ON CONFLICT (room_id, url) DO UPDATE SET secret = excluded.secret",
+            )
+            .bind(room_id.as_uuid())
+            .bind(url)
+            .bind(secret),
+            None => sqlx::query("DELETE FROM webhook_secrets WHERE room_id = ? AND url = ?")
+                .bind(room_id.as_uuid())
+                .bind(url),
+        };
+        query.execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_webhook_secrets(&self, room_id: &RoomId) -> DbResult<HashMap<String, String>> {
+        let rows = sqlx::query("SELECT url, secret FROM webhook_secrets WHERE room_id = ?")
+            .bind(room_id.as_uuid())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    async fn delete_webhook_secrets(&self, room_id: &RoomId) -> DbResult<()> {
+        sqlx::query("DELETE FROM webhook_secrets WHERE room_id = ?")
+            .bind(room_id.as_uuid())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct SqliteRoomRetentionRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRoomRetentionRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomRetentionRepository for SqliteRoomRetentionRepository {
+    async fn purge_inactive_rooms(
+        &self,
+        inactive_since: OffsetDateTime,
+        keep: &[RoomId],
+        dry_run: bool,
+    ) -> DbResult<PurgedRooms> {
+        let mut tx = self.pool.begin().await?;
+        // timestamps are stored as RFC 3339 text, which does not sort by time
+        let rows = sqlx::query(
+            "SELECT room_id, count(*) FROM room_events GROUP BY room_id \
+             HAVING max(julianday(occurred_at)) < julianday(?)",
+        )
+        .bind(inactive_since)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut purged = PurgedRooms::default();
+        for row in rows {
+            let room_id: Uuid = row.get(0);
+            if keep.contains(&RoomId::from_uuid(room_id)) {
+                continue;
+            }
+            purged.rooms += 1;
+            purged.events += row.get::<i64, _>(1) as u64;
+            if !dry_run {
+                sqlx::query("DELETE FROM room_events WHERE room_id = ?")
+                    .bind(room_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM room_aliases WHERE room_id = ?")
+                    .bind(room_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM webhook_secrets WHERE room_id = ?")
+                    .bind(room_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::contract;
+
+    async fn migrated_pool() -> SqlitePool {
+        // every connection would get its own in-memory database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        SqliteMigrator::new(pool.clone()).migrate().await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn room_repository_contract() {
+        let repo = SqliteRoomRepository::new(migrated_pool().await);
+        contract::check_room_repository(&repo).await;
+    }
+
+    #[tokio::test]
+    async fn room_alias_repository_contract() {
+        let repo = SqliteRoomAliasRepository::new(migrated_pool().await);
+        contract::check_room_alias_repository(&repo).await;
+    }
+
+    #[tokio::test]
+    async fn webhook_secret_repository_contract() {
+        let repo = SqliteWebhookSecretRepository::new(migrated_pool().await);
+        contract::check_webhook_secret_repository(&repo).await;
+    }
+
+    #[tokio::test]
+    async fn room_retention_repository_contract() {
+        let pool = migrated_pool().await;
+        contract::check_room_retention_repository(
+            &SqliteRoomRepository::new(pool.clone()),
+            &SqliteRoomAliasRepository::new(pool.clone()),
+            &SqliteWebhookSecretRepository::new(pool.clone()),
+            &SqliteRoomRetentionRepository::new(pool),
+        )
+        .await;
+    }
+}
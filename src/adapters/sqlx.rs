@@ -1,20 +1,29 @@
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-use base64::Engine;
 use futures_util::TryStreamExt;
-use serde::{Deserialize, Serialize};
+use log::{debug, warn};
 use sqlx::migrate::Migrator;
-use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::{PgConnectOptions, PgConnection, PgListener, PgPoolOptions, PgSslMode};
 use sqlx::types::Json;
-use sqlx::{PgPool, Row};
+use sqlx::{Connection, PgPool, Postgres, QueryBuilder, Row};
 use time::OffsetDateTime;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use crate::adapters::DbRoomEvent;
 use crate::ports::{
-    DatabaseMigrator, DatabaseMigratorRef, DatabaseUrl, DbResult, RoomRepository, RoomRepositoryRef,
+    DatabaseMigrator, DatabaseMigratorRef, DatabasePoolConfig, DatabaseUrl, DbResult, LeaseOwner,
+    MessageBus, MessageBusRef, PurgedRooms, RoomAliasRepository, RoomAliasRepositoryRef,
+    RoomLeaseRepository, RoomLeaseRepositoryRef, RoomRepository, RoomRepositoryRef,
+    RoomRetentionRepository, RoomRetentionRepositoryRef, WebhookSecretRepository,
+    WebhookSecretRepositoryRef,
 };
 use crate::room::RoomEvent;
+use crate::room_id::RoomId;
 
 #[derive(Default)]
 pub struct SqlxModule;
@@ -22,11 +31,22 @@ pub struct SqlxModule;
 #[chassis::module]
 impl SqlxModule {
     #[chassis(singleton)]
-    pub fn provide_pool(database_url: DatabaseUrl) -> PgPool {
+    pub fn provide_pool(database_url: DatabaseUrl, config: DatabasePoolConfig) -> PgPool {
+        let mut options = PgConnectOptions::from_str(&database_url.0)
+            .expect("Database configuration should be valid");
+        if let Some(ssl_mode) = &config.ssl_mode {
+            let ssl_mode = PgSslMode::from_str(ssl_mode).expect("GOE_DB_SSL_MODE is invalid");
+            options = options.ssl_mode(ssl_mode);
+        }
+        if let Some(timeout) = config.statement_timeout {
+            options = options.options([("statement_timeout", timeout.as_millis())]);
+        }
         PgPoolOptions::new()
-            .max_connections(5)
-            .connect_lazy(&database_url.0)
-            .expect("Database configuration should be valid")
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect_lazy_with(options)
     }
 
     #[chassis(singleton)]
@@ -38,32 +58,30 @@ impl SqlxModule {
     pub fn provide_room_repo(pool: PgPool) -> RoomRepositoryRef {
         Arc::new(SqlxRoomRepository::new(pool))
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum DbRoomEvent {
-    Created { deck: String },
-    PlayerJoined { player_id: String },
-    PlayerLeaved { player_id: String },
-}
+    #[chassis(singleton)]
+    pub fn provide_room_alias_repo(pool: PgPool) -> RoomAliasRepositoryRef {
+        Arc::new(SqlxRoomAliasRepository::new(pool))
+    }
 
-impl From<RoomEvent> for DbRoomEvent {
-    fn from(value: RoomEvent) -> Self {
-        match value {
-            RoomEvent::Created { deck } => DbRoomEvent::Created { deck },
-            RoomEvent::PlayerJoined { player_id } => DbRoomEvent::PlayerJoined { player_id },
-            RoomEvent::PlayerLeaved { player_id } => DbRoomEvent::PlayerLeaved { player_id },
-        }
+    #[chassis(singleton)]
+    pub fn provide_webhook_secret_repo(pool: PgPool) -> WebhookSecretRepositoryRef {
+        Arc::new(SqlxWebhookSecretRepository::new(pool))
     }
-}
 
-impl From<DbRoomEvent> for RoomEvent {
-    fn from(value: DbRoomEvent) -> Self {
-        match value {
-            DbRoomEvent::Created { deck } => RoomEvent::Created { deck },
-            DbRoomEvent::PlayerJoined { player_id } => RoomEvent::PlayerJoined { player_id },
-            DbRoomEvent::PlayerLeaved { player_id } => RoomEvent::PlayerLeaved { player_id },
-        }
+    #[chassis(singleton)]
+    pub fn provide_room_retention_repo(pool: PgPool) -> RoomRetentionRepositoryRef {
+        Arc::new(SqlxRoomRetentionRepository::new(pool))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_room_lease_repo(pool: PgPool) -> RoomLeaseRepositoryRef {
+        Arc::new(SqlxRoomLeaseRepository::new(pool))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_message_bus(pool: PgPool) -> MessageBusRef {
+        Arc::new(SqlxMessageBus::new(pool))
     }
 }
 
@@ -81,11 +99,29 @@ impl SqlxMigrator {
 
 #[async_trait::async_trait]
 impl DatabaseMigrator for SqlxMigrator {
+    async fn check_connection(&self) -> DbResult<()> {
+        // not through the pool, which would hide the error behind a timeout
+        let timeout = self.pool.options().get_acquire_timeout();
+        let options = self.pool.connect_options();
+        let connect = PgConnection::connect_with(&options);
+        let conn = tokio::time::timeout(timeout, connect).await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no connection within {}s", timeout.as_secs()),
+            )
+        })??;
+        conn.close().await?;
+        Ok(())
+    }
+
     async fn migrate(&self) -> DbResult<()> {
         Ok(MIGRATOR.run(&self.pool).await?)
     }
 }
 
+/// Rows of a multi-row insert, PostgreSQL allows 65535 parameters
+const INSERT_CHUNK_SIZE: usize = 1000;
+
 pub struct SqlxRoomRepository {
     pool: PgPool,
 }
@@ -96,33 +132,25 @@ impl SqlxRoomRepository {
     }
 }
 
-fn decode_room_id(id: &str) -> Uuid {
-    let mut buf: [u8; 18] = [0; 18];
-    match URL_SAFE_NO_PAD.decode_slice(id, &mut buf) {
-        Ok(16) => Uuid::from_slice(&buf[..16]).unwrap(),
-        Ok(size) => panic!("Room ID '{id}' is not a Base64 decoded UUID: wrong size {size}"),
-        Err(err) => panic!("Room ID '{id}' is not a Base64 decoded UUID: {err}"),
-    }
-}
-
 #[async_trait::async_trait]
 impl RoomRepository for SqlxRoomRepository {
-    async fn append_room_event(&self, room_id: &str, event: RoomEvent) -> DbResult<()> {
+    async fn append_room_event(&self, room_id: &RoomId, event: RoomEvent) -> DbResult<()> {
         sqlx::query(
             "INSERT INTO room_events (occurred_at, room_id, event_data) VALUES ($1, $2, $3)",
         )
         .bind(OffsetDateTime::now_utc())
-        .bind(decode_room_id(room_id))
+        .bind(room_id.as_uuid())
         .bind(Json(DbRoomEvent::from(event)))
         .execute(&self.pool)
         .await?;
         Ok(())
     }
 
-    async fn get_room_events(&self, id: &str) -> DbResult<Vec<RoomEvent>> {
-        let mut rows = sqlx::query("SELECT event_data FROM room_events WHERE room_id = $1")
-            .bind(decode_room_id(id))
-            .fetch(&self.pool);
+    async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+        let mut rows =
+            sqlx::query("SELECT event_data FROM room_events WHERE room_id = $1 ORDER BY event_id")
+                .bind(id.as_uuid())
+                .fetch(&self.pool);
 
         let mut res = vec![];
         while let Some(row) = rows.try_next().await? {
@@ -131,4 +159,479 @@ impl RoomRepository for SqlxRoomRepository {
         }
         Ok(res)
     }
+
+    async fn append_room_events(&self, events: Vec<(RoomId, RoomEvent)>) -> DbResult<()> {
+        let occurred_at = OffsetDateTime::now_utc();
+        let mut tx = self.pool.begin().await?;
+        for chunk in events.chunks(INSERT_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO room_events (occurred_at, room_id, event_data) ",
+            );
+            query.push_values(chunk, |mut row, (room_id, event)| {
+                row.push_bind(occurred_at)
+                    .push_bind(room_id.as_uuid())
+                    .push_bind(Json(DbRoomEvent::from(event.clone())));
+            });
+            query.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+pub struct SqlxRoomAliasRepository {
+    pool: PgPool,
+}
+
+impl SqlxRoomAliasRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomAliasRepository for SqlxRoomAliasRepository {
+    async fn create_room_alias(&self, alias: &str, room_id: &RoomId) -> DbResult<bool> {
+        let result = sqlx::query(
+            "INSERT INTO room_aliases (alias, room_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(alias)
+        .bind(room_id.as_uuid())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn resolve_room_alias(&self, alias: &str) -> DbResult<Option<RoomId>> {
+        let row = sqlx::query("SELECT room_id FROM room_aliases WHERE alias = $1")
+            .bind(alias)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| RoomId::from_uuid(row.get(0))))
+    }
+
+    async fn delete_room_aliases(&self, room_id: &RoomId) -> DbResult<()> {
+        sqlx::query("DELETE FROM room_aliases WHERE room_id = $1")
+            .bind(room_id.as_uuid())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct SqlxWebhookSecretRepository {
+    pool: PgPool,
+}
+
+impl SqlxWebhookSecretRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookSecretRepository for SqlxWebhookSecretRepository {
+    async fn set_webhook_secret(
+        &self,
+        room_id: &RoomId,
+        url: &str,
+        secret: Option<&str>,
+    ) -> DbResult<()> {
+        let query = match secret {
+            Some(secret) => sqlx::query(
+                "INSERT INTO webhook_secrets (room_id, url, secret) VALUES ($1, $2, $3) \
+                 ON CONFLICT (room_id, url) DO UPDATE SET secret = EXCLUDED.secret",
+            )
+            .bind(room_id.as_uuid())
+            .bind(url)
+            .bind(secret),
+            None => sqlx::query("DELETE FROM webhook_secrets WHERE room_id = $1 AND url = $2")
+                .bind(room_id.as_uuid())
+                .bind(url),
+        };
+        query.execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_webhook_secrets(&self, room_id: &RoomId) -> DbResult<HashMap<String, String>> {
+        let rows = sqlx::query("SELECT url, secret FROM webhook_secrets WHERE room_id = $1")
+            .bind(room_id.as_uuid())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    async fn delete_webhook_secrets(&self, room_id: &RoomId) -> DbResult<()> {
+        sqlx::query("DELETE FROM webhook_secrets WHERE room_id = $1")
+            .bind(room_id.as_uuid())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct SqlxRoomRetentionRepository {
+    pool: PgPool,
+}
+
+impl SqlxRoomRetentionRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Rooms without events since `$1`, except the rooms in `$2` and rooms
+/// hosted by an instance of the cluster
+const INACTIVE_ROOMS: &str = "\
+    SELECT room_id FROM room_events GROUP BY room_id \
+    HAVING max(occurred_at) < $1 \
+       AND NOT room_id = ANY($2) \
+       AND room_id NOT IN (SELECT room_id FROM room_leases WHERE expires_at >= now())";
+
+#[async_trait::async_trait]
+impl RoomRetentionRepository for SqlxRoomRetentionRepository {
+    async fn purge_inactive_rooms(
+        &self,
+        inactive_since: OffsetDateTime,
+        keep: &[RoomId],
+        dry_run: bool,
+    ) -> DbResult<PurgedRooms> {
+        let keep: Vec<Uuid> = keep.iter().map(RoomId::as_uuid).collect();
+        let query = if dry_run {
+            format!(
+                "SELECT count(DISTINCT room_id), count(*) FROM room_events \
+                 WHERE room_id IN ({INACTIVE_ROOMS})"
+            )
+        } else {
+            // one statement, so events, aliases and secrets are deleted together
+            format!(
+                "WITH inactive AS ({INACTIVE_ROOMS}), \
+                 aliases AS (DELETE FROM room_aliases WHERE room_id IN (SELECT room_id FROM inactive)), \
+                 secrets AS (DELETE FROM webhook_secrets WHERE room_id IN (SELECT room_id FROM inactive)), \
+                 events AS (DELETE FROM room_events WHERE room_id IN (SELECT room_id FROM inactive) \
+                            RETURNING room_id) \
+                 SELECT count(DISTINCT room_id), count(*) FROM events"
+            )
+        };
+        let row = sqlx::query(&query)
+            .bind(inactive_since)
+            .bind(keep)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(PurgedRooms {
+            rooms: row.get::<i64, _>(0) as u64,
+            events: row.get::<i64, _>(1) as u64,
+        })
+    }
+}
+
+pub struct SqlxRoomLeaseRepository {
+    pool: PgPool,
+}
+
+impl SqlxRoomLeaseRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomLeaseRepository for SqlxRoomLeaseRepository {
+    async fn acquire_room_lease(
+        &self,
+        room_id: &RoomId,
+        instance: Uuid,
+        ttl: Duration,
+    ) -> DbResult<LeaseOwner> {
+        // use database clock to be independent of clock skew between instances,
+        // the row is locked, so the returned owner is the one after the statement
+        let owner: Uuid = sqlx::query(
+            "INSERT INTO room_leases (room_id, instance_id, expires_at) \
+             VALUES ($1, $2, now() + make_interval(secs => $3)) \
+             ON CONFLICT (room_id) DO UPDATE SET \
+                instance_id = CASE WHEN room_leases.expires_at < now() \
+                    THEN EXCLUDED.instance_id ELSE room_leases.instance_id END, \
+                expires_at = CASE WHEN room_leases.instance_id = EXCLUDED.instance_id \
+                                   OR room_leases.expires_at < now() \
+                    THEN EXCLUDED.expires_at ELSE room_leases.expires_at END \
+             RETURNING instance_id",
+        )
+        .bind(room_id.as_uuid())
+        .bind(instance)
+        .bind(ttl.as_secs_f64())
+        .fetch_one(&self.pool)
+        .await?
+        .get(0);
+        Ok(if owner == instance {
+            LeaseOwner::Own
+        } else {
+            LeaseOwner::Other(owner)
+        })
+    }
+
+    async fn renew_room_leases(
+        &self,
+        room_ids: &[RoomId],
+        instance: Uuid,
+        ttl: Duration,
+    ) -> DbResult<Vec<RoomId>> {
+        let room_ids: Vec<Uuid> = room_ids.iter().map(RoomId::as_uuid).collect();
+        let renewed = sqlx::query(
+            "UPDATE room_leases SET expires_at = now() + make_interval(secs => $3) \
+             WHERE instance_id = $2 AND room_id = ANY($1) \
+             RETURNING room_id",
+        )
+        .bind(room_ids)
+        .bind(instance)
+        .bind(ttl.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(renewed
+            .into_iter()
+            .map(|row| RoomId::from_uuid(row.get(0)))
+            .collect())
+    }
+
+    async fn release_room_lease(&self, room_id: &RoomId, instance: Uuid) -> DbResult<()> {
+        sqlx::query("DELETE FROM room_leases WHERE room_id = $1 AND instance_id = $2")
+            .bind(room_id.as_uuid())
+            .bind(instance)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn room_lease_owners(&self, room_ids: &[RoomId]) -> DbResult<HashMap<RoomId, Uuid>> {
+        let room_ids: Vec<Uuid> = room_ids.iter().map(RoomId::as_uuid).collect();
+        let rows = sqlx::query(
+            "SELECT room_id, instance_id FROM room_leases \
+             WHERE room_id = ANY($1) AND expires_at >= now()",
+        )
+        .bind(room_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (RoomId::from_uuid(row.get(0)), row.get(1)))
+            .collect())
+    }
+}
+
+/// Payload bytes of a notification, PostgreSQL rejects more than 8000
+const MAX_NOTIFY_PAYLOAD: usize = 7900;
+
+/// Start of a part of a payload that is too large for one notification,
+/// followed by `<id>:<index>:<count>:`
+const CHUNK_PREFIX: &str = "#chunk:";
+
+/// Parts of payloads that are too large for a single notification
+fn split_payload(payload: &str) -> Vec<String> {
+    if payload.len() <= MAX_NOTIFY_PAYLOAD {
+        return vec![payload.to_string()];
+    }
+
+    // room for the prefix with a UUID and two numbers
+    let max_len = MAX_NOTIFY_PAYLOAD - CHUNK_PREFIX.len() - 64;
+    let mut parts = vec![];
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(max_len);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (part, tail) = rest.split_at(end);
+        parts.push(part);
+        rest = tail;
+    }
+
+    let id = Uuid::now_v7().simple();
+    let count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, part)| format!("{CHUNK_PREFIX}{id}:{index}:{count}:{part}"))
+        .collect()
+}
+
+/// Reassembles the parts of [`split_payload`]
+#[derive(Default)]
+struct Chunks {
+    pending: HashMap<String, Vec<Option<String>>>,
+}
+
+impl Chunks {
+    /// Parts of incomplete payloads that are kept, older ones are lost
+    const MAX_PENDING: usize = 64;
+
+    /// Complete payload for a notification, `None` while parts are missing
+    fn receive(&mut self, payload: String) -> Option<String> {
+        let Some(chunk) = payload.strip_prefix(CHUNK_PREFIX) else {
+            return Some(payload);
+        };
+        let mut fields = chunk.splitn(4, ':');
+        let (Some(id), Some(index), Some(count), Some(part)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            warn!("Ignored invalid notification chunk");
+            return None;
+        };
+        let (Ok(index), Ok(count)) = (index.parse::<usize>(), count.parse::<usize>()) else {
+            warn!("Ignored invalid notification chunk");
+            return None;
+        };
+        if index >= count {
+            warn!("Ignored invalid notification chunk");
+            return None;
+        }
+
+        if !self.pending.contains_key(id) && self.pending.len() >= Self::MAX_PENDING {
+            warn!("Dropped incomplete notifications");
+            self.pending.clear();
+        }
+        let parts = self
+            .pending
+            .entry(id.to_string())
+            .or_insert_with(|| vec![None; count]);
+        if let Some(slot) = parts.get_mut(index) {
+            *slot = Some(part.to_string());
+        }
+        if parts.iter().any(Option::is_none) {
+            return None;
+        }
+        let parts = self.pending.remove(id)?;
+        Some(parts.into_iter().flatten().collect())
+    }
+}
+
+/// Message bus based on PostgreSQL `LISTEN`/`NOTIFY`
+///
+/// Payloads over the size limit of notifications are sent in parts within
+/// one transaction, so the parts arrive one after another.
+pub struct SqlxMessageBus {
+    pool: PgPool,
+}
+
+impl SqlxMessageBus {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageBus for SqlxMessageBus {
+    async fn publish(&self, channel: &str, payload: &str) -> DbResult<()> {
+        let parts = split_payload(payload);
+        if let [payload] = parts.as_slice() {
+            sqlx::query("SELECT pg_notify($1, $2)")
+                .bind(channel)
+                .bind(payload)
+                .execute(&self.pool)
+                .await?;
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for part in parts {
+            sqlx::query("SELECT pg_notify($1, $2)")
+                .bind(channel)
+                .bind(part)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> DbResult<mpsc::Receiver<String>> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(channel).await?;
+
+        let (tx, rx) = mpsc::channel(64);
+        let channel = channel.to_string();
+        tokio::spawn(async move {
+            let mut chunks = Chunks::default();
+            loop {
+                // reconnects transparently, notifications in between are lost
+                match listener.recv().await {
+                    Ok(notification) => {
+                        let Some(payload) = chunks.receive(notification.payload().to_string())
+                        else {
+                            continue;
+                        };
+                        if tx.send(payload).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        warn!("Failed to receive notification on {}: {}", channel, err);
+                        if tx.is_closed() {
+                            break;
+                        }
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+            debug!("Stopped listening on {}", channel);
+        });
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::contract;
+
+    /// Pool of the database in `TEST_DATABASE_URL`, tests are skipped without it
+    async fn migrated_pool() -> Option<PgPool> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        let pool = PgPool::connect(&url).await.unwrap();
+        SqlxMigrator::new(pool.clone()).migrate().await.unwrap();
+        Some(pool)
+    }
+
+    #[tokio::test]
+    async fn room_repository_contract() {
+        if let Some(pool) = migrated_pool().await {
+            contract::check_room_repository(&SqlxRoomRepository::new(pool)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn room_alias_repository_contract() {
+        if let Some(pool) = migrated_pool().await {
+            contract::check_room_alias_repository(&SqlxRoomAliasRepository::new(pool)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn webhook_secret_repository_contract() {
+        if let Some(pool) = migrated_pool().await {
+            contract::check_webhook_secret_repository(&SqlxWebhookSecretRepository::new(pool))
+                .await;
+        }
+    }
+
+    #[test]
+    fn reassemble_large_payloads() {
+        let payload = "ä".repeat(MAX_NOTIFY_PAYLOAD);
+        let mut chunks = Chunks::default();
+
+        // ACT
+        let parts = split_payload(&payload);
+        let received: Vec<String> = parts
+            .iter()
+            .filter_map(|part| chunks.receive(part.clone()))
+            .collect();
+
+        // ASSERT
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.len() <= MAX_NOTIFY_PAYLOAD));
+        assert_eq!(received, [payload]);
+        assert_eq!(split_payload("{}"), ["{}"]);
+        assert_eq!(chunks.receive("{}".to_string()).as_deref(), Some("{}"));
+    }
 }
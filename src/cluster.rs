@@ -0,0 +1,903 @@
+//! Room ownership and message forwarding between multiple instances
+//!
+//! Every room is hosted by exactly one instance, which holds a lease for it
+//! in the database. Other instances join their players through a
+//! [`RoomProxy`], which forwards room messages to the owning instance. The
+//! owner sends the room updates for these players back to the instance they
+//! are connected to.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use uactor::blocking::{Actor, ActorContext};
+use uactor::tokio::blocking::Context;
+
+use crate::game_server::{CreatedRoom, GameServerAddr, GameServerMessage};
+use crate::player::{PlayerAddr, PlayerInformation};
+use crate::ports::{DbResult, LeaseOwner, MessageBusRef, RoomLeaseRepositoryRef};
+use crate::room::{
+    DropReason, GamePlayerMessage, GameState, PlayerState, RejectReason, RoomAddr, RoomInfo,
+    RoomMessage, Round,
+};
+use crate::room_id::RoomId;
+use crate::story::Story;
+
+/// Answers of other instances are awaited for this long
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request for a room hosted by another instance
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RoomRequest {
+    Join {
+        player: PlayerInformation,
+    },
+    Left {
+        player_id: String,
+    },
+    Voted {
+        player_id: String,
+        vote: Option<String>,
+    },
+    UpdatePlayer {
+        id: String,
+        voter: bool,
+        name: Option<String>,
+    },
+    ForceOpen,
+    Restart,
+}
+
+/// Query or change of a room, answered by the instance hosting it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RoomQuery {
+    GetInfo,
+    GetHistory,
+    /// The secret is stored by the instance that got the subscription, it
+    /// is never sent to other instances
+    Subscribe {
+        url: String,
+        #[serde(skip)]
+        secret: Option<String>,
+    },
+    Unsubscribe,
+    SetStories {
+        stories: Vec<Story>,
+    },
+    Close,
+}
+
+/// Answer to a [`RoomQuery`], `None` or `false` when the room is not
+/// hosted there
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum QueryAnswer {
+    Info { info: Option<Box<RoomInfo>> },
+    History { rounds: Option<Vec<Round>> },
+    Done { done: bool },
+}
+
+/// Room update for a player connected to another instance
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum PlayerEvent {
+    AliasResolved {
+        alias: String,
+        room: RoomId,
+    },
+    Welcome {
+        room: RoomId,
+        state: GameState,
+        players: Vec<PlayerState>,
+    },
+    RoomCreated {
+        room: CreatedRoom,
+    },
+    Rejected {
+        reason: RejectReason,
+    },
+    Dropped {
+        reason: DropReason,
+    },
+    PlayerJoined {
+        player: PlayerState,
+    },
+    PlayerChanged {
+        player: PlayerState,
+    },
+    PlayerLeft {
+        player_id: String,
+    },
+    GameStateChanged {
+        state: GameState,
+    },
+    StoriesChanged {
+        stories: Vec<Story>,
+    },
+    Notice {
+        message: String,
+    },
+    RoomClosed,
+    RoomMoved,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum ClusterMessage {
+    ToRoom {
+        room: RoomId,
+        origin: Uuid,
+        request: RoomRequest,
+    },
+    ToPlayer {
+        player: String,
+        event: PlayerEvent,
+    },
+    Query {
+        room: RoomId,
+        origin: Uuid,
+        id: Uuid,
+        query: RoomQuery,
+    },
+    Answer {
+        id: Uuid,
+        answer: QueryAnswer,
+    },
+}
+
+impl From<GamePlayerMessage> for PlayerEvent {
+    fn from(msg: GamePlayerMessage) -> Self {
+        match msg {
+            GamePlayerMessage::AliasResolved { alias, room } => {
+                PlayerEvent::AliasResolved { alias, room }
+            }
+            GamePlayerMessage::Welcome(room, _, state, players) => PlayerEvent::Welcome {
+                room,
+                state,
+                players,
+            },
+            GamePlayerMessage::RoomCreated(room) => PlayerEvent::RoomCreated { room },
+            GamePlayerMessage::Rejected(reason) => PlayerEvent::Rejected { reason },
+            GamePlayerMessage::Dropped(reason) => PlayerEvent::Dropped { reason },
+            GamePlayerMessage::PlayerJoined(player) => PlayerEvent::PlayerJoined { player },
+            GamePlayerMessage::PlayerChanged(player) => PlayerEvent::PlayerChanged { player },
+            GamePlayerMessage::PlayerLeft(player_id) => PlayerEvent::PlayerLeft { player_id },
+            GamePlayerMessage::GameStateChanged(state) => PlayerEvent::GameStateChanged { state },
+            GamePlayerMessage::StoriesChanged(stories) => PlayerEvent::StoriesChanged { stories },
+            GamePlayerMessage::Notice(message) => PlayerEvent::Notice { message },
+            GamePlayerMessage::RoomClosed => PlayerEvent::RoomClosed,
+            GamePlayerMessage::RoomMoved => PlayerEvent::RoomMoved,
+        }
+    }
+}
+
+impl PlayerEvent {
+    fn into_message(self, proxy: RoomAddr) -> GamePlayerMessage {
+        match self {
+            PlayerEvent::AliasResolved { alias, room } => {
+                GamePlayerMessage::AliasResolved { alias, room }
+            }
+            PlayerEvent::Welcome {
+                room,
+                state,
+                players,
+            } => GamePlayerMessage::Welcome(room, proxy, state, players),
+            PlayerEvent::RoomCreated { room } => GamePlayerMessage::RoomCreated(room),
+            PlayerEvent::Rejected { reason } => GamePlayerMessage::Rejected(reason),
+            PlayerEvent::Dropped { reason } => GamePlayerMessage::Dropped(reason),
+            PlayerEvent::PlayerJoined { player } => GamePlayerMessage::PlayerJoined(player),
+            PlayerEvent::PlayerChanged { player } => GamePlayerMessage::PlayerChanged(player),
+            PlayerEvent::PlayerLeft { player_id } => GamePlayerMessage::PlayerLeft(player_id),
+            PlayerEvent::GameStateChanged { state } => GamePlayerMessage::GameStateChanged(state),
+            PlayerEvent::StoriesChanged { stories } => GamePlayerMessage::StoriesChanged(stories),
+            PlayerEvent::Notice { message } => GamePlayerMessage::Notice(message),
+            PlayerEvent::RoomClosed => GamePlayerMessage::RoomClosed,
+            PlayerEvent::RoomMoved => GamePlayerMessage::RoomMoved,
+        }
+    }
+}
+
+/// Local player joined to a room of another instance
+#[derive(Clone)]
+struct RemoteMember {
+    player: PlayerAddr,
+    room: RoomId,
+    owner: Uuid,
+    proxy: RoomAddr,
+}
+
+#[derive(Clone)]
+pub struct Cluster {
+    instance: Uuid,
+    lease_ttl: Duration,
+    leases: RoomLeaseRepositoryRef,
+    bus: MessageBusRef,
+    members: Arc<Mutex<HashMap<String, RemoteMember>>>,
+    queries: Arc<Mutex<HashMap<Uuid, oneshot::Sender<QueryAnswer>>>>,
+}
+
+impl Cluster {
+    pub fn new(leases: RoomLeaseRepositoryRef, bus: MessageBusRef, lease_ttl: Duration) -> Self {
+        Self {
+            instance: Uuid::now_v7(),
+            lease_ttl,
+            leases,
+            bus,
+            members: Default::default(),
+            queries: Default::default(),
+        }
+    }
+
+    pub fn instance(&self) -> Uuid {
+        self.instance
+    }
+
+    pub fn lease_ttl(&self) -> Duration {
+        self.lease_ttl
+    }
+
+    fn channel(instance: Uuid) -> String {
+        format!("goe_{}", instance.simple())
+    }
+
+    pub async fn acquire_lease(&self, room: &RoomId) -> DbResult<LeaseOwner> {
+        self.leases
+            .acquire_room_lease(room, self.instance, self.lease_ttl)
+            .await
+    }
+
+    /// Renew the leases of `rooms`, returns the rooms whose lease was lost
+    ///
+    /// Leases expire when they are not renewed in time, for example while the
+    /// database was not reachable, and can be taken by other instances.
+    pub async fn renew_leases(&self, rooms: &[RoomId]) -> Vec<RoomId> {
+        if rooms.is_empty() {
+            return vec![];
+        }
+        match self
+            .leases
+            .renew_room_leases(rooms, self.instance, self.lease_ttl)
+            .await
+        {
+            Ok(renewed) => rooms
+                .iter()
+                .filter(|room| !renewed.contains(room))
+                .copied()
+                .collect(),
+            Err(err) => {
+                error!("Failed to renew room leases: {}", err);
+                vec![]
+            }
+        }
+    }
+
+    /// Current owners of `rooms`, rooms without owner are missing
+    pub async fn lease_owners(&self, rooms: &[RoomId]) -> Option<HashMap<RoomId, Uuid>> {
+        if rooms.is_empty() {
+            return Some(HashMap::new());
+        }
+        match self.leases.room_lease_owners(rooms).await {
+            Ok(owners) => Some(owners),
+            Err(err) => {
+                error!("Failed to query room leases: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Owner of `room`, if another instance hosts it
+    pub async fn remote_owner(&self, room: RoomId) -> Option<Uuid> {
+        let owner = *self.lease_owners(&[room]).await?.get(&room)?;
+        (owner != self.instance).then_some(owner)
+    }
+
+    pub async fn release_lease(&self, room: &RoomId) {
+        if let Err(err) = self.leases.release_room_lease(room, self.instance).await {
+            warn!("{}: Failed to release room lease: {}", room, err);
+        }
+    }
+
+    async fn publish(&self, instance: Uuid, msg: &ClusterMessage) {
+        let payload = match serde_json::to_string(msg) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("Failed to serialize cluster message {:?}: {}", msg, err);
+                return;
+            }
+        };
+        if let Err(err) = self.bus.publish(&Self::channel(instance), &payload).await {
+            error!("Failed to send message to instance {}: {}", instance, err);
+        }
+    }
+
+    /// Ask `owner` about `room`, `None` if it did not answer in time
+    async fn query(&self, owner: Uuid, room: RoomId, query: RoomQuery) -> Option<QueryAnswer> {
+        let id = Uuid::now_v7();
+        let (tx, rx) = oneshot::channel();
+        self.queries.lock().unwrap().insert(id, tx);
+        let msg = ClusterMessage::Query {
+            room,
+            origin: self.instance,
+            id,
+            query,
+        };
+        self.publish(owner, &msg).await;
+
+        let answer = tokio::time::timeout(QUERY_TIMEOUT, rx).await;
+        self.queries.lock().unwrap().remove(&id);
+        match answer {
+            Ok(Ok(answer)) => Some(answer),
+            _ => {
+                warn!("{}: Instance {} did not answer", room, owner);
+                None
+            }
+        }
+    }
+
+    /// Info of a room hosted by `owner`
+    pub async fn room_info(&self, owner: Uuid, room: RoomId) -> Option<RoomInfo> {
+        match self.query(owner, room, RoomQuery::GetInfo).await? {
+            QueryAnswer::Info { info } => info.map(|info| *info),
+            _ => None,
+        }
+    }
+
+    /// History of a room hosted by `owner`
+    pub async fn room_history(&self, owner: Uuid, room: RoomId) -> Option<Vec<Round>> {
+        match self.query(owner, room, RoomQuery::GetHistory).await? {
+            QueryAnswer::History { rounds } => rounds,
+            _ => None,
+        }
+    }
+
+    /// Change a room hosted by `owner`, returns whether it was changed
+    pub async fn change_room(&self, owner: Uuid, room: RoomId, change: RoomQuery) -> bool {
+        matches!(
+            self.query(owner, room, change).await,
+            Some(QueryAnswer::Done { done: true })
+        )
+    }
+
+    /// Answer a query of `origin` with the rooms of `game_server`
+    async fn answer(
+        &self,
+        room: RoomId,
+        origin: Uuid,
+        id: Uuid,
+        query: RoomQuery,
+        game_server: GameServerAddr,
+    ) {
+        let (reply, answer) = oneshot::channel();
+        let msg = GameServerMessage::Queried { room, query, reply };
+        if game_server.send(msg).await.is_err() {
+            return;
+        }
+        if let Ok(answer) = answer.await {
+            self.publish(origin, &ClusterMessage::Answer { id, answer })
+                .await;
+        }
+    }
+
+    /// Proxy for a room hosted by `owner`
+    pub fn start_proxy(&self, room: RoomId, owner: Uuid) -> RoomAddr {
+        info!("{}: Room is hosted by instance {}", room, owner);
+        RoomProxy {
+            room,
+            owner,
+            cluster: self.clone(),
+        }
+        .start()
+    }
+
+    /// Address for a player connected to instance `origin`
+    pub fn remote_player(&self, origin: Uuid, player_id: String) -> PlayerAddr {
+        let (tx, mut rx) = mpsc::channel::<GamePlayerMessage>(16);
+        let cluster = self.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let msg = ClusterMessage::ToPlayer {
+                    player: player_id.clone(),
+                    event: msg.into(),
+                };
+                cluster.publish(origin, &msg).await;
+            }
+        });
+        tx
+    }
+
+    async fn deliver(&self, player_id: String, event: PlayerEvent, game_server: &GameServerAddr) {
+        if let PlayerEvent::RoomMoved = event {
+            // the game server drops the proxy before its members join again
+            let member = self.members.lock().unwrap().get(&player_id).cloned();
+            if let Some(member) = member {
+                let msg = GameServerMessage::RoomMoved {
+                    room: member.room,
+                    owner: member.owner,
+                };
+                let _ = game_server.send(msg).await;
+            }
+            return;
+        }
+
+        let closing = matches!(
+            event,
+            PlayerEvent::Rejected { .. } | PlayerEvent::RoomClosed
+        );
+        let member = {
+            let mut members = self.members.lock().unwrap();
+            if closing {
+                members.remove(&player_id)
+            } else {
+                members.get(&player_id).cloned()
+            }
+        };
+
+        if let Some(member) = member {
+            let msg = event.into_message(member.proxy.clone());
+            if member.player.send(msg).await.is_err() {
+                debug!("{}: Remote member {} is gone", member.room, player_id);
+            }
+            if closing {
+                let _ = member.proxy.send(RoomMessage::CloseWhenEmpty).await;
+            }
+        } else {
+            debug!("Dropped message for unknown player {}", player_id);
+        }
+    }
+
+    /// Dispatch messages from other instances
+    pub async fn run(self, game_server: GameServerAddr) {
+        let mut rx = match self.bus.subscribe(&Self::channel(self.instance)).await {
+            Ok(rx) => rx,
+            Err(err) => {
+                error!("Failed to subscribe to cluster messages: {}", err);
+                return;
+            }
+        };
+        info!("Joined cluster as instance {}", self.instance);
+
+        while let Some(payload) = rx.recv().await {
+            match serde_json::from_str::<ClusterMessage>(&payload) {
+                Ok(ClusterMessage::ToRoom {
+                    room,
+                    origin,
+                    request,
+                }) => {
+                    let msg = GameServerMessage::Forwarded {
+                        room,
+                        origin,
+                        request,
+                    };
+                    if game_server.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(ClusterMessage::ToPlayer { player, event }) => {
+                    self.deliver(player, event, &game_server).await;
+                }
+                Ok(ClusterMessage::Query {
+                    room,
+                    origin,
+                    id,
+                    query,
+                }) => {
+                    // rooms may take a while, other messages must not wait
+                    let cluster = self.clone();
+                    let game_server = game_server.clone();
+                    tokio::spawn(async move {
+                        cluster.answer(room, origin, id, query, game_server).await
+                    });
+                }
+                Ok(ClusterMessage::Answer { id, answer }) => {
+                    if let Some(reply) = self.queries.lock().unwrap().remove(&id) {
+                        let _ = reply.send(answer);
+                    }
+                }
+                Err(err) => warn!("Ignored invalid cluster message: {}", err),
+            }
+        }
+    }
+}
+
+/// Stand-in for a room hosted by another instance
+struct RoomProxy {
+    room: RoomId,
+    owner: Uuid,
+    cluster: Cluster,
+}
+
+impl RoomProxy {
+    async fn forward(&self, request: RoomRequest) {
+        let msg = ClusterMessage::ToRoom {
+            room: self.room,
+            origin: self.cluster.instance,
+            request,
+        };
+        self.cluster.publish(self.owner, &msg).await;
+    }
+
+    fn is_empty(&self) -> bool {
+        let members = self.cluster.members.lock().unwrap();
+        !members.values().any(|member| member.room == self.room)
+    }
+
+    fn local_members(&self) -> Vec<PlayerAddr> {
+        let members = self.cluster.members.lock().unwrap();
+        members
+            .values()
+            .filter(|member| member.room == self.room)
+            .map(|member| member.player.clone())
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for RoomProxy {
+    type Message = RoomMessage;
+    type Context = Context<Self>;
+
+    async fn on_message(&mut self, msg: Self::Message, ctx: &mut Context<Self>) {
+        match msg {
+            RoomMessage::JoinRequest(player_addr, player) => {
+                self.cluster.members.lock().unwrap().insert(
+                    player.id.clone(),
+                    RemoteMember {
+                        player: player_addr,
+                        room: self.room,
+                        owner: self.owner,
+                        proxy: ctx.addr(),
+                    },
+                );
+                self.forward(RoomRequest::Join { player }).await;
+            }
+            RoomMessage::PlayerLeft(player_id) => {
+                self.cluster.members.lock().unwrap().remove(&player_id);
+                self.forward(RoomRequest::Left { player_id }).await;
+                if self.is_empty() {
+                    ctx.force_quit();
+                }
+            }
+            RoomMessage::PlayerVoted(player_id, vote) => {
+                self.forward(RoomRequest::Voted { player_id, vote }).await;
+            }
+            RoomMessage::UpdatePlayer { id, voter, name } => {
+                self.forward(RoomRequest::UpdatePlayer { id, voter, name })
+                    .await;
+            }
+            RoomMessage::ForceOpen => self.forward(RoomRequest::ForceOpen).await,
+            RoomMessage::Restart => self.forward(RoomRequest::Restart).await,
+            RoomMessage::Notice(message) => {
+                for player in self.local_members() {
+                    let _ = player
+                        .send(GamePlayerMessage::Notice(message.clone()))
+                        .await;
+                }
+            }
+            RoomMessage::GetInfo(reply) => {
+                let (cluster, owner, room) = (self.cluster.clone(), self.owner, self.room);
+                tokio::spawn(async move {
+                    if let Some(info) = cluster.room_info(owner, room).await {
+                        let _ = reply.send(info);
+                    }
+                });
+            }
+            RoomMessage::GetHistory(reply) => {
+                let (cluster, owner, room) = (self.cluster.clone(), self.owner, self.room);
+                tokio::spawn(async move {
+                    if let Some(rounds) = cluster.room_history(owner, room).await {
+                        let _ = reply.send(rounds);
+                    }
+                });
+            }
+            RoomMessage::Subscribe(webhook, reply) => {
+                let (cluster, owner, room) = (self.cluster.clone(), self.owner, self.room);
+                tokio::spawn(async move {
+                    // the game server stored the secret already
+                    let change = RoomQuery::Subscribe {
+                        url: webhook.url,
+                        secret: webhook.secret,
+                    };
+                    let _ = reply.send(cluster.change_room(owner, room, change).await);
+                });
+            }
+            RoomMessage::Unsubscribe(reply) => {
+                let (cluster, owner, room) = (self.cluster.clone(), self.owner, self.room);
+                tokio::spawn(async move {
+                    let change = RoomQuery::Unsubscribe;
+                    let _ = reply.send(cluster.change_room(owner, room, change).await);
+                });
+            }
+            RoomMessage::SetStories(stories) => {
+                let (cluster, owner, room) = (self.cluster.clone(), self.owner, self.room);
+                tokio::spawn(async move {
+                    let change = RoomQuery::SetStories { stories };
+                    if !cluster.change_room(owner, room, change).await {
+                        warn!("{}: Instance {} did not set stories", room, owner);
+                    }
+                });
+            }
+            RoomMessage::Close | RoomMessage::Shutdown => ctx.force_quit(),
+            RoomMessage::HandOver => {
+                info!(
+                    "{}: Instance {} does not host room anymore",
+                    self.room, self.owner
+                );
+                let members: Vec<PlayerAddr> = {
+                    let mut members = self.cluster.members.lock().unwrap();
+                    let moved: Vec<String> = members
+                        .iter()
+                        .filter(|(_, member)| member.room == self.room)
+                        .map(|(id, _)| id.clone())
+                        .collect();
+                    moved
+                        .iter()
+                        .filter_map(|id| members.remove(id))
+                        .map(|member| member.player)
+                        .collect()
+                };
+                for player in members {
+                    let _ = player.send(GamePlayerMessage::RoomMoved).await;
+                }
+                ctx.force_quit();
+            }
+            RoomMessage::CloseWhenEmpty => {
+                if self.is_empty() {
+                    ctx.force_quit();
+                }
+            }
+        }
+    }
+
+    async fn tear_down(&mut self, _ctx: &mut Context<Self>) {
+        debug!("{}: Proxy closed", self.room);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::adapters::memory::{MemoryMessageBus, MemoryRepository};
+    use crate::game_server::GameServer;
+    use crate::ports::{MessageBusRef, RoomLeaseRepository};
+
+    /// Game server of an instance sharing `repo` and `bus` with others
+    fn start_instance(repo: &Arc<MemoryRepository>, bus: &MessageBusRef) -> (GameServerAddr, Uuid) {
+        let cluster = Cluster::new(repo.clone(), bus.clone(), Duration::from_secs(30));
+        let instance = cluster.instance();
+        let game_server = GameServer::new(repo.clone(), repo.clone())
+            .with_cluster(cluster)
+            .start();
+        (game_server, instance)
+    }
+
+    async fn create_room(game_server: &GameServerAddr) -> RoomId {
+        let (reply, created) = oneshot::channel();
+        let msg = GameServerMessage::Create {
+            deck: "fibonacci".to_string(),
+            slug: None,
+            reply,
+        };
+        game_server.send(msg).await.unwrap();
+        created.await.unwrap().unwrap().id
+    }
+
+    async fn join(game_server: &GameServerAddr, room: RoomId, player_addr: PlayerAddr, id: &str) {
+        let msg = GameServerMessage::Join {
+            room: room.to_string(),
+            player_addr,
+            player: PlayerInformation {
+                id: id.to_string(),
+                voter: true,
+                name: None,
+            },
+        };
+        game_server.send(msg).await.unwrap();
+    }
+
+    async fn live_rooms(game_server: &GameServerAddr) -> Vec<RoomId> {
+        let (reply, rooms) = oneshot::channel();
+        let msg = GameServerMessage::GetLiveRoomIds { reply };
+        game_server.send(msg).await.unwrap();
+        rooms.await.unwrap()
+    }
+
+    /// Next message of a player that matches, other messages are skipped
+    async fn wait_for(
+        rx: &mut mpsc::Receiver<GamePlayerMessage>,
+        matches: impl Fn(&GamePlayerMessage) -> bool,
+    ) -> GamePlayerMessage {
+        let next = async {
+            loop {
+                let msg = rx.recv().await.expect("player is gone");
+                if matches(&msg) {
+                    return msg;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), next)
+            .await
+            .expect("message did not arrive")
+    }
+
+    fn is_welcome(msg: &GamePlayerMessage) -> bool {
+        matches!(msg, GamePlayerMessage::Welcome(..))
+    }
+
+    fn is_moved(msg: &GamePlayerMessage) -> bool {
+        matches!(msg, GamePlayerMessage::RoomMoved)
+    }
+
+    #[tokio::test]
+    async fn stop_serving_room_after_lease_was_taken() {
+        let repo = Arc::new(MemoryRepository::default());
+        let bus: MessageBusRef = Arc::new(MemoryMessageBus::default());
+        let (a, a_instance) = start_instance(&repo, &bus);
+        let (b, _) = start_instance(&repo, &bus);
+        let room = create_room(&a).await;
+        let (player_a, mut rx_a) = mpsc::channel(16);
+        join(&a, room, player_a.clone(), "A").await;
+        wait_for(&mut rx_a, is_welcome).await;
+        // lease of A expired while it could not reach the database
+        repo.release_room_lease(&room, a_instance).await.unwrap();
+        let (player_b, mut rx_b) = mpsc::channel(16);
+        join(&b, room, player_b, "B").await;
+        wait_for(&mut rx_b, is_welcome).await;
+
+        // ACT
+        a.send(GameServerMessage::RenewLeases).await.unwrap();
+
+        // ASSERT
+        wait_for(&mut rx_a, is_moved).await;
+        assert_eq!(live_rooms(&a).await, []);
+        assert_eq!(live_rooms(&b).await, [room]);
+
+        // joining again reaches the room at B
+        join(&a, room, player_a, "A").await;
+        wait_for(&mut rx_a, is_welcome).await;
+        wait_for(
+            &mut rx_b,
+            |msg| matches!(msg, GamePlayerMessage::PlayerJoined(player) if player.id() == "A"),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn fail_over_when_owner_died() {
+        let repo = Arc::new(MemoryRepository::default());
+        let bus: MessageBusRef = Arc::new(MemoryMessageBus::default());
+        let (a, a_instance) = start_instance(&repo, &bus);
+        let (b, _) = start_instance(&repo, &bus);
+        let room = create_room(&a).await;
+        let (player_b, mut rx_b) = mpsc::channel(16);
+        join(&b, room, player_b.clone(), "B").await;
+        wait_for(&mut rx_b, is_welcome).await;
+        // A died and its lease expired
+        a.send(GameServerMessage::Shutdown {
+            reply: oneshot::channel().0,
+        })
+        .await
+        .unwrap();
+        a.closed().await;
+        repo.release_room_lease(&room, a_instance).await.unwrap();
+
+        // ACT
+        b.send(GameServerMessage::RenewLeases).await.unwrap();
+
+        // ASSERT
+        wait_for(&mut rx_b, is_moved).await;
+        join(&b, room, player_b, "B").await;
+        wait_for(&mut rx_b, is_welcome).await;
+        assert_eq!(live_rooms(&b).await, [room]);
+    }
+
+    #[tokio::test]
+    async fn query_room_hosted_by_other_instance() {
+        let repo = Arc::new(MemoryRepository::default());
+        let bus: MessageBusRef = Arc::new(MemoryMessageBus::default());
+        let (a, _) = start_instance(&repo, &bus);
+        let (b, _) = start_instance(&repo, &bus);
+        let room = create_room(&a).await;
+        let (player_a, mut rx_a) = mpsc::channel(16);
+        join(&a, room, player_a, "A").await;
+        wait_for(&mut rx_a, is_welcome).await;
+
+        for proxied in [false, true] {
+            if proxied {
+                let (player_b, mut rx_b) = mpsc::channel(16);
+                join(&b, room, player_b, "B").await;
+                wait_for(&mut rx_b, is_welcome).await;
+            }
+
+            // ACT
+            let (reply, info) = oneshot::channel();
+            b.send(GameServerMessage::GetRoom { room, reply })
+                .await
+                .unwrap();
+            let info = info.await.unwrap();
+            let (reply, history) = oneshot::channel();
+            b.send(GameServerMessage::GetRoomHistory { room, reply })
+                .await
+                .unwrap();
+            let history = history.await.unwrap();
+
+            // ASSERT
+            let info = info.expect("room info is missing");
+            assert_eq!(info.id, room);
+            assert_eq!(info.players.len(), if proxied { 2 } else { 1 });
+            assert_eq!(history, Some(vec![]));
+        }
+    }
+
+    #[tokio::test]
+    async fn close_room_hosted_by_other_instance() {
+        let repo = Arc::new(MemoryRepository::default());
+        let bus: MessageBusRef = Arc::new(MemoryMessageBus::default());
+        let (a, _) = start_instance(&repo, &bus);
+        let (b, _) = start_instance(&repo, &bus);
+        let room = create_room(&a).await;
+        let (player_a, mut rx_a) = mpsc::channel(16);
+        join(&a, room, player_a, "A").await;
+        wait_for(&mut rx_a, is_welcome).await;
+
+        // ACT
+        let (reply, closed) = oneshot::channel();
+        b.send(GameServerMessage::CloseRoom { room, reply })
+            .await
+            .unwrap();
+        let closed = closed.await.unwrap();
+
+        // ASSERT
+        assert!(closed);
+        wait_for(&mut rx_a, |msg| {
+            matches!(msg, GamePlayerMessage::RoomClosed)
+        })
+        .await;
+        assert_eq!(live_rooms(&a).await, []);
+        // released once the room has written its events
+        let released = async {
+            while !repo.room_lease_owners(&[room]).await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), released)
+            .await
+            .expect("lease was not released");
+    }
+
+    #[test]
+    fn cluster_message_roundtrip() {
+        let room_id = RoomId::generate();
+        let msg = ClusterMessage::ToRoom {
+            room: room_id,
+            origin: Uuid::nil(),
+            request: RoomRequest::Voted {
+                player_id: "P1".to_string(),
+                vote: Some("5".to_string()),
+            },
+        };
+
+        let json = serde_json::to_string(&msg).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            ClusterMessage::ToRoom {
+                room,
+                origin,
+                request: RoomRequest::Voted { player_id, vote },
+            } => {
+                assert_eq!(room, room_id);
+                assert_eq!(origin, Uuid::nil());
+                assert_eq!(player_id, "P1");
+                assert_eq!(vote.as_deref(), Some("5"));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Results of a planning session as CSV, JSON or Markdown
+
+use quick_error::quick_error;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::room::{Round, VoteStats};
+use crate::room_id::RoomId;
+use crate::story::Story;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    #[serde(rename = "md")]
+    Markdown,
+}
+
+impl ExportFormat {
+    /// Formats in order of preference when the client accepts several
+    pub const ALL: [ExportFormat; 3] = [Self::Json, Self::Csv, Self::Markdown];
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Json => "application/json",
+            Self::Markdown => "text/markdown",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Markdown => "md",
+        }
+    }
+
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.media_type() == media_type)
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ExportError {
+        Csv(err: csv::Error) {
+            display("CSV error: {}", err)
+            from()
+        }
+        Json(err: serde_json::Error) {
+            display("JSON error: {}", err)
+            from()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedVote {
+    pub player: String,
+    pub name: Option<String>,
+    /// `None` if the player did not vote
+    pub vote: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedRound {
+    pub number: u32,
+    /// RFC 3339 timestamp in UTC
+    pub revealed_at: String,
+    pub story: Option<Story>,
+    pub votes: Vec<ExportedVote>,
+    pub stats: VoteStats,
+}
+
+impl From<&Round> for ExportedRound {
+    fn from(round: &Round) -> Self {
+        let state = round.state();
+        // voters in the order they joined
+        let mut votes: Vec<ExportedVote> = round
+            .players()
+            .iter()
+            .filter_map(|player| {
+                state.votes().get(player.id()).map(|vote| ExportedVote {
+                    player: player.id().to_string(),
+                    name: player.name().map(str::to_string),
+                    vote: vote.clone(),
+                })
+            })
+            .collect();
+        let mut left: Vec<_> = state
+            .votes()
+            .iter()
+            .filter(|(id, _)| !votes.iter().any(|vote| &vote.player == *id))
+            .collect();
+        left.sort();
+        votes.extend(left.into_iter().map(|(id, vote)| ExportedVote {
+            player: id.clone(),
+            name: None,
+            vote: vote.clone(),
+        }));
+
+        Self {
+            number: round.number(),
+            revealed_at: format_timestamp(round.revealed_at()),
+            story: state.story().cloned(),
+            votes,
+            stats: round.stats(),
+        }
+    }
+}
+
+/// Revealed rounds of a room
+#[derive(Debug, Serialize)]
+pub struct Export {
+    pub room: RoomId,
+    pub rounds: Vec<ExportedRound>,
+}
+
+impl Export {
+    pub fn new(room: RoomId, rounds: &[Round]) -> Self {
+        Self {
+            room,
+            rounds: rounds.iter().map(ExportedRound::from).collect(),
+        }
+    }
+
+    pub fn render(&self, format: ExportFormat) -> Result<String, ExportError> {
+        match format {
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            ExportFormat::Markdown => Ok(self.to_markdown()),
+        }
+    }
+
+    /// One row per vote, so the file can be filtered in a spreadsheet
+    fn to_csv(&self) -> Result<String, ExportError> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record([
+            "round",
+            "revealed_at",
+            "story_key",
+            "story_title",
+            "player",
+            "name",
+            "vote",
+            "estimate",
+            "consensus",
+            "min",
+            "max",
+            "mean",
+            "median",
+        ])?;
+        for round in &self.rounds {
+            let story = round.story.as_ref();
+            let stats = &round.stats;
+            let round_columns = |player: &str, name: &str, vote: &str| {
+                [
+                    round.number.to_string(),
+                    round.revealed_at.clone(),
+                    story.and_then(|s| s.key.clone()).unwrap_or_default(),
+                    story.map(|s| s.title.clone()).unwrap_or_default(),
+                    player.to_string(),
+                    name.to_string(),
+                    vote.to_string(),
+                    stats.estimate.clone().unwrap_or_default(),
+                    stats.consensus.to_string(),
+                    format_number(stats.min),
+                    format_number(stats.max),
+                    format_number(stats.mean),
+                    format_number(stats.median),
+                ]
+            };
+            if round.votes.is_empty() {
+                writer.write_record(round_columns("", "", ""))?;
+            }
+            for vote in &round.votes {
+                writer.write_record(round_columns(
+                    &vote.player,
+                    vote.name.as_deref().unwrap_or_default(),
+                    vote.vote.as_deref().unwrap_or_default(),
+                ))?;
+            }
+        }
+
+        let bytes = writer
+            .into_inner()
+            .map_err(|err| csv::Error::from(err.into_error()))?;
+        Ok(String::from_utf8(bytes).expect("CSV of strings is UTF-8"))
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = format!("# Estimates of room {}\n", self.room);
+        if self.rounds.is_empty() {
+            out.push_str("\nNo rounds were revealed.\n");
+        }
+        for round in &self.rounds {
+            let title = match &round.story {
+                Some(Story {
+                    key: Some(key),
+                    title,
+                    ..
+                }) => format!("{key}: {title}"),
+                Some(story) => story.title.clone(),
+                None => format!("Round {}", round.number),
+            };
+            out.push_str(&format!("\n## {}\n\n", escape_markdown(&title)));
+            if let Some(link) = round.story.as_ref().and_then(|s| s.link.as_ref()) {
+                out.push_str(&format!("<{link}>\n\n"));
+            }
+            out.push_str(&format!(
+                "Revealed at {}. Estimate: **{}**",
+                round.revealed_at,
+                round
+                    .stats
+                    .estimate
+                    .as_deref()
+                    .map(escape_markdown)
+                    .unwrap_or_else(|| "none".to_string()),
+            ));
+            if let (Some(mean), Some(median)) = (round.stats.mean, round.stats.median) {
+                out.push_str(&format!(
+                    " (mean {}, median {})",
+                    format_number(Some(mean)),
+                    format_number(Some(median))
+                ));
+            }
+            out.push_str("\n\n| Player | Vote |\n| --- | --- |\n");
+            for vote in &round.votes {
+                out.push_str(&format!(
+                    "| {} | {} |\n",
+                    escape_markdown(vote.name.as_deref().unwrap_or(&vote.player)),
+                    escape_markdown(vote.vote.as_deref().unwrap_or("–")),
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Unix timestamp in seconds as RFC 3339 in UTC
+fn format_timestamp(secs: u64) -> String {
+    let Ok(time) = OffsetDateTime::from_unix_timestamp(secs as i64) else {
+        return secs.to_string();
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+fn format_number(value: Option<f64>) -> String {
+    value
+        .map(|value| ((value * 100.0).round() / 100.0).to_string())
+        .unwrap_or_default()
+}
+
+/// Escape text for a Markdown table cell or heading
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\r' | '\n' => out.push(' '),
+            '\\' | '|' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round() -> Round {
+        serde_json::from_value(json!({
+            "number": 1,
+            "revealed_at": 1700000000,
+            "state": {
+                "deck": "fibonacci",
+                "open": true,
+                "votes": {"p1": "3", "p2": "5", "p3": null},
+                "story": {"key": "GOE-1", "title": "Export | results"},
+            },
+            "players": [
+                {"id": "p2", "name": "Bob", "voter": true},
+                {"id": "p1", "name": "Alice", "voter": true},
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn export() -> Export {
+        Export::new(RoomId::generate(), &[round()])
+    }
+
+    #[test]
+    fn format_timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn export_votes_in_join_order() {
+        // ACT
+        let exported = export();
+
+        // ASSERT
+        let votes: Vec<_> = exported.rounds[0]
+            .votes
+            .iter()
+            .map(|vote| (vote.player.as_str(), vote.vote.as_deref()))
+            .collect();
+        assert_eq!(votes, [("p2", Some("5")), ("p1", Some("3")), ("p3", None)]);
+    }
+
+    #[test]
+    fn export_csv() {
+        // ACT
+        let csv = export().render(ExportFormat::Csv).unwrap();
+
+        // ASSERT
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            "1,2023-11-14T22:13:20Z,GOE-1,Export | results,p2,Bob,5,5,false,3,5,4,4"
+        );
+        assert_eq!(
+            lines[3],
+            "1,2023-11-14T22:13:20Z,GOE-1,Export | results,p3,,,5,false,3,5,4,4"
+        );
+    }
+
+    #[test]
+    fn export_markdown() {
+        // ACT
+        let md = export().render(ExportFormat::Markdown).unwrap();
+
+        // ASSERT
+        assert!(md.contains("## GOE-1: Export \\| results\n"));
+        assert!(md.contains("Estimate: **5** (mean 4, median 4)"));
+        assert!(md.contains("| Alice | 3 |\n"));
+        assert!(md.contains("| p3 | – |\n"));
+    }
+}
@@ -1,15 +1,30 @@
-use log::{error, warn};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::time::interval;
+use uuid::Uuid;
 
-use uactor::blocking::Actor;
+use uactor::blocking::{Actor, ActorContext};
 use uactor::tokio::blocking::Context;
 
+use crate::cluster::{Cluster, QueryAnswer, RoomQuery, RoomRequest};
 use crate::player::{PlayerAddr, PlayerInformation};
-use crate::ports::RoomRepositoryRef;
-use crate::room::{GamePlayerMessage, RejectReason, Room, RoomAddr, RoomMessage};
+use crate::ports::{
+    DbResult, LeaseOwner, RoomAliasRepositoryRef, RoomRepositoryRef, WebhookSecretRepositoryRef,
+};
+use crate::room::{
+    GamePlayerMessage, RejectReason, Room, RoomAddr, RoomEvent, RoomInfo, RoomMessage, Round,
+};
+use crate::room_alias::{gen_room_code, is_valid_alias, normalize_slug};
+use crate::room_id::RoomId;
+use crate::story::Story;
+use crate::webhook::{Subscription, Webhook, WebhookEvent, Webhooks};
+
+/// Tries to find a free generated room code
+const ROOM_CODE_ATTEMPTS: usize = 8;
 
 #[derive(Debug)]
 pub enum GameServerMessage {
@@ -21,28 +36,1025 @@ pub enum GameServerMessage {
     },
     Create {
         deck: String,
-        reply: oneshot::Sender<Option<String>>,
+        slug: Option<String>,
+        reply: oneshot::Sender<Result<CreatedRoom, CreateRoomError>>,
+    },
+    /// Create a room and join its creator
+    CreateAndJoin {
+        deck: String,
+        slug: Option<String>,
+
+        player_addr: PlayerAddr,
+        player: PlayerInformation,
+    },
+
+    // queries
+    /// Room ID for a room ID, code or slug
+    ResolveRoom {
+        room: String,
+        reply: oneshot::Sender<Option<RoomId>>,
+    },
+    /// Recent rounds of a room, also of rooms hosted elsewhere or not live
+    GetRoomHistory {
+        room: RoomId,
+        reply: oneshot::Sender<Option<Vec<Round>>>,
+    },
+    /// IDs of the rooms hosted by this instance
+    GetLiveRoomIds {
+        reply: oneshot::Sender<Vec<RoomId>>,
+    },
+    /// Revealed rounds from the event log, `None` for unknown rooms
+    GetPersistedRounds {
+        room: RoomId,
+        reply: oneshot::Sender<DbResult<Option<Vec<Round>>>>,
+    },
+    /// Replace the stories of a room, replies `false` for unknown rooms
+    SetStories {
+        room: RoomId,
+        stories: Vec<Story>,
+        reply: oneshot::Sender<bool>,
+    },
+
+    // admin
+    ListRooms {
+        reply: oneshot::Sender<Vec<RoomInfo>>,
+    },
+    GetRoom {
+        room: RoomId,
+        reply: oneshot::Sender<Option<RoomInfo>>,
+    },
+    CloseRoom {
+        room: RoomId,
+        reply: oneshot::Sender<bool>,
+    },
+    Broadcast {
+        message: String,
+        reply: oneshot::Sender<usize>,
+    },
+    /// Send the events of a room to a webhook, replies `false` for unknown
+    /// rooms
+    Subscribe {
+        room: RoomId,
+        webhook: Webhook,
+        reply: oneshot::Sender<bool>,
+    },
+    /// Webhooks of a room, also of rooms that are not live, `None` for
+    /// unknown rooms
+    GetWebhooks {
+        room: RoomId,
+        reply: oneshot::Sender<Option<Vec<Subscription>>>,
+    },
+    /// Remove all webhooks of a room, replies `false` for unknown rooms
+    Unsubscribe {
+        room: RoomId,
+        reply: oneshot::Sender<bool>,
+    },
+
+    // shutdown
+    StopAccepting,
+    Shutdown {
+        reply: oneshot::Sender<()>,
+    },
+
+    // cluster
+    Forwarded {
+        room: RoomId,
+        origin: Uuid,
+        request: RoomRequest,
+    },
+    /// Query of another instance about a room hosted here
+    Queried {
+        room: RoomId,
+        query: RoomQuery,
+        reply: oneshot::Sender<QueryAnswer>,
+    },
+    RenewLeases,
+    /// Room is not hosted by `owner` anymore
+    RoomMoved {
+        room: RoomId,
+        owner: Uuid,
     },
+
+    // event log
+    /// Room was restored from its event log, or the reason it was not
+    Restored {
+        room: RoomId,
+        restored: Result<RoomAddr, RejectReason>,
+    },
+    /// Event log of a room that is not live was written, because it was
+    /// changed or the room closed
+    EventLogWritten {
+        room: RoomId,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedRoom {
+    pub id: RoomId,
+    pub code: Option<String>,
+    pub slug: Option<String>,
+}
+
+impl CreatedRoom {
+    /// Name to share with other players
+    pub fn join_name(&self) -> String {
+        self.slug
+            .clone()
+            .or_else(|| self.code.clone())
+            .unwrap_or_else(|| self.id.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateRoomError {
+    InvalidSlug,
+    SlugTaken,
+    Unavailable,
+}
+
+impl From<CreateRoomError> for RejectReason {
+    fn from(err: CreateRoomError) -> Self {
+        match err {
+            CreateRoomError::InvalidSlug => RejectReason::InvalidSlug,
+            CreateRoomError::SlugTaken => RejectReason::SlugTaken,
+            CreateRoomError::Unavailable => RejectReason::CreateGameError,
+        }
+    }
+}
+
+/// Room hosted by another instance
+struct RemoteRoom {
+    owner: Uuid,
+    proxy: RoomAddr,
+}
+
+/// Requests for a room whose event log is read or written outside of the
+/// actor, they are handled when it is done
+#[derive(Default)]
+struct Loading {
+    joins: Vec<(PlayerAddr, PlayerInformation)>,
+    changes: Vec<(RoomQuery, oneshot::Sender<bool>)>,
 }
 
 pub struct GameServer {
-    rooms: HashMap<String, RoomAddr>,
+    rooms: HashMap<RoomId, RoomAddr>,
+    proxies: HashMap<RoomId, RemoteRoom>,
+    loading: HashMap<RoomId, Loading>,
+    aliases: HashMap<String, RoomId>,
     room_repo: RoomRepositoryRef,
+    alias_repo: RoomAliasRepositoryRef,
+    cluster: Option<Cluster>,
+    webhooks: Webhooks,
+    webhook_secrets: Option<WebhookSecretRepositoryRef>,
+    accepting: bool,
 }
 
 pub type GameServerAddr = mpsc::Sender<GameServerMessage>;
 
 impl GameServer {
-    pub fn new(room_repo: RoomRepositoryRef) -> Self {
+    pub fn new(room_repo: RoomRepositoryRef, alias_repo: RoomAliasRepositoryRef) -> Self {
         Self {
             rooms: Default::default(),
+            proxies: Default::default(),
+            loading: Default::default(),
+            aliases: Default::default(),
             room_repo,
+            alias_repo,
+            cluster: None,
+            webhooks: Webhooks::default(),
+            webhook_secrets: None,
+            accepting: true,
         }
     }
 
+    /// Share rooms with other instances
+    pub fn with_cluster(mut self, cluster: Cluster) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Send room events to webhooks
+    pub fn with_webhooks(mut self, webhooks: Webhooks) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Keep the secrets of the webhooks of rooms, without it only webhooks
+    /// without a secret can be added
+    pub fn with_webhook_secrets(mut self, secrets: WebhookSecretRepositoryRef) -> Self {
+        self.webhook_secrets = Some(secrets);
+        self
+    }
+
     async fn send_rejection(player: &PlayerAddr, reason: RejectReason) {
         let _ = player.send(GamePlayerMessage::Rejected(reason)).await;
     }
+
+    async fn query_room(room: RoomAddr) -> Option<RoomInfo> {
+        let (tx, rx) = oneshot::channel();
+        room.send(RoomMessage::GetInfo(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    async fn query_history(room: RoomAddr) -> Option<Vec<Round>> {
+        let (tx, rx) = oneshot::channel();
+        room.send(RoomMessage::GetHistory(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    async fn ask_room<T>(
+        room: RoomAddr,
+        msg: impl FnOnce(oneshot::Sender<T>) -> RoomMessage,
+    ) -> Option<T> {
+        let (tx, rx) = oneshot::channel();
+        room.send(msg(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// Change a room, wherever it is hosted
+    ///
+    /// Rooms that are not live are changed in their event log, while
+    /// holding their lease so no other instance restores them meanwhile.
+    /// Secrets of subscribed webhooks are stored here, before the room is
+    /// changed, and never sent to other instances.
+    async fn change_room(
+        &mut self,
+        room: RoomId,
+        change: RoomQuery,
+        reply: oneshot::Sender<bool>,
+        server: GameServerAddr,
+    ) {
+        let secrets = self.webhook_secrets.clone();
+        if let Some(room_addr) = self.live_room(&room) {
+            <Self as Actor>::Context::spawn(async move {
+                let done = Self::store_secret(&secrets, room, &change).await
+                    && Self::change_local_room(room, room_addr, change, secrets).await;
+                let _ = reply.send(done);
+            });
+            return;
+        }
+        if let Some(loading) = self.loading.get_mut(&room) {
+            loading.changes.push((change, reply));
+            return;
+        }
+
+        let owner = self.live_proxy(&room).and_then(|_| self.proxies.get(&room));
+        let owner = owner.map(|remote| remote.owner);
+        if let Some(cluster) = &self.cluster {
+            let owner = match owner {
+                Some(owner) => Some(owner),
+                None => match cluster.acquire_lease(&room).await {
+                    Ok(LeaseOwner::Own) => None,
+                    Ok(LeaseOwner::Other(owner)) => Some(owner),
+                    Err(db_err) => {
+                        error!("Failed to acquire lease for room {}: {:?}", room, db_err);
+                        let _ = reply.send(false);
+                        return;
+                    }
+                },
+            };
+            if let Some(owner) = owner {
+                let cluster = cluster.clone();
+                <Self as Actor>::Context::spawn(async move {
+                    let done = Self::store_secret(&secrets, room, &change).await
+                        && cluster.change_room(owner, room, change).await;
+                    let _ = reply.send(done);
+                });
+                return;
+            }
+        }
+
+        self.loading.insert(room, Loading::default());
+        let repo = self.room_repo.clone();
+        // read the event log outside of the actor to not block joins
+        <Self as Actor>::Context::spawn(async move {
+            let done = Self::change_stored_room(repo, secrets, room, change).await;
+            let _ = server
+                .send(GameServerMessage::EventLogWritten { room })
+                .await;
+            let _ = reply.send(done);
+        });
+    }
+
+    /// Release the lease of a room whose event log was written, or restore
+    /// it for the players that joined meanwhile
+    async fn event_log_written(&mut self, room: RoomId, server: GameServerAddr) {
+        let loading = self.loading.remove(&room).unwrap_or_default();
+        if !loading.joins.is_empty() {
+            // the lease is kept for the restored room
+            self.loading.insert(room, loading);
+            self.restore(room, server);
+            return;
+        }
+        if let Some(cluster) = &self.cluster {
+            cluster.release_lease(&room).await;
+        }
+        for (change, reply) in loading.changes {
+            self.change_room(room, change, reply, server.clone()).await;
+        }
+    }
+
+    /// Store the secret of a webhook that is subscribed
+    async fn store_secret(
+        secrets: &Option<WebhookSecretRepositoryRef>,
+        room: RoomId,
+        change: &RoomQuery,
+    ) -> bool {
+        let RoomQuery::Subscribe { url, secret } = change else {
+            return true;
+        };
+        let Some(secrets) = secrets else {
+            return secret.is_none();
+        };
+        match secrets
+            .set_webhook_secret(&room, url, secret.as_deref())
+            .await
+        {
+            Ok(()) => true,
+            Err(err) => {
+                error!("{}: Failed to store webhook secret: {}", room, err);
+                false
+            }
+        }
+    }
+
+    /// Stored secret of a webhook, `Err` when it could not be read
+    async fn stored_secret(
+        secrets: &Option<WebhookSecretRepositoryRef>,
+        room: RoomId,
+        url: &str,
+    ) -> Result<Option<String>, ()> {
+        let Some(secrets) = secrets else {
+            return Ok(None);
+        };
+        match secrets.get_webhook_secrets(&room).await {
+            Ok(mut secrets) => Ok(secrets.remove(url)),
+            Err(err) => {
+                error!("{}: Failed to read webhook secrets: {}", room, err);
+                Err(())
+            }
+        }
+    }
+
+    /// Delete the secrets of the webhooks of a room that were removed
+    async fn delete_secrets(secrets: &Option<WebhookSecretRepositoryRef>, room: RoomId) {
+        if let Some(secrets) = secrets {
+            if let Err(err) = secrets.delete_webhook_secrets(&room).await {
+                error!("{}: Failed to delete webhook secrets: {}", room, err);
+            }
+        }
+    }
+
+    async fn change_local_room(
+        room: RoomId,
+        room_addr: RoomAddr,
+        change: RoomQuery,
+        secrets: Option<WebhookSecretRepositoryRef>,
+    ) -> bool {
+        let done = match change {
+            RoomQuery::Subscribe { url, .. } => {
+                // also for subscriptions from other instances, which do not
+                // send the secret
+                let Ok(secret) = Self::stored_secret(&secrets, room, &url).await else {
+                    return false;
+                };
+                let webhook = Webhook { url, secret };
+                Self::ask_room(room_addr, |reply| RoomMessage::Subscribe(webhook, reply)).await
+            }
+            RoomQuery::Unsubscribe => {
+                let done = Self::ask_room(room_addr, RoomMessage::Unsubscribe).await;
+                if done == Some(true) {
+                    Self::delete_secrets(&secrets, room).await;
+                }
+                done
+            }
+            RoomQuery::SetStories { stories } => Some(
+                room_addr
+                    .send(RoomMessage::SetStories(stories))
+                    .await
+                    .is_ok(),
+            ),
+            RoomQuery::GetInfo | RoomQuery::GetHistory | RoomQuery::Close => None,
+        };
+        done.unwrap_or(false)
+    }
+
+    /// Change a room that is not live by appending to its event log
+    async fn change_stored_room(
+        repo: RoomRepositoryRef,
+        secrets: Option<WebhookSecretRepositoryRef>,
+        room: RoomId,
+        change: RoomQuery,
+    ) -> bool {
+        let events = match repo.get_room_events(&room).await {
+            Ok(events) => events,
+            Err(err) => {
+                error!("{}: Failed to read room: {}", room, err);
+                return false;
+            }
+        };
+        let Some(mut urls) = Room::persisted_webhooks(events) else {
+            return false;
+        };
+        if !Self::store_secret(&secrets, room, &change).await {
+            return false;
+        }
+        let unsubscribe = matches!(change, RoomQuery::Unsubscribe);
+        let event = match change {
+            RoomQuery::SetStories { stories } => RoomEvent::StoriesChanged { stories },
+            RoomQuery::Subscribe { url, .. } => {
+                urls.retain(|existing| *existing != url);
+                urls.push(url);
+                RoomEvent::WebhooksChanged { urls }
+            }
+            RoomQuery::Unsubscribe => RoomEvent::WebhooksChanged { urls: vec![] },
+            RoomQuery::GetInfo | RoomQuery::GetHistory | RoomQuery::Close => return false,
+        };
+        if let Err(err) = repo.append_room_event(&room, event).await {
+            error!("{}: Failed to change room: {}", room, err);
+            return false;
+        }
+        if unsubscribe {
+            Self::delete_secrets(&secrets, room).await;
+        }
+        true
+    }
+
+    /// Webhooks of a room that is not live, from its event log
+    async fn stored_webhooks(
+        repo: RoomRepositoryRef,
+        secrets: Option<WebhookSecretRepositoryRef>,
+        room: RoomId,
+    ) -> Option<Vec<Subscription>> {
+        let urls = match repo.get_room_events(&room).await {
+            Ok(events) => Room::persisted_webhooks(events)?,
+            Err(err) => {
+                error!("{}: Failed to read webhooks: {}", room, err);
+                return None;
+            }
+        };
+        let signed = match &secrets {
+            Some(secrets) => match secrets.get_webhook_secrets(&room).await {
+                Ok(secrets) => secrets,
+                Err(err) => {
+                    error!("{}: Failed to read webhook secrets: {}", room, err);
+                    return None;
+                }
+            },
+            None => HashMap::new(),
+        };
+        let webhooks = urls
+            .into_iter()
+            .map(|url| Subscription {
+                signed: signed.contains_key(&url),
+                url,
+            })
+            .collect();
+        Some(webhooks)
+    }
+
+    /// Cluster and owner of a room hosted by another instance
+    async fn remote_host(cluster: Option<Cluster>, room: RoomId) -> Option<(Cluster, Uuid)> {
+        let cluster = cluster?;
+        let owner = cluster.remote_owner(room).await?;
+        Some((cluster, owner))
+    }
+
+    /// Revealed rounds of a room that is not live, from its event log
+    async fn stored_history(repo: RoomRepositoryRef, room: RoomId) -> Option<Vec<Round>> {
+        match repo.get_room_events(&room).await {
+            Ok(events) => Room::persisted_history(events),
+            Err(err) => {
+                error!("{}: Failed to read history: {}", room, err);
+                None
+            }
+        }
+    }
+
+    fn live_rooms(&mut self) -> Vec<RoomAddr> {
+        self.forget_closed_rooms();
+        self.rooms.values().cloned().collect()
+    }
+
+    /// Drop closed rooms and the cached aliases of rooms that are not live
+    ///
+    /// Rooms that are not live can be purged, so their aliases must be
+    /// resolved by the repository again.
+    fn forget_closed_rooms(&mut self) {
+        self.rooms.retain(|_, room| !room.is_closed());
+        self.proxies.retain(|_, remote| !remote.proxy.is_closed());
+        let (rooms, proxies) = (&self.rooms, &self.proxies);
+        self.aliases
+            .retain(|_, room_id| rooms.contains_key(room_id) || proxies.contains_key(room_id));
+    }
+
+    fn live_room(&mut self, room: &RoomId) -> Option<RoomAddr> {
+        let room_addr = self.rooms.get(room)?;
+        if room_addr.is_closed() {
+            // room closed itself, because it was empty
+            self.rooms.remove(room);
+            None
+        } else {
+            Some(room_addr.clone())
+        }
+    }
+
+    fn live_proxy(&mut self, room: &RoomId) -> Option<RoomAddr> {
+        let remote = self.proxies.get(room)?;
+        if remote.proxy.is_closed() {
+            self.proxies.remove(room);
+            None
+        } else {
+            Some(remote.proxy.clone())
+        }
+    }
+
+    /// Canonical room ID for a room ID, code or slug
+    ///
+    /// Malformed names are rejected here, so they never reach a repository.
+    async fn resolve_room(&mut self, room: &str) -> Result<RoomId, RejectReason> {
+        let room_id = room.parse::<RoomId>().ok();
+        if let Some(room_id) = room_id {
+            if self.rooms.contains_key(&room_id) {
+                return Ok(room_id);
+            }
+        }
+        if !is_valid_alias(room) {
+            return room_id.ok_or(RejectReason::RoomDoesNotExist);
+        }
+        if let Some(room_id) = self.aliases.get(room) {
+            return Ok(*room_id);
+        }
+
+        match self.alias_repo.resolve_room_alias(room).await {
+            Ok(Some(room_id)) => {
+                self.aliases.insert(room.to_string(), room_id);
+                Ok(room_id)
+            }
+            Ok(None) => room_id.ok_or(RejectReason::RoomDoesNotExist),
+            Err(db_err) => {
+                error!("Failed to resolve room alias {}: {:?}", room, db_err);
+                Err(RejectReason::JoinGameError)
+            }
+        }
+    }
+
+    async fn create_alias(&self, alias: &str, room_id: &RoomId) -> Result<bool, CreateRoomError> {
+        self.alias_repo
+            .create_room_alias(alias, room_id)
+            .await
+            .map_err(|err| {
+                warn!(
+                    "Unable to create alias {} for room {}: {}",
+                    alias, room_id, err
+                );
+                CreateRoomError::Unavailable
+            })
+    }
+
+    async fn create_aliases(
+        &mut self,
+        room_id: RoomId,
+        slug: Option<String>,
+    ) -> Result<CreatedRoom, CreateRoomError> {
+        let slug = match slug {
+            Some(slug) => {
+                let slug = normalize_slug(&slug).ok_or(CreateRoomError::InvalidSlug)?;
+                if !self.create_alias(&slug, &room_id).await? {
+                    return Err(CreateRoomError::SlugTaken);
+                }
+                self.aliases.insert(slug.clone(), room_id);
+                Some(slug)
+            }
+            None => None,
+        };
+
+        let mut code = None;
+        for _ in 0..ROOM_CODE_ATTEMPTS {
+            let candidate = gen_room_code();
+            if self.create_alias(&candidate, &room_id).await? {
+                self.aliases.insert(candidate.clone(), room_id);
+                code = Some(candidate);
+                break;
+            }
+        }
+        if code.is_none() {
+            warn!("{}: No free room code found", room_id);
+        }
+
+        Ok(CreatedRoom {
+            id: room_id,
+            code,
+            slug,
+        })
+    }
+
+    async fn create(
+        &mut self,
+        deck: String,
+        slug: Option<String>,
+    ) -> Result<CreatedRoom, CreateRoomError> {
+        let room_id = RoomId::generate();
+        if let Some(cluster) = &self.cluster {
+            if let Err(err) = cluster.acquire_lease(&room_id).await {
+                warn!(
+                    "Unable to create room {}, because of lease error: {}",
+                    room_id, err
+                );
+                return Err(CreateRoomError::Unavailable);
+            }
+        }
+
+        let created = match self.create_aliases(room_id, slug).await {
+            Ok(created) => created,
+            Err(err) => {
+                self.abandon_room(room_id).await;
+                return Err(err);
+            }
+        };
+        match Room::new(room_id, deck.clone(), self.room_repo.clone()).await {
+            Ok(room) => {
+                let room = room.with_webhooks(self.webhooks.clone());
+                self.rooms.insert(room_id, room.start());
+                // a new room has no subscriptions yet
+                self.webhooks.emit(
+                    room_id,
+                    &[],
+                    WebhookEvent::RoomCreated {
+                        deck,
+                        code: created.code.clone(),
+                        slug: created.slug.clone(),
+                    },
+                );
+                Ok(created)
+            }
+            Err(err) => {
+                warn!(
+                    "Unable to create room {}, because of database error: {}",
+                    room_id, err
+                );
+                self.abandon_room(room_id).await;
+                Err(CreateRoomError::Unavailable)
+            }
+        }
+    }
+
+    /// Close a room hosted here, `false` if it is not live
+    ///
+    /// Its lease is released once the room has written its events, joins
+    /// and changes of the room wait until then.
+    async fn close_local_room(&mut self, room: RoomId, server: GameServerAddr) -> bool {
+        let Some(room_addr) = self.rooms.remove(&room) else {
+            return false;
+        };
+        if room_addr.send(RoomMessage::Close).await.is_err() {
+            return false;
+        }
+        info!("{}: Closed by administrator", room);
+        self.loading.insert(room, Loading::default());
+        // wait outside of the actor to not block joins
+        <Self as Actor>::Context::spawn(async move {
+            room_addr.closed().await;
+            let _ = server
+                .send(GameServerMessage::EventLogWritten { room })
+                .await;
+        });
+        true
+    }
+
+    /// Undo a failed creation: delete the aliases and release the lease
+    async fn abandon_room(&mut self, room_id: RoomId) {
+        self.aliases.retain(|_, id| id != &room_id);
+        if let Err(err) = self.alias_repo.delete_room_aliases(&room_id).await {
+            warn!("{}: Failed to delete aliases: {}", room_id, err);
+        }
+        if let Some(cluster) = &self.cluster {
+            cluster.release_lease(&room_id).await;
+        }
+    }
+
+    async fn create_and_join(
+        &mut self,
+        deck: String,
+        slug: Option<String>,
+        player_addr: PlayerAddr,
+        player: PlayerInformation,
+    ) {
+        if !self.accepting {
+            Self::send_rejection(&player_addr, RejectReason::ServerShuttingDown).await;
+            return;
+        }
+
+        let created = match self.create(deck, slug).await {
+            Ok(created) => created,
+            Err(err) => {
+                Self::send_rejection(&player_addr, err.into()).await;
+                return;
+            }
+        };
+        let room_id = created.id;
+        let _ = player_addr
+            .send(GamePlayerMessage::RoomCreated(created))
+            .await;
+        match self.live_room(&room_id) {
+            Some(room_addr) => {
+                let _ = room_addr
+                    .send(RoomMessage::JoinRequest(player_addr, player))
+                    .await;
+            }
+            None => Self::send_rejection(&player_addr, RejectReason::JoinGameError).await,
+        }
+    }
+
+    async fn join(
+        &mut self,
+        alias: String,
+        player_addr: PlayerAddr,
+        player: PlayerInformation,
+        server: GameServerAddr,
+    ) {
+        let room = match self.resolve_room(&alias).await {
+            Ok(room) => room,
+            Err(reason) => {
+                Self::send_rejection(&player_addr, reason).await;
+                return;
+            }
+        };
+        if room.to_string() != alias {
+            let _ = player_addr
+                .send(GamePlayerMessage::AliasResolved { alias, room })
+                .await;
+        }
+
+        if let Some(room_addr) = self.live_room(&room).or_else(|| self.live_proxy(&room)) {
+            let _ = room_addr
+                .send(RoomMessage::JoinRequest(player_addr, player))
+                .await;
+            return;
+        }
+
+        if !self.accepting {
+            Self::send_rejection(&player_addr, RejectReason::ServerShuttingDown).await;
+            return;
+        }
+        if let Some(loading) = self.loading.get_mut(&room) {
+            loading.joins.push((player_addr, player));
+            return;
+        }
+
+        if let Some(cluster) = &self.cluster {
+            match cluster.acquire_lease(&room).await {
+                Ok(LeaseOwner::Own) => {}
+                Ok(LeaseOwner::Other(owner)) => {
+                    let proxy = cluster.start_proxy(room, owner);
+                    let remote = RemoteRoom {
+                        owner,
+                        proxy: proxy.clone(),
+                    };
+                    self.proxies.insert(room, remote);
+                    let _ = proxy
+                        .send(RoomMessage::JoinRequest(player_addr, player))
+                        .await;
+                    return;
+                }
+                Err(db_err) => {
+                    error!("Failed to acquire lease for room {}: {:?}", room, db_err);
+                    Self::send_rejection(&player_addr, RejectReason::JoinGameError).await;
+                    return;
+                }
+            }
+        }
+
+        self.restore_and_join(room, player_addr, player, server);
+    }
+
+    /// Join of a player connected to another instance
+    async fn join_forwarded(
+        &mut self,
+        room: RoomId,
+        player_addr: PlayerAddr,
+        player: PlayerInformation,
+        server: GameServerAddr,
+    ) {
+        if let Some(room_addr) = self.live_room(&room) {
+            let _ = room_addr
+                .send(RoomMessage::JoinRequest(player_addr, player))
+                .await;
+            return;
+        }
+
+        if !self.accepting {
+            Self::send_rejection(&player_addr, RejectReason::ServerShuttingDown).await;
+            return;
+        }
+        if let Some(loading) = self.loading.get_mut(&room) {
+            loading.joins.push((player_addr, player));
+            return;
+        }
+
+        if let Some(cluster) = &self.cluster {
+            match cluster.acquire_lease(&room).await {
+                Ok(LeaseOwner::Own) => self.restore_and_join(room, player_addr, player, server),
+                Ok(LeaseOwner::Other(owner)) => {
+                    warn!("{}: Got join for room owned by instance {}", room, owner);
+                    Self::send_rejection(&player_addr, RejectReason::JoinGameError).await;
+                }
+                Err(db_err) => {
+                    error!("Failed to acquire lease for room {}: {:?}", room, db_err);
+                    Self::send_rejection(&player_addr, RejectReason::JoinGameError).await;
+                }
+            }
+        }
+    }
+
+    /// Events of a room and the secrets of its webhooks
+    async fn restore_events(
+        repo: &RoomRepositoryRef,
+        secrets: &Option<WebhookSecretRepositoryRef>,
+        room: RoomId,
+    ) -> DbResult<(Vec<RoomEvent>, HashMap<String, String>)> {
+        let events = repo.get_room_events(&room).await?;
+        let secrets = match secrets {
+            Some(secrets) if !events.is_empty() => secrets.get_webhook_secrets(&room).await?,
+            _ => HashMap::new(),
+        };
+        Ok((events, secrets))
+    }
+
+    /// Start a room from its event log
+    async fn restore_room(
+        room: RoomId,
+        repo: RoomRepositoryRef,
+        secrets: Option<WebhookSecretRepositoryRef>,
+        webhooks: Webhooks,
+    ) -> Result<RoomAddr, RejectReason> {
+        let (events, secrets) = match Self::restore_events(&repo, &secrets, room).await {
+            Ok(restored) => restored,
+            Err(db_err) => {
+                error!("Failed to restore room {}: {:?}", room, db_err);
+                return Err(RejectReason::JoinGameError);
+            }
+        };
+        if events.is_empty() {
+            return Err(RejectReason::RoomDoesNotExist);
+        }
+        match Room::restore(room, events, repo) {
+            Some(restored_room) => Ok(restored_room
+                .with_webhooks(webhooks)
+                .with_webhook_secrets(secrets)
+                .start()),
+            None => {
+                error!("Failed to restore room {}", room);
+                Err(RejectReason::JoinGameError)
+            }
+        }
+    }
+
+    fn restore_and_join(
+        &mut self,
+        room: RoomId,
+        player_addr: PlayerAddr,
+        player: PlayerInformation,
+        server: GameServerAddr,
+    ) {
+        let loading = self.loading.entry(room).or_default();
+        loading.joins.push((player_addr, player));
+        self.restore(room, server);
+    }
+
+    /// Restore a room, joins and changes of the room wait until it is done
+    fn restore(&self, room: RoomId, server: GameServerAddr) {
+        let repo = self.room_repo.clone();
+        let secrets = self.webhook_secrets.clone();
+        let webhooks = self.webhooks.clone();
+        // read the event log outside of the actor to not block joins
+        <Self as Actor>::Context::spawn(async move {
+            let restored = Self::restore_room(room, repo, secrets, webhooks).await;
+            let _ = server
+                .send(GameServerMessage::Restored { room, restored })
+                .await;
+        });
+    }
+
+    /// Join the players that waited for a room to be restored
+    async fn restored(
+        &mut self,
+        room: RoomId,
+        restored: Result<RoomAddr, RejectReason>,
+        server: GameServerAddr,
+    ) {
+        let loading = self.loading.remove(&room).unwrap_or_default();
+        let room_addr = match restored {
+            Ok(room_addr) => room_addr,
+            Err(reason) => {
+                if let Some(cluster) = &self.cluster {
+                    cluster.release_lease(&room).await;
+                }
+                for (player_addr, _) in loading.joins {
+                    Self::send_rejection(&player_addr, reason).await;
+                }
+                for (_, reply) in loading.changes {
+                    let _ = reply.send(false);
+                }
+                return;
+            }
+        };
+
+        for (player_addr, player) in loading.joins {
+            let _ = room_addr
+                .send(RoomMessage::JoinRequest(player_addr, player))
+                .await;
+        }
+        self.rooms.insert(room, room_addr);
+        for (change, reply) in loading.changes {
+            self.change_room(room, change, reply, server.clone()).await;
+        }
+    }
+
+    async fn on_forwarded(
+        &mut self,
+        room: RoomId,
+        origin: Uuid,
+        request: RoomRequest,
+        server: GameServerAddr,
+    ) {
+        let msg = match request {
+            RoomRequest::Join { player } => {
+                if let Some(cluster) = &self.cluster {
+                    let player_addr = cluster.remote_player(origin, player.id.clone());
+                    self.join_forwarded(room, player_addr, player, server).await;
+                }
+                return;
+            }
+            RoomRequest::Left { player_id } => RoomMessage::PlayerLeft(player_id),
+            RoomRequest::Voted { player_id, vote } => RoomMessage::PlayerVoted(player_id, vote),
+            RoomRequest::UpdatePlayer { id, voter, name } => {
+                RoomMessage::UpdatePlayer { id, voter, name }
+            }
+            RoomRequest::ForceOpen => RoomMessage::ForceOpen,
+            RoomRequest::Restart => RoomMessage::Restart,
+        };
+
+        if let Some(room_addr) = self.live_room(&room) {
+            let _ = room_addr.send(msg).await;
+        } else {
+            warn!("{}: Dropped forwarded message for unknown room", room);
+        }
+    }
+
+    async fn renew_leases(&mut self) {
+        let Some(cluster) = &self.cluster else {
+            return;
+        };
+
+        let mut live = vec![];
+        let mut closed = vec![];
+        for (room_id, room) in &self.rooms {
+            if room.is_closed() {
+                closed.push(*room_id);
+            } else {
+                live.push(*room_id);
+            }
+        }
+
+        for room_id in closed {
+            self.rooms.remove(&room_id);
+            cluster.release_lease(&room_id).await;
+        }
+
+        // another instance took over, so players have to join there
+        for room_id in cluster.renew_leases(&live).await {
+            warn!("{}: Lost lease to another instance", room_id);
+            if let Some(room) = self.rooms.remove(&room_id) {
+                let _ = room.send(RoomMessage::HandOver).await;
+            }
+        }
+
+        // owners that died do not renew their leases
+        self.proxies.retain(|_, remote| !remote.proxy.is_closed());
+        let proxied: Vec<RoomId> = self.proxies.keys().copied().collect();
+        if let Some(owners) = cluster.lease_owners(&proxied).await {
+            for room_id in proxied {
+                let owner = self.proxies[&room_id].owner;
+                if owners.get(&room_id) != Some(&owner) {
+                    self.room_moved(room_id, owner).await;
+                }
+            }
+        }
+    }
+
+    /// Drop proxy of a room that `owner` does not host anymore
+    ///
+    /// The proxy is dropped first, so its players can join the room at its
+    /// new owner.
+    async fn room_moved(&mut self, room: RoomId, owner: Uuid) {
+        if self.proxies.get(&room).map(|remote| remote.owner) != Some(owner) {
+            return;
+        }
+        if let Some(remote) = self.proxies.remove(&room) {
+            let _ = remote.proxy.send(RoomMessage::HandOver).await;
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -50,63 +1062,797 @@ impl Actor for GameServer {
     type Message = GameServerMessage;
     type Context = Context<Self>;
 
-    async fn on_message(&mut self, msg: Self::Message, _ctx: &mut Context<Self>) {
+    async fn on_message(&mut self, msg: Self::Message, ctx: &mut Context<Self>) {
         match msg {
             GameServerMessage::Join {
                 room,
                 player_addr,
                 player,
+            } => self.join(room, player_addr, player, ctx.addr()).await,
+
+            GameServerMessage::Create { reply, .. } if !self.accepting => {
+                let _ = reply.send(Err(CreateRoomError::Unavailable));
+            }
+
+            GameServerMessage::Create { deck, slug, reply } => {
+                let _ = reply.send(self.create(deck, slug).await);
+            }
+
+            GameServerMessage::CreateAndJoin {
+                deck,
+                slug,
+                player_addr,
+                player,
+            } => self.create_and_join(deck, slug, player_addr, player).await,
+
+            GameServerMessage::ResolveRoom { room, reply } => {
+                let _ = reply.send(self.resolve_room(&room).await.ok());
+            }
+
+            GameServerMessage::GetRoomHistory { room, reply } => {
+                let room_addr = self.live_room(&room).or_else(|| self.live_proxy(&room));
+                let cluster = self.cluster.clone();
+                let repo = self.room_repo.clone();
+                <Self as Actor>::Context::spawn(async move {
+                    let history = match room_addr {
+                        Some(room_addr) => Self::query_history(room_addr).await,
+                        None => match Self::remote_host(cluster, room).await {
+                            Some((cluster, owner)) => cluster.room_history(owner, room).await,
+                            None => Self::stored_history(repo, room).await,
+                        },
+                    };
+                    let _ = reply.send(history);
+                });
+            }
+
+            GameServerMessage::GetLiveRoomIds { reply } => {
+                self.forget_closed_rooms();
+                let _ = reply.send(self.rooms.keys().copied().collect());
+            }
+
+            GameServerMessage::GetPersistedRounds { room, reply } => {
+                let repo = self.room_repo.clone();
+                // read the event log outside of the actor to not block joins
+                <Self as Actor>::Context::spawn(async move {
+                    let rounds = repo
+                        .get_room_events(&room)
+                        .await
+                        .map(Room::persisted_rounds);
+                    let _ = reply.send(rounds);
+                });
+            }
+
+            GameServerMessage::SetStories {
+                room,
+                stories,
+                reply,
             } => {
-                if let Some(room_addr) = self.rooms.get_mut(&room) {
-                    let result = room_addr
-                        .send(RoomMessage::JoinRequest(player_addr.clone(), player))
-                        .await;
-                    if result.is_err() {
-                        // room does not exist
-                        Self::send_rejection(&player_addr, RejectReason::RoomDoesNotExist).await;
-                        self.rooms.remove(&room);
-                    }
-                } else {
-                    match self.room_repo.get_room_events(&room).await {
-                        Ok(events) => {
-                            if events.is_empty() {
-                                Self::send_rejection(&player_addr, RejectReason::RoomDoesNotExist)
-                                    .await;
-                            } else if let Some(restored_room) =
-                                Room::restore(&room, events, self.room_repo.clone())
-                            {
-                                self.rooms.insert(room, restored_room.start());
-                            } else {
-                                error!("Failed to restore room {}", room);
-                                Self::send_rejection(&player_addr, RejectReason::JoinGameError)
-                                    .await;
-                            }
+                let change = RoomQuery::SetStories { stories };
+                self.change_room(room, change, reply, ctx.addr()).await
+            }
+
+            GameServerMessage::ListRooms { reply } => {
+                let rooms = self.live_rooms();
+                // query rooms outside of the actor to not block joins
+                <Self as Actor>::Context::spawn(async move {
+                    let mut infos = Vec::with_capacity(rooms.len());
+                    for room in rooms {
+                        if let Some(info) = Self::query_room(room).await {
+                            infos.push(info);
                         }
-                        Err(db_err) => {
-                            error!("Failed to restore room {}: {:?}", room, db_err);
-                            Self::send_rejection(&player_addr, RejectReason::JoinGameError).await;
+                    }
+                    let _ = reply.send(infos);
+                });
+            }
+
+            GameServerMessage::GetRoom { room, reply } => {
+                let room_addr = self.live_room(&room).or_else(|| self.live_proxy(&room));
+                let cluster = self.cluster.clone();
+                <Self as Actor>::Context::spawn(async move {
+                    let info = match room_addr {
+                        Some(room_addr) => Self::query_room(room_addr).await,
+                        None => match Self::remote_host(cluster, room).await {
+                            Some((cluster, owner)) => cluster.room_info(owner, room).await,
+                            None => None,
+                        },
+                    };
+                    let _ = reply.send(info);
+                });
+            }
+
+            GameServerMessage::CloseRoom { room, reply } => {
+                if self.live_room(&room).is_some() {
+                    let _ = reply.send(self.close_local_room(room, ctx.addr()).await);
+                    return;
+                }
+                // the owner closes the room, its players are connected there
+                let owner = self.live_proxy(&room).and_then(|_| self.proxies.get(&room));
+                let owner = owner.map(|remote| remote.owner);
+                let cluster = self.cluster.clone();
+                <Self as Actor>::Context::spawn(async move {
+                    let host = match (cluster, owner) {
+                        (Some(cluster), Some(owner)) => Some((cluster, owner)),
+                        (cluster, None) => Self::remote_host(cluster, room).await,
+                        (None, Some(_)) => None,
+                    };
+                    let closed = match host {
+                        Some((cluster, owner)) => {
+                            cluster.change_room(owner, room, RoomQuery::Close).await
                         }
+                        None => false,
+                    };
+                    let _ = reply.send(closed);
+                });
+            }
+
+            GameServerMessage::Subscribe {
+                room,
+                webhook,
+                reply,
+            } => {
+                let change = RoomQuery::Subscribe {
+                    url: webhook.url,
+                    secret: webhook.secret,
+                };
+                self.change_room(room, change, reply, ctx.addr()).await
+            }
+
+            GameServerMessage::GetWebhooks { room, reply } => {
+                let room_addr = self.live_room(&room).or_else(|| self.live_proxy(&room));
+                let cluster = self.cluster.clone();
+                let repo = self.room_repo.clone();
+                let secrets = self.webhook_secrets.clone();
+                <Self as Actor>::Context::spawn(async move {
+                    let webhooks = match room_addr {
+                        Some(room_addr) => Self::query_room(room_addr).await.map(|i| i.webhooks),
+                        None => match Self::remote_host(cluster, room).await {
+                            Some((cluster, owner)) => {
+                                cluster.room_info(owner, room).await.map(|i| i.webhooks)
+                            }
+                            None => Self::stored_webhooks(repo, secrets, room).await,
+                        },
+                    };
+                    let _ = reply.send(webhooks);
+                });
+            }
+
+            GameServerMessage::Unsubscribe { room, reply } => {
+                let change = RoomQuery::Unsubscribe;
+                self.change_room(room, change, reply, ctx.addr()).await
+            }
+
+            GameServerMessage::Broadcast { message, reply } => {
+                let mut reached = 0;
+                self.proxies.retain(|_, remote| !remote.proxy.is_closed());
+                let proxies: Vec<RoomAddr> = self
+                    .proxies
+                    .values()
+                    .map(|remote| remote.proxy.clone())
+                    .collect();
+                for room in self.live_rooms().into_iter().chain(proxies) {
+                    if room
+                        .send(RoomMessage::Notice(message.clone()))
+                        .await
+                        .is_ok()
+                    {
+                        reached += 1;
                     }
                 }
+                info!("Broadcasted notice to {} rooms: {}", reached, message);
+                let _ = reply.send(reached);
+            }
+
+            GameServerMessage::StopAccepting => {
+                info!("Stop accepting new rooms");
+                self.accepting = false;
             }
 
-            GameServerMessage::Create { deck, reply } => {
-                let room_id = Room::gen_id();
-                let room = Room::new(&room_id, deck, self.room_repo.clone()).await;
-                match room {
-                    Ok(room) => {
-                        self.rooms.insert(room_id.clone(), room.start());
-                        let _ = reply.send(Some(room_id));
+            GameServerMessage::Shutdown { reply } => {
+                self.accepting = false;
+                // rooms persist their pending events before they quit
+                for (_, remote) in self.proxies.drain() {
+                    let _ = remote.proxy.send(RoomMessage::Shutdown).await;
+                }
+                for (_, loading) in self.loading.drain() {
+                    for (player_addr, _) in loading.joins {
+                        Self::send_rejection(&player_addr, RejectReason::ServerShuttingDown).await;
+                    }
+                }
+                for (room_id, room) in self.rooms.drain() {
+                    if room.send(RoomMessage::Shutdown).await.is_ok() {
+                        room.closed().await;
+                    } else {
+                        warn!("{}: Room was already closed", room_id);
                     }
-                    Err(err) => {
-                        warn!(
-                            "Unable to create room {}, because of database error: {}",
-                            room_id, err
-                        );
-                        let _ = reply.send(None);
+                    if let Some(cluster) = &self.cluster {
+                        cluster.release_lease(&room_id).await;
                     }
                 }
+                info!("All rooms are shut down");
+                ctx.force_quit();
+                let _ = reply.send(());
+            }
+
+            GameServerMessage::Forwarded {
+                room,
+                origin,
+                request,
+            } => self.on_forwarded(room, origin, request, ctx.addr()).await,
+
+            GameServerMessage::Queried {
+                room,
+                query: RoomQuery::Close,
+                reply,
+            } => {
+                let done = self.close_local_room(room, ctx.addr()).await;
+                let _ = reply.send(QueryAnswer::Done { done });
+            }
+
+            GameServerMessage::Queried { room, query, reply } => {
+                // only rooms hosted here, to not pass the query on
+                let room_addr = self.live_room(&room);
+                let repo = self.room_repo.clone();
+                let secrets = self.webhook_secrets.clone();
+                <Self as Actor>::Context::spawn(async move {
+                    let answer = match (query, room_addr) {
+                        (RoomQuery::GetInfo, Some(room_addr)) => QueryAnswer::Info {
+                            info: Self::query_room(room_addr).await.map(Box::new),
+                        },
+                        (RoomQuery::GetInfo, None) => QueryAnswer::Info { info: None },
+                        (RoomQuery::GetHistory, Some(room_addr)) => QueryAnswer::History {
+                            rounds: Self::query_history(room_addr).await,
+                        },
+                        (RoomQuery::GetHistory, None) => QueryAnswer::History {
+                            rounds: Self::stored_history(repo, room).await,
+                        },
+                        (change, Some(room_addr)) => QueryAnswer::Done {
+                            done: Self::change_local_room(room, room_addr, change, secrets).await,
+                        },
+                        (_, None) => QueryAnswer::Done { done: false },
+                    };
+                    let _ = reply.send(answer);
+                });
+            }
+
+            GameServerMessage::RenewLeases => self.renew_leases().await,
+
+            GameServerMessage::RoomMoved { room, owner } => self.room_moved(room, owner).await,
+
+            GameServerMessage::Restored { room, restored } => {
+                self.restored(room, restored, ctx.addr()).await
+            }
+
+            GameServerMessage::EventLogWritten { room } => {
+                self.event_log_written(room, ctx.addr()).await
+            }
+        }
+    }
+
+    async fn setup(&mut self, ctx: &mut Context<Self>) {
+        let Some(cluster) = self.cluster.clone() else {
+            return;
+        };
+
+        let addr = ctx.addr();
+        let mut ticker = interval(cluster.lease_ttl() / 3);
+        <Self as Actor>::Context::spawn(async move {
+            loop {
+                ticker.tick().await;
+                if addr.send(GameServerMessage::RenewLeases).await.is_err() {
+                    break;
+                }
             }
+        });
+
+        <Self as Actor>::Context::spawn(cluster.run(ctx.addr()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::adapters::memory::{MemoryMessageBus, MemoryRepository};
+    use crate::ports::{DbResult, RoomAliasRepository, RoomLeaseRepository, RoomRepository};
+
+    use super::*;
+
+    /// Repository that must not be reached
+    struct UnreachableRepository;
+
+    #[async_trait::async_trait]
+    impl RoomRepository for UnreachableRepository {
+        async fn append_room_event(&self, id: &RoomId, _evt: RoomEvent) -> DbResult<()> {
+            panic!("unexpected append for room {id}")
+        }
+
+        async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+            panic!("unexpected query for room {id}")
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl RoomAliasRepository for UnreachableRepository {
+        async fn create_room_alias(&self, alias: &str, _room_id: &RoomId) -> DbResult<bool> {
+            panic!("unexpected alias {alias}")
+        }
+
+        async fn resolve_room_alias(&self, alias: &str) -> DbResult<Option<RoomId>> {
+            panic!("unexpected alias {alias}")
+        }
+
+        async fn delete_room_aliases(&self, room_id: &RoomId) -> DbResult<()> {
+            panic!("unexpected alias deletion for room {room_id}")
+        }
+    }
+
+    /// Repository that accepts everything and remembers nothing
+    struct NullRepository;
+
+    #[async_trait::async_trait]
+    impl RoomRepository for NullRepository {
+        async fn append_room_event(&self, _id: &RoomId, _evt: RoomEvent) -> DbResult<()> {
+            Ok(())
+        }
+
+        async fn get_room_events(&self, _id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+            Ok(vec![])
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl RoomAliasRepository for NullRepository {
+        async fn create_room_alias(&self, _alias: &str, _room_id: &RoomId) -> DbResult<bool> {
+            Ok(true)
+        }
+
+        async fn resolve_room_alias(&self, _alias: &str) -> DbResult<Option<RoomId>> {
+            Ok(None)
+        }
+
+        async fn delete_room_aliases(&self, _room_id: &RoomId) -> DbResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Repository failing every write, remembers the rooms it was asked for
+    #[derive(Default)]
+    struct FailingRepository {
+        rooms: Mutex<Vec<RoomId>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RoomRepository for FailingRepository {
+        async fn append_room_event(&self, id: &RoomId, _evt: RoomEvent) -> DbResult<()> {
+            self.rooms.lock().unwrap().push(*id);
+            Err(io::Error::from(io::ErrorKind::InvalidData).into())
+        }
+
+        async fn get_room_events(&self, _id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+            Ok(vec![])
+        }
+    }
+
+    fn player_info() -> PlayerInformation {
+        PlayerInformation {
+            id: "1".to_string(),
+            voter: true,
+            name: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn create_and_join_creator() {
+        let repo = Arc::new(NullRepository);
+        let game_server = GameServer::new(repo.clone(), repo).start();
+        let (player_addr, mut rx) = mpsc::channel(16);
+
+        // ACT
+        game_server
+            .send(GameServerMessage::CreateAndJoin {
+                deck: "TEST-DECK".to_string(),
+                slug: Some("Sprint 42".to_string()),
+                player_addr,
+                player: player_info(),
+            })
+            .await
+            .unwrap();
+
+        // ASSERT
+        let Some(GamePlayerMessage::RoomCreated(created)) = rx.recv().await else {
+            panic!("room was not created");
+        };
+        assert_eq!(created.slug.as_deref(), Some("sprint-42"));
+        assert!(created.code.is_some());
+        match rx.recv().await {
+            Some(GamePlayerMessage::Welcome(room_id, ..)) => assert_eq!(room_id, created.id),
+            other => panic!("creator was not welcomed: {:?}", other),
+        }
+    }
+
+    async fn create(
+        game_server: &GameServerAddr,
+        slug: &str,
+    ) -> Result<CreatedRoom, CreateRoomError> {
+        let (reply, created) = oneshot::channel();
+        let msg = GameServerMessage::Create {
+            deck: "TEST-DECK".to_string(),
+            slug: Some(slug.to_string()),
+            reply,
+        };
+        game_server.send(msg).await.unwrap();
+        created.await.unwrap()
+    }
+
+    async fn resolve(game_server: &GameServerAddr, room: &str) -> Option<RoomId> {
+        let (reply, room_id) = oneshot::channel();
+        let msg = GameServerMessage::ResolveRoom {
+            room: room.to_string(),
+            reply,
+        };
+        game_server.send(msg).await.unwrap();
+        room_id.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reject_taken_slug() {
+        let repo = Arc::new(MemoryRepository::default());
+        let game_server = GameServer::new(repo.clone(), repo).start();
+        let first = create(&game_server, "Sprint 42").await.unwrap();
+
+        // ACT
+        let second = create(&game_server, "sprint-42").await;
+
+        // ASSERT
+        assert_eq!(second.unwrap_err(), CreateRoomError::SlugTaken);
+        assert_eq!(resolve(&game_server, "sprint-42").await, Some(first.id));
+    }
+
+    #[tokio::test]
+    async fn resolve_room_by_code_and_slug() {
+        let repo = Arc::new(MemoryRepository::default());
+        let game_server = GameServer::new(repo.clone(), repo.clone()).start();
+        let created = create(&game_server, "Sprint 42").await.unwrap();
+        let code = created.code.clone().unwrap();
+        // knows the aliases only from the repository
+        let other_server = GameServer::new(repo.clone(), repo).start();
+
+        for game_server in [&game_server, &other_server] {
+            // ACT
+            let by_slug = resolve(game_server, "sprint-42").await;
+            let by_code = resolve(game_server, &code).await;
+            let by_id = resolve(game_server, &created.id.to_string()).await;
+            let unknown = resolve(game_server, "sprint-43").await;
+
+            // ASSERT
+            assert_eq!(by_slug, Some(created.id));
+            assert_eq!(by_code, Some(created.id));
+            assert_eq!(by_id, Some(created.id));
+            assert_eq!(unknown, None);
+        }
+    }
+
+    async fn history(game_server: &GameServerAddr, room: RoomId) -> Option<Vec<Round>> {
+        let (reply, rounds) = oneshot::channel();
+        let msg = GameServerMessage::GetRoomHistory { room, reply };
+        game_server.send(msg).await.unwrap();
+        rounds.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn rebuild_history_of_room_that_is_not_live() {
+        let repo = Arc::new(MemoryRepository::default());
+        let game_server = GameServer::new(repo.clone(), repo.clone()).start();
+        let created = create(&game_server, "Sprint 42").await.unwrap();
+        let (player_addr, mut rx) = mpsc::channel(16);
+        let msg = GameServerMessage::Join {
+            room: created.id.to_string(),
+            player_addr,
+            player: player_info(),
+        };
+        game_server.send(msg).await.unwrap();
+        let room_addr = loop {
+            match rx.recv().await {
+                Some(GamePlayerMessage::Welcome(_, room_addr, ..)) => break room_addr,
+                Some(_) => {}
+                None => panic!("player was not welcomed"),
+            }
+        };
+        let vote = RoomMessage::PlayerVoted("1".to_string(), Some("5".to_string()));
+        room_addr.send(vote).await.unwrap();
+        room_addr.send(RoomMessage::ForceOpen).await.unwrap();
+        let live_history = history(&game_server, created.id).await.unwrap();
+        // knows the room only from the repository
+        let other_server = GameServer::new(repo.clone(), repo).start();
+
+        // ACT
+        let restored_history = history(&other_server, created.id).await;
+        let unknown = history(&other_server, RoomId::generate()).await;
+
+        // ASSERT
+        assert_eq!(live_history.len(), 1);
+        assert_eq!(restored_history, Some(live_history));
+        assert_eq!(unknown, None);
+    }
+
+    async fn welcomed(rx: &mut mpsc::Receiver<GamePlayerMessage>) -> RoomAddr {
+        loop {
+            match rx.recv().await {
+                Some(GamePlayerMessage::Welcome(_, room_addr, ..)) => return room_addr,
+                Some(_) => {}
+                None => panic!("player was not welcomed"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_room_once_for_players_joining_meanwhile() {
+        let repo = Arc::new(MemoryRepository::default());
+        let game_server = GameServer::new(repo.clone(), repo.clone()).start();
+        let created = create(&game_server, "Sprint 42").await.unwrap();
+        // knows the room only from the repository
+        let other_server = GameServer::new(repo.clone(), repo).start();
+        let (first_addr, mut first) = mpsc::channel(16);
+        let (second_addr, mut second) = mpsc::channel(16);
+
+        // ACT
+        for (player_addr, id) in [(first_addr, "1"), (second_addr, "2")] {
+            let msg = GameServerMessage::Join {
+                room: created.id.to_string(),
+                player_addr,
+                player: PlayerInformation {
+                    id: id.to_string(),
+                    ..player_info()
+                },
+            };
+            other_server.send(msg).await.unwrap();
+        }
+
+        // ASSERT
+        let first_room = welcomed(&mut first).await;
+        let second_room = welcomed(&mut second).await;
+        assert!(first_room.same_channel(&second_room));
+    }
+
+    async fn set_stories(game_server: &GameServerAddr, room: RoomId, stories: Vec<Story>) -> bool {
+        let (reply, set) = oneshot::channel();
+        let msg = GameServerMessage::SetStories {
+            room,
+            stories,
+            reply,
+        };
+        game_server.send(msg).await.unwrap();
+        set.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn import_stories_into_room_that_is_not_live() {
+        let repo = Arc::new(MemoryRepository::default());
+        let game_server = GameServer::new(repo.clone(), repo.clone()).start();
+        let created = create(&game_server, "Sprint 42").await.unwrap();
+        // knows the room only from the repository
+        let other_server = GameServer::new(repo.clone(), repo).start();
+        let stories = vec![Story {
+            key: Some("GOE-1".to_string()),
+            title: "Login".to_string(),
+            link: None,
+            description: None,
+        }];
+
+        // ACT
+        let imported = set_stories(&other_server, created.id, stories.clone()).await;
+        let unknown = set_stories(&other_server, RoomId::generate(), stories.clone()).await;
+        let (player_addr, mut rx) = mpsc::channel(16);
+        let msg = GameServerMessage::Join {
+            room: created.id.to_string(),
+            player_addr,
+            player: player_info(),
+        };
+        other_server.send(msg).await.unwrap();
+
+        // ASSERT
+        assert!(imported);
+        assert!(!unknown);
+        loop {
+            match rx.recv().await {
+                Some(GamePlayerMessage::StoriesChanged(restored)) => {
+                    assert_eq!(restored, stories);
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("stories were not sent"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_room_events_to_webhooks() {
+        let repo = Arc::new(MemoryRepository::default());
+        let (webhooks, mut events) = Webhooks::recording();
+        let game_server = GameServer::new(repo.clone(), repo.clone())
+            .with_webhooks(webhooks)
+            .with_webhook_secrets(repo)
+            .start();
+        let webhook = Webhook {
+            url: "http://localhost/hook".to_string(),
+            secret: Some("s3cr3t".to_string()),
+        };
+
+        // ACT
+        let created = create(&game_server, "Sprint 42").await.unwrap();
+        let (reply, subscribed) = oneshot::channel();
+        let msg = GameServerMessage::Subscribe {
+            room: created.id,
+            webhook: webhook.clone(),
+            reply,
+        };
+        game_server.send(msg).await.unwrap();
+        assert!(subscribed.await.unwrap());
+        let (player_addr, mut rx) = mpsc::channel(16);
+        let msg = GameServerMessage::Join {
+            room: created.id.to_string(),
+            player_addr,
+            player: player_info(),
+        };
+        game_server.send(msg).await.unwrap();
+        let room_addr = loop {
+            match rx.recv().await {
+                Some(GamePlayerMessage::Welcome(_, room_addr, ..)) => break room_addr,
+                Some(_) => {}
+                None => panic!("player was not welcomed"),
+            }
+        };
+        let vote = RoomMessage::PlayerVoted("1".to_string(), Some("5".to_string()));
+        room_addr.send(vote).await.unwrap();
+
+        // ASSERT
+        let (room, subscriptions, event) = events.recv().await.unwrap();
+        assert_eq!(room, created.id);
+        assert!(subscriptions.is_empty());
+        match event {
+            WebhookEvent::RoomCreated { deck, code, slug } => {
+                assert_eq!(deck, "TEST-DECK");
+                assert_eq!(code, created.code);
+                assert_eq!(slug.as_deref(), Some("sprint-42"));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        let (room, subscriptions, event) = events.recv().await.unwrap();
+        assert_eq!(room, created.id);
+        assert_eq!(subscriptions, [webhook]);
+        match event {
+            WebhookEvent::RoundRevealed { round, stats } => {
+                assert_eq!(round.number(), 1);
+                assert_eq!(stats.votes, 1);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_webhooks_of_room_that_is_not_live() {
+        let repo = Arc::new(MemoryRepository::default());
+        let game_server = GameServer::new(repo.clone(), repo.clone()).start();
+        let created = create(&game_server, "Sprint 42").await.unwrap();
+        // knows the room only from the repository
+        let other_server = GameServer::new(repo.clone(), repo.clone())
+            .with_webhook_secrets(repo.clone())
+            .start();
+        let webhook = Webhook {
+            url: "http://localhost/hook".to_string(),
+            secret: Some("s3cr3t".to_string()),
+        };
+
+        // ACT
+        let (reply, subscribed) = oneshot::channel();
+        let msg = GameServerMessage::Subscribe {
+            room: created.id,
+            webhook,
+            reply,
+        };
+        other_server.send(msg).await.unwrap();
+        let subscribed = subscribed.await.unwrap();
+        let (reply, webhooks) = oneshot::channel();
+        let msg = GameServerMessage::GetWebhooks {
+            room: created.id,
+            reply,
+        };
+        other_server.send(msg).await.unwrap();
+        let webhooks = webhooks.await.unwrap();
+
+        // ASSERT
+        assert!(subscribed);
+        let expected = Subscription {
+            url: "http://localhost/hook".to_string(),
+            signed: true,
+        };
+        assert_eq!(webhooks, Some(vec![expected]));
+        let events = repo.get_room_events(&created.id).await.unwrap();
+        assert!(!format!("{:?}", events).contains("s3cr3t"));
+    }
+
+    #[tokio::test]
+    async fn release_lease_and_aliases_when_creation_fails() {
+        let repo = Arc::new(MemoryRepository::default());
+        let failing = Arc::new(FailingRepository::default());
+        let cluster = Cluster::new(
+            repo.clone(),
+            Arc::new(MemoryMessageBus::default()),
+            Duration::from_secs(30),
+        );
+        let game_server = GameServer::new(failing.clone(), repo.clone())
+            .with_cluster(cluster)
+            .start();
+
+        // ACT
+        let created = create(&game_server, "Sprint 42").await;
+
+        // ASSERT
+        assert_eq!(created.unwrap_err(), CreateRoomError::Unavailable);
+        let room_id = failing.rooms.lock().unwrap()[0];
+        assert!(repo.room_lease_owners(&[room_id]).await.unwrap().is_empty());
+        assert_eq!(repo.resolve_room_alias("sprint-42").await.unwrap(), None);
+        assert_eq!(resolve(&game_server, "sprint-42").await, None);
+    }
+
+    #[tokio::test]
+    async fn reject_creation_with_invalid_slug() {
+        let repo = Arc::new(NullRepository);
+        let game_server = GameServer::new(repo.clone(), repo).start();
+        let (player_addr, mut rx) = mpsc::channel(16);
+
+        // ACT
+        game_server
+            .send(GameServerMessage::CreateAndJoin {
+                deck: "TEST-DECK".to_string(),
+                slug: Some("--".to_string()),
+                player_addr,
+                player: player_info(),
+            })
+            .await
+            .unwrap();
+
+        // ASSERT
+        assert!(matches!(
+            rx.recv().await,
+            Some(GamePlayerMessage::Rejected(RejectReason::InvalidSlug))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_malformed_room_ids() {
+        let repo = Arc::new(UnreachableRepository);
+        let game_server = GameServer::new(repo.clone(), repo).start();
+
+        for room in [
+            "",
+            "garbage!",
+            "AAAA",
+            "AAAAAAAAAAAAAAAAAAAAAA==",
+            "Room 42",
+        ] {
+            let (player_addr, mut rx) = mpsc::channel(16);
+
+            // ACT
+            game_server
+                .send(GameServerMessage::Join {
+                    room: room.to_string(),
+                    player_addr,
+                    player: player_info(),
+                })
+                .await
+                .unwrap();
+
+            // ASSERT
+            assert!(
+                matches!(
+                    rx.recv().await,
+                    Some(GamePlayerMessage::Rejected(RejectReason::RoomDoesNotExist))
+                ),
+                "{room:?} was not rejected"
+            );
         }
     }
 }
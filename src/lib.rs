@@ -41,9 +41,21 @@ macro_rules! assert_no_message {
     };
 }
 
+pub mod cluster;
+pub mod export;
 pub mod game_server;
+pub mod long_poll;
+pub mod persistence;
 pub mod player;
+pub mod rate_limit;
+pub mod retention;
+pub mod retry;
 pub mod room;
+pub mod room_alias;
+pub mod room_id;
+pub mod story;
+pub mod testing;
+pub mod webhook;
 
 pub mod adapters;
 pub mod ports;
@@ -0,0 +1,520 @@
+//! HTTP long-polling transport for clients without WebSockets
+//!
+//! A client opens a session and gets a secret session ID. It fetches
+//! messages with long-running poll requests, which acknowledge all messages
+//! up to a sequence number. Unacknowledged messages are sent again, so a
+//! lost response does not lose messages. Messages of the client carry
+//! sequence numbers too, so retried requests are not processed twice.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, warn};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::remote::{CloseReason, ConnResult, RemoteMessage, Transport};
+
+/// Session is closed when the client did not poll for this long
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Session is closed when the client did not poll within this time after
+/// opening it, so sessions nobody uses do not take up the limit
+pub const FIRST_POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Closed sessions are kept for this long to deliver the close reason
+const CLOSED_SESSION_RETENTION: Duration = Duration::from_secs(30);
+
+/// Messages the server keeps for a client that does not acknowledge them,
+/// the session is closed when there are more
+const MAX_UNACKED: usize = 256;
+
+/// Open sessions, including closed ones kept for their close reason
+pub const MAX_SESSIONS: usize = 1000;
+
+/// Open sessions of one client address, so a single client can not take
+/// up all sessions
+pub const MAX_SESSIONS_PER_CLIENT: usize = 100;
+
+/// Message with its sequence number
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub seq: u64,
+    pub message: T,
+}
+
+/// Response to a poll request
+#[derive(Debug, Serialize)]
+pub struct Poll {
+    pub messages: Vec<Envelope<serde_json::Value>>,
+    /// Reason when the server closed the session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<&'static str>,
+}
+
+#[derive(Default)]
+struct Outbox {
+    next_seq: u64,
+    unacked: VecDeque<Envelope<serde_json::Value>>,
+    closed: Option<CloseReason>,
+}
+
+struct Session {
+    outbox: Mutex<Outbox>,
+    outbox_changed: Notify,
+    inbox: mpsc::Sender<RemoteMessage>,
+    /// Sequence number of the last message received from the client
+    received: tokio::sync::Mutex<u64>,
+    last_seen: Mutex<Instant>,
+    opened_at: Instant,
+    polled: AtomicBool,
+    client: IpAddr,
+}
+
+impl Session {
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    fn is_expired(&self) -> bool {
+        if !self.polled.load(Ordering::Relaxed) {
+            return self.opened_at.elapsed() > FIRST_POLL_TIMEOUT;
+        }
+        self.last_seen.lock().unwrap().elapsed() > SESSION_TIMEOUT
+    }
+
+    /// Messages after `ack`, or `None` if there are none yet
+    fn take_after(&self, ack: u64) -> Option<Poll> {
+        let mut outbox = self.outbox.lock().unwrap();
+        while outbox.unacked.front().is_some_and(|env| env.seq <= ack) {
+            outbox.unacked.pop_front();
+        }
+        if outbox.unacked.is_empty() && outbox.closed.is_none() {
+            return None;
+        }
+
+        Some(Poll {
+            messages: outbox
+                .unacked
+                .iter()
+                .map(|env| Envelope {
+                    seq: env.seq,
+                    message: env.message.clone(),
+                })
+                .collect(),
+            closed: outbox.closed.map(|reason| reason.message()),
+        })
+    }
+}
+
+/// Open long-polling sessions
+#[derive(Clone, Default)]
+pub struct LongPollSessions {
+    sessions: Arc<Mutex<HashMap<String, Arc<Session>>>>,
+}
+
+impl LongPollSessions {
+    fn gen_id() -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect::<String>()
+    }
+
+    /// Open a session of `client`, returns its ID and the transport for the
+    /// player
+    ///
+    /// Returns `None` when there are already `MAX_SESSIONS` sessions or
+    /// `MAX_SESSIONS_PER_CLIENT` sessions of the client. Expired sessions do
+    /// not count, even if their players did not notice yet.
+    pub fn open(
+        &self,
+        protocol: Option<u32>,
+        client: IpAddr,
+    ) -> Option<(String, LongPollTransport)> {
+        let id = Self::gen_id();
+        let (tx, rx) = mpsc::channel(16);
+        let session = Arc::new(Session {
+            outbox: Default::default(),
+            outbox_changed: Notify::new(),
+            inbox: tx,
+            received: tokio::sync::Mutex::new(0),
+            last_seen: Mutex::new(Instant::now()),
+            opened_at: Instant::now(),
+            polled: AtomicBool::new(false),
+            client,
+        });
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            let of_client = |sessions: &HashMap<String, Arc<Session>>| {
+                let sessions = sessions.values();
+                sessions.filter(|session| session.client == client).count()
+            };
+            if sessions.len() >= MAX_SESSIONS || of_client(&sessions) >= MAX_SESSIONS_PER_CLIENT {
+                sessions.retain(|_, session| !session.is_expired());
+            }
+            if sessions.len() >= MAX_SESSIONS {
+                warn!("Rejected long-polling session, too many sessions");
+                return None;
+            }
+            if of_client(&sessions) >= MAX_SESSIONS_PER_CLIENT {
+                warn!(
+                    "Rejected long-polling session, too many sessions of {}",
+                    client
+                );
+                return None;
+            }
+            sessions.insert(id.clone(), session.clone());
+        }
+
+        let transport = LongPollTransport {
+            id: id.clone(),
+            sessions: self.clone(),
+            session,
+            inbox: rx,
+            protocol,
+        };
+        Some((id, transport))
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<Session>> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    /// Wait up to `timeout` for messages after `ack`
+    ///
+    /// Returns `None` for unknown sessions.
+    pub async fn poll(&self, id: &str, ack: u64, timeout: Duration) -> Option<Poll> {
+        let session = self.get(id)?;
+        session.touch();
+        session.polled.store(true, Ordering::Relaxed);
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let changed = session.outbox_changed.notified();
+            tokio::pin!(changed);
+            // register before checking to not miss a notification
+            changed.as_mut().enable();
+
+            if let Some(poll) = session.take_after(ack) {
+                return Some(poll);
+            }
+            tokio::select! {
+                _ = changed => {}
+                _ = tokio::time::sleep_until(deadline) => {
+                    return Some(Poll {
+                        messages: vec![],
+                        closed: None,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Pass messages of the client to its player
+    ///
+    /// Skips messages that were already received and stops at gaps.
+    /// Returns the sequence number of the last received message or `None`
+    /// for unknown sessions.
+    pub async fn deliver(&self, id: &str, messages: Vec<Envelope<RemoteMessage>>) -> Option<u64> {
+        let session = self.get(id)?;
+        session.touch();
+
+        // keeps concurrent requests of a session in order
+        let mut received = session.received.lock().await;
+        for envelope in messages {
+            if envelope.seq <= *received {
+                continue;
+            }
+            if envelope.seq != *received + 1 {
+                debug!("{}: Gap in long-polling messages", id);
+                break;
+            }
+            if session.inbox.send(envelope.message).await.is_err() {
+                break;
+            }
+            *received = envelope.seq;
+        }
+        Some(*received)
+    }
+
+    /// Client ends the session
+    pub async fn close(&self, id: &str) -> bool {
+        let Some(session) = self.get(id) else {
+            return false;
+        };
+        let _ = session.inbox.send(RemoteMessage::Close).await;
+        true
+    }
+}
+
+/// Transport of a long-polling session, removes the session when dropped
+pub struct LongPollTransport {
+    id: String,
+    sessions: LongPollSessions,
+    session: Arc<Session>,
+    inbox: mpsc::Receiver<RemoteMessage>,
+    protocol: Option<u32>,
+}
+
+#[async_trait::async_trait]
+impl Transport for LongPollTransport {
+    fn protocol(&self) -> Option<u32> {
+        self.protocol
+    }
+
+    async fn send(&mut self, message: RemoteMessage) -> ConnResult<()> {
+        let message = serde_json::to_value(&message)?;
+        {
+            let mut outbox = self.session.outbox.lock().unwrap();
+            if outbox.closed.is_some() {
+                return Ok(());
+            }
+            if outbox.unacked.len() >= MAX_UNACKED {
+                // dropping messages would leave the client in a wrong state,
+                // so it has to reconnect and gets the current state
+                warn!("{}: Too many unacknowledged messages", self.id);
+                outbox.closed = Some(CloseReason::Lagging);
+                drop(outbox);
+                self.session.outbox_changed.notify_waiters();
+                return Ok(());
+            }
+            outbox.next_seq += 1;
+            let seq = outbox.next_seq;
+            outbox.unacked.push_back(Envelope { seq, message });
+        }
+        self.session.outbox_changed.notify_waiters();
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> ConnResult<RemoteMessage> {
+        if self.session.outbox.lock().unwrap().closed.is_some() {
+            return Ok(RemoteMessage::Close);
+        }
+        loop {
+            tokio::select! {
+                msg = self.inbox.recv() => return Ok(msg.unwrap_or(RemoteMessage::Close)),
+                _ = sleep(FIRST_POLL_TIMEOUT / 2) => {
+                    if self.session.is_expired() {
+                        debug!("{}: Long-polling session expired", self.id);
+                        return Ok(RemoteMessage::Close);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn ping(&mut self) -> ConnResult<()> {
+        // every poll request shows that the client is alive
+        Ok(())
+    }
+
+    async fn close(&mut self, reason: CloseReason) -> ConnResult<()> {
+        self.session.outbox.lock().unwrap().closed = Some(reason);
+        self.session.outbox_changed.notify_waiters();
+        Ok(())
+    }
+}
+
+impl Drop for LongPollTransport {
+    fn drop(&mut self) {
+        let closed = self.session.outbox.lock().unwrap().closed.is_some();
+        let sessions = self.sessions.clone();
+        let id = std::mem::take(&mut self.id);
+        if closed {
+            // keep session for a while, so the client learns why it was closed
+            tokio::spawn(async move {
+                sleep(CLOSED_SESSION_RETENTION).await;
+                sessions.sessions.lock().unwrap().remove(&id);
+            });
+        } else {
+            sessions.sessions.lock().unwrap().remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_WAIT: Duration = Duration::ZERO;
+
+    /// Address of the `n`th client
+    fn client(n: usize) -> IpAddr {
+        IpAddr::from([10, 0, (n / 256) as u8, (n % 256) as u8])
+    }
+
+    fn envelope(seq: u64, vote: &str) -> Envelope<RemoteMessage> {
+        Envelope {
+            seq,
+            message: RemoteMessage::Vote {
+                vote: Some(vote.to_string()),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn resend_until_acknowledged() {
+        let sessions = LongPollSessions::default();
+        let (id, mut transport) = sessions.open(Some(2), client(0)).unwrap();
+
+        transport.send(RemoteMessage::ForceOpen).await.unwrap();
+        transport.send(RemoteMessage::Restart).await.unwrap();
+
+        let poll = sessions.poll(&id, 0, NO_WAIT).await.unwrap();
+        assert_eq!(poll.messages.len(), 2);
+        let poll = sessions.poll(&id, 0, NO_WAIT).await.unwrap();
+        assert_eq!(poll.messages.len(), 2);
+        let poll = sessions.poll(&id, 1, NO_WAIT).await.unwrap();
+        assert_eq!(poll.messages.len(), 1);
+        assert_eq!(poll.messages[0].seq, 2);
+        let poll = sessions.poll(&id, 2, NO_WAIT).await.unwrap();
+        assert!(poll.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn wake_up_waiting_poll() {
+        let sessions = LongPollSessions::default();
+        let (id, mut transport) = sessions.open(Some(2), client(0)).unwrap();
+
+        let poll = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { sessions.poll(&id, 0, Duration::from_secs(10)).await }
+        });
+        tokio::task::yield_now().await;
+        transport.send(RemoteMessage::ForceOpen).await.unwrap();
+
+        let poll = poll.await.unwrap().unwrap();
+        assert_eq!(poll.messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deliver_client_messages_once() {
+        let sessions = LongPollSessions::default();
+        let (id, mut transport) = sessions.open(Some(2), client(0)).unwrap();
+
+        let ack = sessions.deliver(&id, vec![envelope(1, "1"), envelope(2, "2")]);
+        assert_eq!(ack.await, Some(2));
+        // retry of a request that got lost and a gap
+        let ack = sessions.deliver(
+            &id,
+            vec![envelope(2, "2"), envelope(3, "3"), envelope(5, "5")],
+        );
+        assert_eq!(ack.await, Some(3));
+
+        for vote in ["1", "2", "3"] {
+            assert_eq!(
+                transport.recv().await.unwrap(),
+                RemoteMessage::Vote {
+                    vote: Some(vote.to_string())
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn remove_session_with_transport() {
+        let sessions = LongPollSessions::default();
+        let (id, transport) = sessions.open(Some(2), client(0)).unwrap();
+
+        drop(transport);
+        assert!(sessions.poll(&id, 0, NO_WAIT).await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn report_close_reason() {
+        let sessions = LongPollSessions::default();
+        let (id, mut transport) = sessions.open(None, client(0)).unwrap();
+
+        transport.send(RemoteMessage::Rejected).await.unwrap();
+        transport.close(CloseReason::Outdated).await.unwrap();
+        drop(transport);
+
+        let poll = sessions.poll(&id, 0, NO_WAIT).await.unwrap();
+        assert_eq!(poll.messages.len(), 1);
+        assert_eq!(poll.closed, Some("Please reload the page"));
+
+        sleep(CLOSED_SESSION_RETENTION * 2).await;
+        assert!(sessions.poll(&id, 0, NO_WAIT).await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_session_of_lagging_client() {
+        let sessions = LongPollSessions::default();
+        let (id, mut transport) = sessions.open(Some(2), client(0)).unwrap();
+
+        // ACT
+        for _ in 0..=MAX_UNACKED {
+            transport.send(RemoteMessage::ForceOpen).await.unwrap();
+        }
+
+        // ASSERT
+        let poll = sessions.poll(&id, 0, NO_WAIT).await.unwrap();
+        assert_eq!(poll.messages.len(), MAX_UNACKED);
+        assert_eq!(poll.closed, Some("Connection is too slow"));
+        assert_eq!(transport.recv().await.unwrap(), RemoteMessage::Close);
+    }
+
+    #[tokio::test]
+    async fn limit_sessions() {
+        let sessions = LongPollSessions::default();
+        let transports: Vec<_> = (0..MAX_SESSIONS)
+            .map(|n| sessions.open(None, client(n)).unwrap())
+            .collect();
+
+        // ACT
+        let rejected = sessions.open(None, client(MAX_SESSIONS));
+        drop(transports);
+        let accepted = sessions.open(None, client(MAX_SESSIONS));
+
+        // ASSERT
+        assert!(rejected.is_none());
+        assert!(accepted.is_some());
+    }
+
+    #[tokio::test]
+    async fn limit_sessions_per_client() {
+        let sessions = LongPollSessions::default();
+        let transports: Vec<_> = (0..MAX_SESSIONS_PER_CLIENT)
+            .map(|_| sessions.open(None, client(0)).unwrap())
+            .collect();
+
+        // ACT
+        let rejected = sessions.open(None, client(0));
+        let other_client = sessions.open(None, client(1));
+        drop(transports);
+        let accepted = sessions.open(None, client(0));
+
+        // ASSERT
+        assert!(rejected.is_none());
+        assert!(other_client.is_some());
+        assert!(accepted.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expire_sessions_that_are_never_polled() {
+        let sessions = LongPollSessions::default();
+        let (polled, _transport) = sessions.open(None, client(0)).unwrap();
+        let transports: Vec<_> = (1..MAX_SESSIONS)
+            .map(|n| sessions.open(None, client(n)).unwrap())
+            .collect();
+        sessions.poll(&polled, 0, NO_WAIT).await.unwrap();
+
+        // ACT
+        let rejected = sessions.open(None, client(MAX_SESSIONS));
+        sleep(FIRST_POLL_TIMEOUT * 2).await;
+        let accepted = sessions.open(None, client(MAX_SESSIONS));
+
+        // ASSERT
+        assert!(rejected.is_none());
+        assert!(accepted.is_some());
+        assert!(sessions.poll(&polled, 0, NO_WAIT).await.is_some());
+        assert!(sessions.poll(&transports[0].0, 0, NO_WAIT).await.is_none());
+    }
+}
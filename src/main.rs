@@ -1,9 +1,31 @@
+use game_of_estimates::adapters::file::FileModule;
+use game_of_estimates::adapters::memory::MemoryModule;
+#[cfg(feature = "sqlite")]
+use game_of_estimates::adapters::sqlite::SqliteModule;
+#[cfg(feature = "postgres")]
 use game_of_estimates::adapters::sqlx::SqlxModule;
+use game_of_estimates::cluster::Cluster;
 use game_of_estimates::game_server::{GameServer, GameServerAddr};
-use game_of_estimates::ports::{DatabaseMigratorRef, DatabaseUrl, RoomRepositoryRef};
-use log::info;
+use game_of_estimates::persistence::{
+    EventWriter, EventWriterConfig, ResilienceConfig, ResilientRoomRepository,
+};
+use game_of_estimates::ports::{
+    DatabaseMigratorRef, DatabasePoolConfig, DatabaseUrl, MessageBusRef, RoomAliasRepositoryRef,
+    RoomLeaseRepositoryRef, RoomRepositoryRef, RoomRetentionRepositoryRef,
+    WebhookSecretRepositoryRef,
+};
+use game_of_estimates::rate_limit::{RateLimit, RateLimitConfig};
+use game_of_estimates::retention::{Retention, RetentionConfig};
+use game_of_estimates::retry::RetryPolicy;
+use game_of_estimates::webhook::{Webhook, WebhookConfig, Webhooks};
+use log::{error, info, warn};
+use prometheus_client::registry::Registry;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 use uactor::blocking::Actor;
+use web::admin::AdminToken;
+use web::shutdown::ShutdownConfig;
 
 mod web;
 
@@ -19,9 +41,103 @@ impl MainModule {
         DatabaseUrl(std::env::var("DATABASE_URL").expect("DATABASE_URL should be set"))
     }
 
+    pub fn provide_database_pool_config() -> DatabasePoolConfig {
+        let defaults = DatabasePoolConfig::default();
+        let idle_timeout = defaults.idle_timeout.map_or(0, |timeout| timeout.as_secs());
+        let statement_timeout = env_secs("GOE_DB_STATEMENT_TIMEOUT", 0);
+        DatabasePoolConfig {
+            max_connections: env_number("GOE_DB_MAX_CONNECTIONS", defaults.max_connections as usize)
+                as u32,
+            min_connections: env_number("GOE_DB_MIN_CONNECTIONS", defaults.min_connections as usize)
+                as u32,
+            acquire_timeout: Duration::from_secs(env_secs(
+                "GOE_DB_ACQUIRE_TIMEOUT",
+                defaults.acquire_timeout.as_secs(),
+            )),
+            // 0 disables the timeouts
+            idle_timeout: Some(env_secs("GOE_DB_IDLE_TIMEOUT", idle_timeout))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            statement_timeout: Some(statement_timeout)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            ssl_mode: env::var("GOE_DB_SSL_MODE").ok(),
+        }
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_cluster(leases: RoomLeaseRepositoryRef, bus: MessageBusRef) -> Option<Cluster> {
+        let enabled = env::var("GOE_CLUSTER").is_ok_and(|value| value == "1" || value == "true");
+        if enabled {
+            let lease_ttl = Duration::from_secs(env_secs("GOE_ROOM_LEASE_TTL", 30));
+            Some(Cluster::new(leases, bus, lease_ttl))
+        } else {
+            None
+        }
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_webhooks() -> Webhooks {
+        let secret = env::var("GOE_WEBHOOK_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let server_wide = env::var("GOE_WEBHOOK_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| Webhook {
+                url: url.to_string(),
+                secret: secret.clone(),
+            })
+            .collect();
+        Webhooks::start(WebhookConfig {
+            server_wide,
+            retry: RetryPolicy::default(),
+        })
+    }
+
     #[chassis(singleton)]
-    pub fn provide_game_server(room_repo: RoomRepositoryRef) -> GameServerAddr {
-        GameServer::new(room_repo).start()
+    pub fn provide_resilient_room_repo(
+        room_repo: RoomRepositoryRef,
+    ) -> Arc<ResilientRoomRepository> {
+        Arc::new(ResilientRoomRepository::start(
+            room_repo,
+            ResilienceConfig::default(),
+        ))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_event_writer(room_repo: Arc<ResilientRoomRepository>) -> Arc<EventWriter> {
+        let defaults = EventWriterConfig::default();
+        let config = EventWriterConfig {
+            queue_size: env_number("GOE_EVENT_QUEUE_SIZE", defaults.queue_size),
+            max_batch_size: env_number("GOE_EVENT_BATCH_SIZE", defaults.max_batch_size),
+        };
+        Arc::new(EventWriter::start(room_repo, config))
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_game_server(
+        event_writer: Arc<EventWriter>,
+        alias_repo: RoomAliasRepositoryRef,
+        cluster: Option<Cluster>,
+        webhooks: Webhooks,
+        webhook_secrets: WebhookSecretRepositoryRef,
+    ) -> GameServerAddr {
+        let game_server = GameServer::new(event_writer, alias_repo)
+            .with_webhooks(webhooks)
+            .with_webhook_secrets(webhook_secrets);
+        match cluster {
+            Some(cluster) => {
+                info!(
+                    "Cluster mode enabled, instance ID is {}",
+                    cluster.instance()
+                );
+                game_server.with_cluster(cluster).start()
+            }
+            None => game_server.start(),
+        }
     }
 
     // #[chassis(singleton)]
@@ -43,28 +159,218 @@ impl MainModule {
     //     TlsCert::Unencrypted
     // }
 
+    pub fn provide_retention(
+        repo: RoomRetentionRepositoryRef,
+        game_server: GameServerAddr,
+    ) -> Option<Retention> {
+        let days = env::var("GOE_RETENTION_DAYS").ok()?;
+        let days: u64 = days
+            .parse()
+            .expect("GOE_RETENTION_DAYS should be a number of days");
+        let config = RetentionConfig {
+            max_inactivity: Duration::from_secs(days * 24 * 60 * 60),
+            interval: Duration::from_secs(env_secs("GOE_RETENTION_INTERVAL", 60 * 60)),
+            dry_run: env::var("GOE_RETENTION_DRY_RUN")
+                .is_ok_and(|value| value == "1" || value == "true"),
+        };
+        Some(Retention::new(repo, game_server, config))
+    }
+
     #[chassis(singleton)]
     pub fn provide_listen_addr() -> ListenAddr {
         ListenAddr(env::var("GOE_LISTEN_ADDR").unwrap_or_else(|_| "127.0.0.1:5500".to_string()))
     }
 
-    pub fn provide_main(game_server: GameServerAddr, listen_addr: ListenAddr) -> Main {
+    pub fn provide_admin_token() -> Option<AdminToken> {
+        match env::var("GOE_ADMIN_TOKEN") {
+            Ok(token) if !token.trim().is_empty() => Some(AdminToken(token.trim().to_string())),
+            _ => None,
+        }
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_shutdown_config() -> ShutdownConfig {
+        ShutdownConfig {
+            timeout: Duration::from_secs(env_secs("GOE_SHUTDOWN_TIMEOUT", 10)),
+            reconnect_after: Duration::from_secs(env_secs("GOE_RECONNECT_AFTER", 5)),
+        }
+    }
+
+    #[chassis(singleton)]
+    pub fn provide_rate_limits() -> RateLimitConfig {
+        let defaults = RateLimitConfig::default();
+        RateLimitConfig {
+            vote: env_rate_limit("GOE_RATE_LIMIT_VOTE", defaults.vote),
+            update_player: env_rate_limit("GOE_RATE_LIMIT_UPDATE_PLAYER", defaults.update_player),
+            control: env_rate_limit("GOE_RATE_LIMIT_CONTROL", defaults.control),
+            join: env_rate_limit("GOE_RATE_LIMIT_JOIN", defaults.join),
+            abuse: env_rate_limit("GOE_RATE_LIMIT_ABUSE", defaults.abuse),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn provide_main(
+        game_server: GameServerAddr,
+        listen_addr: ListenAddr,
+        admin_token: Option<AdminToken>,
+        shutdown_config: ShutdownConfig,
+        rate_limits: RateLimitConfig,
+        webhooks: Webhooks,
+        retention: Option<Retention>,
+        event_writer: Arc<EventWriter>,
+        room_repo: Arc<ResilientRoomRepository>,
+    ) -> Main {
         Main {
             game_server,
             listen_addr,
+            admin_token,
+            shutdown_config,
+            rate_limits,
+            webhooks,
+            retention,
+            event_writer,
+            room_repo,
         }
     }
 }
 
+#[cfg(feature = "postgres")]
 #[chassis::injector(modules = [MainModule, SqlxModule])]
-pub trait Integrator {
+pub trait PostgresIntegrator {
     fn provide_main(&self) -> Main;
     fn provide_db_migrator(&self) -> DatabaseMigratorRef;
 }
 
+#[chassis::injector(modules = [MainModule, MemoryModule])]
+pub trait MemoryIntegrator {
+    fn provide_main(&self) -> Main;
+    fn provide_db_migrator(&self) -> DatabaseMigratorRef;
+}
+
+#[chassis::injector(modules = [MainModule, FileModule])]
+pub trait FileIntegrator {
+    fn provide_main(&self) -> Main;
+    fn provide_db_migrator(&self) -> DatabaseMigratorRef;
+}
+
+#[cfg(feature = "sqlite")]
+#[chassis::injector(modules = [MainModule, SqliteModule])]
+pub trait SqliteIntegrator {
+    fn provide_main(&self) -> Main;
+    fn provide_db_migrator(&self) -> DatabaseMigratorRef;
+}
+
+/// Injector of the storage backend chosen at startup
+enum Backend {
+    #[cfg(feature = "postgres")]
+    Postgres(Box<dyn PostgresIntegrator>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(Box<dyn SqliteIntegrator>),
+    File(Box<dyn FileIntegrator>),
+    Memory(Box<dyn MemoryIntegrator>),
+}
+
+impl Backend {
+    /// Backend for the scheme of `DATABASE_URL`, memory if it is not set
+    fn select() -> Self {
+        let database_url = env::var("DATABASE_URL").unwrap_or_default();
+        let scheme = database_url
+            .split_once(':')
+            .map(|(scheme, _)| scheme.trim().to_ascii_lowercase());
+        let cluster = env::var("GOE_CLUSTER").is_ok_and(|value| value == "1" || value == "true");
+
+        match scheme.as_deref() {
+            #[cfg(feature = "postgres")]
+            Some("postgres" | "postgresql") => Backend::Postgres(
+                <dyn PostgresIntegrator>::new().expect("Unable to build injector"),
+            ),
+            #[cfg(feature = "sqlite")]
+            Some("sqlite") => {
+                if cluster {
+                    panic!("GOE_CLUSTER needs PostgreSQL, a SQLite database can not be shared");
+                }
+                Backend::Sqlite(<dyn SqliteIntegrator>::new().expect("Unable to build injector"))
+            }
+            Some("file") => {
+                if cluster {
+                    panic!("GOE_CLUSTER needs PostgreSQL, an event log can not be shared");
+                }
+                Backend::File(<dyn FileIntegrator>::new().expect("Unable to build injector"))
+            }
+            Some(scheme) => panic!(
+                "DATABASE_URL has unsupported scheme {scheme}, \
+                 its feature (`postgres` or `sqlite`) might not be built in"
+            ),
+            None => {
+                if cluster {
+                    panic!("GOE_CLUSTER needs a database, DATABASE_URL should be set");
+                }
+                warn!("DATABASE_URL is not set, rooms are lost on restart");
+                Backend::Memory(<dyn MemoryIntegrator>::new().expect("Unable to build injector"))
+            }
+        }
+    }
+
+    fn provide_db_migrator(&self) -> DatabaseMigratorRef {
+        match self {
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(integrator) => integrator.provide_db_migrator(),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(integrator) => integrator.provide_db_migrator(),
+            Backend::File(integrator) => integrator.provide_db_migrator(),
+            Backend::Memory(integrator) => integrator.provide_db_migrator(),
+        }
+    }
+
+    fn provide_main(&self) -> Main {
+        match self {
+            #[cfg(feature = "postgres")]
+            Backend::Postgres(integrator) => integrator.provide_main(),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(integrator) => integrator.provide_main(),
+            Backend::File(integrator) => integrator.provide_main(),
+            Backend::Memory(integrator) => integrator.provide_main(),
+        }
+    }
+}
+
 pub struct Main {
     game_server: GameServerAddr,
     listen_addr: ListenAddr,
+    admin_token: Option<AdminToken>,
+    shutdown_config: ShutdownConfig,
+    rate_limits: RateLimitConfig,
+    webhooks: Webhooks,
+    retention: Option<Retention>,
+    event_writer: Arc<EventWriter>,
+    room_repo: Arc<ResilientRoomRepository>,
+}
+
+fn env_secs(name: &str, default: u64) -> u64 {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} should be a number of seconds")),
+        Err(_) => default,
+    }
+}
+
+fn env_number(name: &str, default: usize) -> usize {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} should be a number")),
+        Err(_) => default,
+    }
+}
+
+fn env_rate_limit(name: &str, default: RateLimit) -> RateLimit {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|err| panic!("{name} is invalid: {err}")),
+        Err(_) => default,
+    }
 }
 
 #[tokio::main]
@@ -73,15 +379,59 @@ async fn main() {
         info!("No .env file found, using only environment variables")
     }
 
-    let integrator = <dyn Integrator>::new().expect("Unable to build injector");
+    let backend = Backend::select();
 
-    integrator
-        .provide_db_migrator()
+    let migrator = backend.provide_db_migrator();
+    if let Err(err) = migrator.check_connection().await {
+        let url = DatabaseUrl(env::var("DATABASE_URL").unwrap_or_default());
+        eprintln!(
+            "Unable to connect to database {}: {:#}",
+            url.redacted(),
+            err
+        );
+        std::process::exit(1);
+    }
+    migrator
         .migrate()
         .await
         .expect("Unable to migrate database");
 
-    let main = integrator.provide_main();
+    let main = backend.provide_main();
+    if main.admin_token.is_none() {
+        warn!("GOE_ADMIN_TOKEN is not set, admin API is disabled");
+    }
+    let mut registry = Registry::default();
+    if let Some(retention) = main.retention {
+        retention.start(&mut registry);
+    }
+
     eprintln!("Listening on http://{}", main.listen_addr.0);
-    web::main(main.game_server, main.listen_addr).await
+    let flush_timeout = main.shutdown_config.timeout;
+    web::main(
+        registry,
+        main.game_server,
+        main.listen_addr,
+        main.admin_token,
+        main.shutdown_config,
+        main.rate_limits,
+        main.webhooks,
+    )
+    .await;
+
+    info!("Write queued room events");
+    let flushed = tokio::time::timeout(flush_timeout, async {
+        main.event_writer.flush().await;
+        main.room_repo.flush().await;
+    })
+    .await;
+    if flushed.is_err() {
+        warn!("Queued room events were not written in time");
+    }
+    let unwritten = main.event_writer.queued_events() as u64
+        + main.event_writer.lost_events()
+        + main.room_repo.pending_events() as u64
+        + main.room_repo.dropped_events();
+    if unwritten > 0 {
+        error!("{} room events were not written", unwritten);
+    }
 }
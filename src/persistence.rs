@@ -0,0 +1,742 @@
+//! Writing room events without blocking rooms
+//!
+//! [`EventWriter`] queues the events of all rooms and writes them in batches
+//! in the background, so rooms do not wait for the database. Rooms only wait
+//! when the queue is full.
+//!
+//! [`ResilientRoomRepository`] retries operations that failed with a
+//! transient error a few times with backoff. Events that still can not be
+//! written are buffered and written in order by a background task once the
+//! database is back. While events are waiting, later events are buffered
+//! without trying the database, so the events of a room keep their order.
+//! When the buffer is full, writes wait until there is space again, which
+//! fills the queue of the [`EventWriter`] and makes rooms wait. Only events
+//! the database rejects for good are dropped, they are counted.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use log::{debug, error, info, warn};
+use quick_error::quick_error;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{sleep, Duration};
+
+use crate::ports::{DbResult, RoomRepository, RoomRepositoryRef};
+use crate::retry::RetryPolicy;
+use crate::room::RoomEvent;
+use crate::room_id::RoomId;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum PersistenceError {
+        WriterStopped {
+            display("Event writer stopped")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventWriterConfig {
+    /// Events waiting to be written, rooms wait when it is full
+    pub queue_size: usize,
+    /// Events written at once
+    pub max_batch_size: usize,
+}
+
+impl Default for EventWriterConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: 10_000,
+            max_batch_size: 500,
+        }
+    }
+}
+
+enum Command {
+    Append(u64),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Repository that writes the events of another one in the background
+///
+/// Events are taken from the queue as they come, while a batch is written
+/// the next one fills up. Reads add the events of the room that are not
+/// written yet, so they do not wait for the queue.
+pub struct EventWriter {
+    repo: RoomRepositoryRef,
+    queue: mpsc::Sender<Command>,
+    state: Arc<WriterState>,
+}
+
+#[derive(Default)]
+struct WriterState {
+    /// Events appended but not yet written, by the order they were appended
+    unwritten: Mutex<BTreeMap<u64, (RoomId, RoomEvent)>>,
+    next: AtomicU64,
+    /// Events of batches the repository failed to write
+    lost: AtomicU64,
+    /// Held while a batch is written, so readers see its events either in
+    /// the repository or as unwritten
+    writing: tokio::sync::Mutex<()>,
+}
+
+impl EventWriter {
+    pub fn start(repo: RoomRepositoryRef, config: EventWriterConfig) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        let state = Arc::new(WriterState::default());
+        tokio::spawn(Self::write(
+            repo.clone(),
+            rx,
+            config.max_batch_size,
+            state.clone(),
+        ));
+        Self {
+            repo,
+            queue: tx,
+            state,
+        }
+    }
+
+    /// Events waiting in the queue or being written
+    pub fn queued_events(&self) -> usize {
+        self.state.unwritten.lock().unwrap().len()
+    }
+
+    /// Events that could not be written
+    pub fn lost_events(&self) -> u64 {
+        self.state.lost.load(Ordering::SeqCst)
+    }
+
+    /// Wait until all events appended before are written
+    pub async fn flush(&self) {
+        let (reply, flushed) = oneshot::channel();
+        if self.queue.send(Command::Flush(reply)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    async fn enqueue(&self, id: RoomId, evt: RoomEvent) -> DbResult<()> {
+        // kept before it is sent, so the writer always finds the event
+        let seq = self.state.next.fetch_add(1, Ordering::SeqCst);
+        self.state.unwritten.lock().unwrap().insert(seq, (id, evt));
+        let res = self.send(Command::Append(seq)).await;
+        if res.is_err() {
+            self.state.unwritten.lock().unwrap().remove(&seq);
+        }
+        res
+    }
+
+    async fn send(&self, command: Command) -> DbResult<()> {
+        let command = match self.queue.try_send(command) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(command)) => command,
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                return Err(PersistenceError::WriterStopped.into())
+            }
+        };
+        debug!("Event queue is full, waiting for the database");
+        self.queue
+            .send(command)
+            .await
+            .map_err(|_| PersistenceError::WriterStopped.into())
+    }
+
+    async fn write(
+        repo: RoomRepositoryRef,
+        mut queue: mpsc::Receiver<Command>,
+        max_batch_size: usize,
+        state: Arc<WriterState>,
+    ) {
+        let mut batch = Vec::with_capacity(max_batch_size);
+        let mut flushed = vec![];
+        while let Some(command) = queue.recv().await {
+            let mut next = Some(command);
+            while let Some(command) = next {
+                match command {
+                    Command::Append(seq) => batch.push(seq),
+                    Command::Flush(reply) => flushed.push(reply),
+                }
+                if batch.len() >= max_batch_size {
+                    break;
+                }
+                next = queue.try_recv().ok();
+            }
+
+            if !batch.is_empty() {
+                let _writing = state.writing.lock().await;
+                let events: Vec<_> = {
+                    let unwritten = state.unwritten.lock().unwrap();
+                    batch.iter().map(|seq| unwritten[seq].clone()).collect()
+                };
+                let count = events.len();
+                // the repository retries and buffers what it can, so the
+                // events are lost
+                if let Err(err) = repo.append_room_events(events).await {
+                    error!("Failed to write {} room events: {}", count, err);
+                    state.lost.fetch_add(count as u64, Ordering::SeqCst);
+                }
+                let mut unwritten = state.unwritten.lock().unwrap();
+                for seq in batch.drain(..) {
+                    unwritten.remove(&seq);
+                }
+            }
+            for reply in flushed.drain(..) {
+                let _ = reply.send(());
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomRepository for EventWriter {
+    async fn append_room_event(&self, id: &RoomId, evt: RoomEvent) -> DbResult<()> {
+        self.enqueue(*id, evt).await
+    }
+
+    async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+        let _writing = self.state.writing.lock().await;
+        let mut events = self.repo.get_room_events(id).await?;
+        events.extend(
+            self.state
+                .unwritten
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|(room, _)| room == id)
+                .map(|(_, event)| event.clone()),
+        );
+        Ok(events)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResilienceConfig {
+    /// Retries of every operation, the maximum backoff is also the time
+    /// between attempts to write buffered events
+    pub retry: RetryPolicy,
+    /// Events kept while the database is unavailable, further writes wait
+    /// for space
+    pub buffer_size: usize,
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy {
+                attempts: 3,
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(1),
+            },
+            buffer_size: 10_000,
+        }
+    }
+}
+
+/// Buffered events written at once
+const PENDING_BATCH_SIZE: usize = 500;
+
+struct Shared {
+    repo: RoomRepositoryRef,
+    config: ResilienceConfig,
+    /// Events that failed to be written, oldest first
+    pending: Mutex<VecDeque<(RoomId, RoomEvent)>>,
+    /// Notified when buffered events were written or dropped
+    space: Notify,
+    /// Buffered events the database rejected
+    dropped: AtomicU64,
+    /// Held while a buffered event is written, so readers see it either in
+    /// the database or in the buffer
+    writing: tokio::sync::Mutex<()>,
+}
+
+/// Repository that retries and buffers the operations of another one
+pub struct ResilientRoomRepository {
+    shared: Arc<Shared>,
+    buffered: Arc<Notify>,
+}
+
+impl ResilientRoomRepository {
+    pub fn start(repo: RoomRepositoryRef, config: ResilienceConfig) -> Self {
+        let shared = Arc::new(Shared {
+            repo,
+            config,
+            pending: Mutex::default(),
+            space: Notify::new(),
+            dropped: AtomicU64::default(),
+            writing: tokio::sync::Mutex::default(),
+        });
+        let buffered = Arc::new(Notify::new());
+        tokio::spawn(Self::write_buffered(
+            Arc::downgrade(&shared),
+            buffered.clone(),
+        ));
+        Self { shared, buffered }
+    }
+
+    /// Events waiting for the database
+    pub fn pending_events(&self) -> usize {
+        self.shared.pending.lock().unwrap().len()
+    }
+
+    /// Buffered events that were dropped, because the database rejected them
+    pub fn dropped_events(&self) -> u64 {
+        self.shared.dropped.load(Ordering::SeqCst)
+    }
+
+    /// Write the buffered events, waits for the database until they are
+    /// written
+    pub async fn flush(&self) {
+        loop {
+            self.shared.write_pending().await;
+            if self.pending_events() == 0 {
+                break;
+            }
+            sleep(self.shared.config.retry.max_backoff).await;
+        }
+    }
+
+    async fn write_buffered(shared: Weak<Shared>, buffered: Arc<Notify>) {
+        loop {
+            let Some(shared) = shared.upgrade() else {
+                break;
+            };
+            let interval = shared.config.retry.max_backoff;
+            shared.write_pending().await;
+            drop(shared);
+
+            tokio::select! {
+                _ = buffered.notified() => {}
+                _ = sleep(interval) => {}
+            }
+        }
+    }
+
+    /// Buffer the events, waits while the buffer is full
+    async fn buffer(&self, events: Vec<(RoomId, RoomEvent)>) {
+        loop {
+            let space = self.shared.space.notified();
+            {
+                let mut pending = self.shared.pending.lock().unwrap();
+                // batches larger than the buffer are taken when it is empty
+                let fits = pending.len() + events.len() <= self.shared.config.buffer_size;
+                if fits || pending.is_empty() {
+                    pending.extend(events);
+                    break;
+                }
+            }
+            debug!("Buffer of unwritten events is full, waiting for the database");
+            self.buffered.notify_one();
+            space.await;
+        }
+        self.buffered.notify_one();
+    }
+}
+
+impl Shared {
+    async fn write(&self, mut events: Vec<(RoomId, RoomEvent)>) -> DbResult<()> {
+        if events.len() == 1 {
+            let (id, evt) = events.remove(0);
+            self.repo.append_room_event(&id, evt).await
+        } else {
+            self.repo.append_room_events(events).await
+        }
+    }
+
+    /// Write buffered events until the buffer is empty or the database fails
+    async fn write_pending(&self) {
+        let mut written = 0;
+        loop {
+            let _writing = self.writing.lock().await;
+            let batch: Vec<_> = {
+                let pending = self.pending.lock().unwrap();
+                pending.iter().take(PENDING_BATCH_SIZE).cloned().collect()
+            };
+            if batch.is_empty() {
+                break;
+            }
+            let count = batch.len();
+            match self.write(batch).await {
+                Ok(()) => written += count,
+                Err(err) if err.is_transient() => {
+                    debug!("Database is still unavailable: {}", err);
+                    break;
+                }
+                Err(err) => {
+                    error!("Dropped {} buffered events: {}", count, err);
+                    self.dropped.fetch_add(count as u64, Ordering::SeqCst);
+                }
+            }
+            self.pending.lock().unwrap().drain(..count);
+            self.space.notify_waiters();
+        }
+        if written > 0 {
+            info!("Wrote {} buffered events", written);
+        }
+    }
+}
+
+/// Run `op` until it succeeds, fails permanently or all attempts are used
+async fn with_retries<T, F, Fut>(retry: &RetryPolicy, mut op: F) -> DbResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = DbResult<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(err) if err.is_transient() && attempt < retry.attempts => {
+                debug!("Retrying failed database operation: {}", err);
+                sleep(retry.backoff(attempt)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RoomRepository for ResilientRoomRepository {
+    async fn append_room_event(&self, id: &RoomId, evt: RoomEvent) -> DbResult<()> {
+        self.append_room_events(vec![(*id, evt)]).await
+    }
+
+    async fn append_room_events(&self, events: Vec<(RoomId, RoomEvent)>) -> DbResult<()> {
+        if self.pending_events() > 0 {
+            self.buffer(events).await;
+            return Ok(());
+        }
+
+        let shared = &self.shared;
+        let res = with_retries(&shared.config.retry, || shared.write(events.clone())).await;
+        match res {
+            Err(err) if err.is_transient() => {
+                warn!(
+                    "Buffered {} events, database is unavailable: {}",
+                    events.len(),
+                    err
+                );
+                self.buffer(events).await;
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+        let shared = &self.shared;
+        let _writing = shared.writing.lock().await;
+        let mut events =
+            with_retries(&shared.config.retry, || shared.repo.get_room_events(id)).await?;
+        events.extend(
+            shared
+                .pending
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(room, _)| room == id)
+                .map(|(_, event)| event.clone()),
+        );
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    use super::*;
+    use crate::adapters::memory::MemoryRepository;
+
+    /// Repository with a database that can be unavailable
+    #[derive(Default)]
+    struct FlakyRepository {
+        events: MemoryRepository,
+        down: AtomicBool,
+        /// Appends that fail before the next succeeds
+        failures: AtomicU32,
+        attempts: AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl RoomRepository for FlakyRepository {
+        async fn append_room_event(&self, id: &RoomId, evt: RoomEvent) -> DbResult<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            let failing = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if failing || self.down.load(Ordering::SeqCst) {
+                return Err(io::Error::from(io::ErrorKind::ConnectionRefused).into());
+            }
+            self.events.append_room_event(id, evt).await
+        }
+
+        async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+            self.events.get_room_events(id).await
+        }
+    }
+
+    fn config() -> ResilienceConfig {
+        ResilienceConfig {
+            retry: RetryPolicy {
+                attempts: 3,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(10),
+            },
+            buffer_size: 2,
+        }
+    }
+
+    fn joined(player: &str) -> RoomEvent {
+        RoomEvent::PlayerJoined {
+            player_id: player.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_transient_errors() {
+        let flaky = Arc::new(FlakyRepository::default());
+        flaky.failures.store(2, Ordering::SeqCst);
+        let repo = ResilientRoomRepository::start(flaky.clone(), config());
+        let room = RoomId::generate();
+
+        // ACT
+        let res = repo.append_room_event(&room, joined("p1")).await;
+
+        // ASSERT
+        assert!(res.is_ok());
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(repo.pending_events(), 0);
+        assert_eq!(flaky.get_room_events(&room).await.unwrap(), [joined("p1")]);
+    }
+
+    #[tokio::test]
+    async fn do_not_retry_permanent_errors() {
+        struct BrokenRepository;
+
+        #[async_trait::async_trait]
+        impl RoomRepository for BrokenRepository {
+            async fn append_room_event(&self, _id: &RoomId, _evt: RoomEvent) -> DbResult<()> {
+                Err(io::Error::from(io::ErrorKind::InvalidData).into())
+            }
+
+            async fn get_room_events(&self, _id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+                Ok(vec![])
+            }
+        }
+        let repo = ResilientRoomRepository::start(Arc::new(BrokenRepository), config());
+
+        // ACT
+        let res = repo
+            .append_room_event(&RoomId::generate(), joined("p1"))
+            .await;
+
+        // ASSERT
+        assert!(!res.unwrap_err().is_transient());
+        assert_eq!(repo.pending_events(), 0);
+    }
+
+    #[tokio::test]
+    async fn write_buffered_events_when_database_is_back() {
+        let flaky = Arc::new(FlakyRepository::default());
+        flaky.down.store(true, Ordering::SeqCst);
+        let repo = Arc::new(ResilientRoomRepository::start(flaky.clone(), config()));
+        let room = RoomId::generate();
+
+        // ACT
+        repo.append_room_event(&room, joined("p1")).await.unwrap();
+        repo.append_room_event(&room, joined("p2")).await.unwrap();
+        let waiting = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.append_room_event(&room, joined("p3")).await }
+        });
+        sleep(Duration::from_millis(20)).await;
+        let while_down = repo.get_room_events(&room).await.unwrap();
+        let waited = !waiting.is_finished();
+        flaky.down.store(false, Ordering::SeqCst);
+        let overflow = tokio::time::timeout(Duration::from_secs(5), waiting).await;
+        tokio::time::timeout(Duration::from_secs(5), repo.flush())
+            .await
+            .unwrap();
+
+        // ASSERT
+        assert!(waited);
+        assert!(overflow.unwrap().unwrap().is_ok());
+        assert_eq!(while_down, [joined("p1"), joined("p2")]);
+        assert_eq!(
+            flaky.get_room_events(&room).await.unwrap(),
+            [joined("p1"), joined("p2"), joined("p3")]
+        );
+        assert_eq!(repo.dropped_events(), 0);
+    }
+
+    #[tokio::test]
+    async fn flush_buffered_events() {
+        let flaky = Arc::new(FlakyRepository::default());
+        flaky.down.store(true, Ordering::SeqCst);
+        let repo = ResilientRoomRepository::start(flaky.clone(), config());
+        let room = RoomId::generate();
+        repo.append_room_event(&room, joined("p1")).await.unwrap();
+
+        // ACT
+        let while_down = tokio::time::timeout(Duration::from_millis(50), repo.flush()).await;
+        flaky.down.store(false, Ordering::SeqCst);
+        let flushed = tokio::time::timeout(Duration::from_secs(5), repo.flush()).await;
+
+        // ASSERT
+        assert!(while_down.is_err());
+        assert!(flushed.is_ok());
+        assert_eq!(repo.pending_events(), 0);
+        assert_eq!(flaky.get_room_events(&room).await.unwrap(), [joined("p1")]);
+    }
+
+    /// Repository that records batches and blocks while `gate` is locked
+    #[derive(Default)]
+    struct SlowRepository {
+        gate: tokio::sync::Mutex<()>,
+        batches: Mutex<Vec<Vec<(RoomId, RoomEvent)>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RoomRepository for SlowRepository {
+        async fn append_room_event(&self, id: &RoomId, evt: RoomEvent) -> DbResult<()> {
+            self.append_room_events(vec![(*id, evt)]).await
+        }
+
+        async fn get_room_events(&self, id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+            let batches = self.batches.lock().unwrap();
+            let events = batches.iter().flatten().filter(|(room, _)| room == id);
+            Ok(events.map(|(_, event)| event.clone()).collect())
+        }
+
+        async fn append_room_events(&self, events: Vec<(RoomId, RoomEvent)>) -> DbResult<()> {
+            let _gate = self.gate.lock().await;
+            self.batches.lock().unwrap().push(events);
+            Ok(())
+        }
+    }
+
+    fn batch_sizes(repo: &SlowRepository) -> Vec<usize> {
+        repo.batches.lock().unwrap().iter().map(Vec::len).collect()
+    }
+
+    #[tokio::test]
+    async fn write_queued_events_in_batches() {
+        let slow = Arc::new(SlowRepository::default());
+        let writer = EventWriter::start(slow.clone(), EventWriterConfig::default());
+        let (room, other) = (RoomId::generate(), RoomId::generate());
+        let gate = slow.gate.lock().await;
+
+        // ACT
+        writer.append_room_event(&room, joined("p1")).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        writer
+            .append_room_event(&other, joined("p2"))
+            .await
+            .unwrap();
+        writer.append_room_event(&room, joined("p3")).await.unwrap();
+        drop(gate);
+        let events = writer.get_room_events(&room).await.unwrap();
+
+        // ASSERT
+        assert_eq!(batch_sizes(&slow), [1, 2]);
+        assert_eq!(events, [joined("p1"), joined("p3")]);
+    }
+
+    #[tokio::test]
+    async fn read_unwritten_events_of_room() {
+        let slow = Arc::new(SlowRepository::default());
+        let writer = Arc::new(EventWriter::start(
+            slow.clone(),
+            EventWriterConfig::default(),
+        ));
+        let (room, other) = (RoomId::generate(), RoomId::generate());
+        let gate = slow.gate.lock().await;
+        writer.append_room_event(&room, joined("p1")).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        writer.append_room_event(&room, joined("p2")).await.unwrap();
+        writer
+            .append_room_event(&other, joined("p3"))
+            .await
+            .unwrap();
+
+        // ACT
+        let reading = tokio::spawn({
+            let writer = writer.clone();
+            async move { writer.get_room_events(&room).await }
+        });
+        sleep(Duration::from_millis(10)).await;
+        drop(gate);
+        // the first batch is written, the next one has to wait
+        let gate = slow.gate.lock().await;
+        let events = tokio::time::timeout(Duration::from_secs(5), reading).await;
+        let sizes = batch_sizes(&slow);
+        drop(gate);
+
+        // ASSERT
+        assert_eq!(sizes, [1]);
+        assert_eq!(
+            events.unwrap().unwrap().unwrap(),
+            [joined("p1"), joined("p2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_when_queue_is_full() {
+        let slow = Arc::new(SlowRepository::default());
+        let config = EventWriterConfig {
+            queue_size: 1,
+            max_batch_size: 10,
+        };
+        let writer = EventWriter::start(slow.clone(), config);
+        let room = RoomId::generate();
+        let gate = slow.gate.lock().await;
+        writer.append_room_event(&room, joined("p1")).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        writer.append_room_event(&room, joined("p2")).await.unwrap();
+
+        // ACT
+        let full = tokio::time::timeout(
+            Duration::from_millis(50),
+            writer.append_room_event(&room, joined("p3")),
+        )
+        .await;
+        drop(gate);
+        let events = writer.get_room_events(&room).await.unwrap();
+
+        // ASSERT
+        assert!(full.is_err());
+        assert_eq!(events, [joined("p1"), joined("p2")]);
+    }
+
+    #[tokio::test]
+    async fn count_events_that_are_lost() {
+        struct RejectingRepository;
+
+        #[async_trait::async_trait]
+        impl RoomRepository for RejectingRepository {
+            async fn append_room_event(&self, _id: &RoomId, _evt: RoomEvent) -> DbResult<()> {
+                Err(io::Error::from(io::ErrorKind::InvalidData).into())
+            }
+
+            async fn get_room_events(&self, _id: &RoomId) -> DbResult<Vec<RoomEvent>> {
+                Ok(vec![])
+            }
+        }
+        let writer =
+            EventWriter::start(Arc::new(RejectingRepository), EventWriterConfig::default());
+        let room = RoomId::generate();
+
+        // ACT
+        writer.append_room_event(&room, joined("p1")).await.unwrap();
+        writer.append_room_event(&room, joined("p2")).await.unwrap();
+        writer.flush().await;
+
+        // ASSERT
+        assert_eq!(writer.lost_events(), 2);
+        assert_eq!(writer.queued_events(), 0);
+    }
+}
@@ -1,29 +1,35 @@
 use log::{debug, error, warn};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
 use tokio::time::{interval, Duration, Interval};
 
 use uactor::blocking::Addr;
 
 use crate::game_server::{GameServerAddr, GameServerMessage};
-use crate::remote::{RemoteConnection, RemoteMessage};
+use crate::rate_limit::{MessageKind, RateLimitConfig, RateLimiter, Verdict};
+use crate::remote::{
+    is_supported_protocol, CloseReason, ErrorCode, RemoteMessage, Transport, FEATURES,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 use crate::room::{GamePlayerMessage, RoomAddr, RoomMessage};
+use crate::room_id::RoomId;
 use crate::utils::{char_len, char_trim};
 
-const TO_BE_CREATED: &str = "<to be created>";
-
 pub struct Player {
     channel: mpsc::Receiver<GamePlayerMessage>,
     addr: mpsc::Sender<GamePlayerMessage>,
 
     id: String,
-    room_id: Option<String>,
+    state: RoomState,
     room: Option<RoomAddr>,
     game_server: GameServerAddr,
 
-    remote: RemoteConnection,
+    remote: Box<dyn Transport>,
     ping_interval: Interval,
+    shutdown: ShutdownReceiver,
+    rate_limiter: RateLimiter,
 
     name: Option<String>,
     voter: bool,
@@ -31,7 +37,32 @@ pub struct Player {
 
 pub type PlayerAddr = mpsc::Sender<GamePlayerMessage>;
 
-#[derive(Debug, Clone)]
+/// Room the player is in or waits for
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RoomState {
+    Idle,
+    /// Join of a room ID, code or slug was requested
+    Joining(String),
+    /// Creation of a room was requested, it is joined once created
+    Creating,
+    Joined(RoomId),
+}
+
+impl RoomState {
+    /// Whether `room` is the room joined or being joined
+    fn is(&self, room: &str) -> bool {
+        match self {
+            Self::Joining(joining) => joining == room,
+            Self::Joined(id) => id.to_string() == room,
+            Self::Idle | Self::Creating => false,
+        }
+    }
+}
+
+/// Receives the reconnect hint when the server shuts down
+pub type ShutdownReceiver = watch::Receiver<Option<Duration>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerInformation {
     pub id: String,
     pub voter: bool,
@@ -39,7 +70,12 @@ pub struct PlayerInformation {
 }
 
 impl Player {
-    pub fn new(remote: RemoteConnection, game_server: GameServerAddr) -> Self {
+    pub fn new(
+        remote: impl Transport + 'static,
+        game_server: GameServerAddr,
+        shutdown: ShutdownReceiver,
+        rate_limits: RateLimitConfig,
+    ) -> Self {
         let (tx, rx) = mpsc::channel(16);
         Self {
             channel: rx,
@@ -48,10 +84,12 @@ impl Player {
             id: Self::gen_id(),
             game_server,
             room: None,
-            room_id: None,
+            state: RoomState::Idle,
 
-            remote,
+            remote: Box::new(remote),
             ping_interval: interval(Duration::from_secs(30)),
+            shutdown,
+            rate_limiter: RateLimiter::new(rate_limits),
 
             name: None,
             voter: true,
@@ -76,37 +114,74 @@ impl Player {
     }
 
     async fn leave_old_room(&mut self) {
-        if let Some(old_room_id) = &self.room_id {
-            debug!("{}: Leaves already joined room {}", self.id, old_room_id);
-            if let Some(old_room) = self.room.take() {
-                Self::leave_room(self.id.to_string(), old_room).await;
-            }
+        if let Some(old_room) = self.room.take() {
+            debug!("{}: Leaves already joined room {:?}", self.id, self.state);
+            Self::leave_room(self.id.to_string(), old_room).await;
         }
     }
 
+    /// Report error and that the player is not in a room anymore
+    async fn reject(&mut self, code: ErrorCode) {
+        self.room = None;
+        self.state = RoomState::Idle;
+        self.send_to_remote(RemoteMessage::error(code)).await;
+        self.send_to_remote(RemoteMessage::Rejected).await;
+    }
+
     async fn send_to_room(&mut self, msg: RoomMessage) {
         if let Some(ref room) = &self.room {
             if room.send(msg).await.is_err() {
                 warn!("{}: Room does not exist anymore", self.id);
-                self.room = None;
-                self.room_id = None;
-                self.send_to_remote(RemoteMessage::Rejected).await;
+                self.reject(ErrorCode::RoomClosed).await;
             }
         } else {
             warn!("{}: No room to interact with", self.id);
+            self.send_to_remote(RemoteMessage::error(ErrorCode::NotInRoom))
+                .await;
         }
     }
 
     async fn send_join_message(&mut self, msg: GameServerMessage) {
         if self.game_server.send(msg).await.is_err() {
             warn!("{}: Join room does not exist", self.id);
-            self.room = None;
-            self.room_id = None;
-            self.send_to_remote(RemoteMessage::Rejected).await;
+            self.reject(ErrorCode::JoinRoomFailed).await;
+        }
+    }
+
+    async fn on_throttled(&mut self, kind: MessageKind, retry_after: Duration) {
+        debug!("{}: Throttled {:?}", self.id, kind);
+        self.send_to_remote(RemoteMessage::error_with_context(
+            ErrorCode::RateLimited,
+            [
+                ("message_type", kind.name().to_string()),
+                ("retry_after_ms", retry_after.as_millis().to_string()),
+            ],
+        ))
+        .await;
+    }
+
+    async fn on_abuse(&mut self) {
+        warn!("{}: Disconnect because of too many messages", self.id);
+        if let Err(err) = self.remote.close(CloseReason::Abuse).await {
+            warn!("{}: Failed to close connection: {:?}", self.id, err);
         }
     }
 
     async fn on_remote_message(&mut self, msg: RemoteMessage) -> bool {
+        if let Some(kind) = MessageKind::of(&msg) {
+            match self.rate_limiter.check(kind) {
+                Verdict::Allow => {}
+                Verdict::Throttle { retry_after } => {
+                    self.on_throttled(kind, retry_after).await;
+                    return true;
+                }
+                Verdict::Disconnect => {
+                    self.on_abuse().await;
+                    return false;
+                }
+            }
+        }
+
         match msg {
             RemoteMessage::Close => {
                 debug!("{}: Player disconnected friendly", self.id);
@@ -115,13 +190,13 @@ impl Player {
             RemoteMessage::JoinRoom { room } => {
                 debug!("{}: Wants to join {}", self.id, &room);
                 let player_addr = self.addr();
-                if self.room_id.as_ref() == Some(&room) {
+                if self.state.is(&room) {
                     warn!("{}: Already joined {}", self.id, &room);
                     return true;
                 }
 
                 self.leave_old_room().await;
-                self.room_id = Some(room.clone());
+                self.state = RoomState::Joining(room.clone());
                 let player_information = self.get_player_information();
                 self.send_join_message(GameServerMessage::Join {
                     room,
@@ -135,30 +210,27 @@ impl Player {
                 self.send_to_room(RoomMessage::PlayerVoted(self.id.clone(), vote))
                     .await;
             }
+            RemoteMessage::CreateRoom { deck, slug } => {
+                debug!("{}: Wants to create room with deck {}", self.id, &deck);
+                let player_addr = self.addr();
+                self.leave_old_room().await;
+                self.state = RoomState::Creating;
+                let player_information = self.get_player_information();
+                self.send_join_message(GameServerMessage::CreateAndJoin {
+                    deck,
+                    slug,
+                    player_addr,
+                    player: player_information,
+                })
+                .await;
+            }
             RemoteMessage::UpdatePlayer { voter, name } => {
-                let name = name.map(|name| {
-                    if char_len(&name) >= 32 {
-                        char_trim(&name, 32)
-                    } else {
-                        name
-                    }
-                });
-
-                debug!(
-                    "{}: Update player: voter={:?} name={:?}",
-                    self.id, &voter, &name
-                );
-                self.voter = voter;
-                self.name.clone_from(&name);
-
-                if self.room.is_some() {
-                    self.send_to_room(RoomMessage::UpdatePlayer {
-                        id: self.id.clone(),
-                        voter,
-                        name,
-                    })
+                self.update_player(voter, name.map(Self::limit_name)).await;
+            }
+            RemoteMessage::SetName { name } => {
+                let name = Some(name).filter(|name| !name.trim().is_empty());
+                self.update_player(self.voter, name.map(Self::limit_name))
                     .await;
-                }
             }
             RemoteMessage::ForceOpen => {
                 debug!("{}: Force open", self.id);
@@ -178,6 +250,32 @@ impl Player {
         true
     }
 
+    fn limit_name(name: String) -> String {
+        if char_len(&name) >= 32 {
+            char_trim(&name, 32)
+        } else {
+            name
+        }
+    }
+
+    async fn update_player(&mut self, voter: bool, name: Option<String>) {
+        debug!(
+            "{}: Update player: voter={:?} name={:?}",
+            self.id, &voter, &name
+        );
+        self.voter = voter;
+        self.name.clone_from(&name);
+
+        if self.room.is_some() {
+            self.send_to_room(RoomMessage::UpdatePlayer {
+                id: self.id.clone(),
+                voter,
+                name,
+            })
+            .await;
+        }
+    }
+
     fn get_player_information(&self) -> PlayerInformation {
         PlayerInformation {
             id: self.id.clone(),
@@ -198,7 +296,9 @@ impl Player {
     }
 
     pub async fn run(&mut self) {
-        self.setup().await;
+        if !self.setup().await {
+            return;
+        }
 
         loop {
             tokio::select! {
@@ -225,6 +325,14 @@ impl Player {
                         warn!("{}: Failed to send ping: {:?}", self.id, err);
                     }
                 }
+                changed = self.shutdown.changed() => {
+                    let reconnect_after = match changed {
+                        Ok(()) => *self.shutdown.borrow_and_update(),
+                        Err(_) => None,
+                    };
+                    self.on_shutdown(reconnect_after).await;
+                    break;
+                }
             }
         }
 
@@ -233,11 +341,28 @@ impl Player {
 
     async fn on_message(&mut self, msg: GamePlayerMessage) {
         match msg {
+            GamePlayerMessage::AliasResolved { alias, room } => {
+                if self.state == RoomState::Joining(alias.clone()) {
+                    debug!("{}: Room {} is known as {}", self.id, alias, room);
+                    self.state = RoomState::Joining(room.to_string());
+                }
+            }
+            GamePlayerMessage::RoomCreated(created) => {
+                if self.state == RoomState::Creating {
+                    debug!("{}: Created room {}", self.id, created.id);
+                    self.state = RoomState::Joining(created.id.to_string());
+                    self.send_to_remote(RemoteMessage::RoomCreated {
+                        room: created.id,
+                        code: created.code,
+                        slug: created.slug,
+                    })
+                    .await;
+                }
+            }
             GamePlayerMessage::Welcome(id, room, game_state, players) => {
-                if self.room_id.as_ref() == Some(&id)
-                    || self.room_id.as_ref().map(|e| e as &str) == Some(TO_BE_CREATED)
-                {
+                if self.state.is(&id.to_string()) {
                     debug!("{}: Joined {}", self.id, id);
+                    self.state = RoomState::Joined(id);
                     self.room = Some(room);
                     self.send_to_remote(RemoteMessage::Joined {
                         room: id,
@@ -248,14 +373,18 @@ impl Player {
                 } else {
                     debug!(
                         "{}: Reject welcome of room {}, got {:?}",
-                        self.id, id, self.room_id
+                        self.id, id, self.state
                     );
                     Self::leave_room(self.id.to_string(), room).await;
                 }
             }
             GamePlayerMessage::Rejected(reason) => {
                 warn!("{}: Player was rejected: {:?}", self.id, reason);
-                self.send_to_remote(RemoteMessage::Rejected).await;
+                self.reject(reason.into()).await;
+            }
+            GamePlayerMessage::Dropped(reason) => {
+                self.send_to_remote(RemoteMessage::error(reason.into()))
+                    .await;
             }
             GamePlayerMessage::PlayerJoined(player) => {
                 self.send_to_remote(RemoteMessage::PlayerJoined { player })
@@ -273,15 +402,79 @@ impl Player {
                 self.send_to_remote(RemoteMessage::GameChanged { game_state })
                     .await;
             }
+            GamePlayerMessage::StoriesChanged(stories) => {
+                self.send_to_remote(RemoteMessage::StoriesChanged { stories })
+                    .await;
+            }
+            GamePlayerMessage::Notice(message) => {
+                self.send_to_remote(RemoteMessage::Notice { message }).await;
+            }
+            GamePlayerMessage::RoomClosed => {
+                debug!("{}: Room {:?} was closed", self.id, self.state);
+                self.reject(ErrorCode::RoomClosed).await;
+            }
+            GamePlayerMessage::RoomMoved => {
+                let RoomState::Joined(id) = self.state else {
+                    return;
+                };
+                let room = id.to_string();
+                debug!("{}: Room {} moved, join again", self.id, room);
+                self.room = None;
+                self.state = RoomState::Joining(room.clone());
+                let player_addr = self.addr();
+                let player_information = self.get_player_information();
+                self.send_join_message(GameServerMessage::Join {
+                    room,
+                    player_addr,
+                    player: player_information,
+                })
+                .await;
+            }
+        }
+    }
+
+    async fn on_shutdown(&mut self, reconnect_after: Option<Duration>) {
+        debug!("{}: Disconnect because of server shutdown", self.id);
+        if let Some(reconnect_after) = reconnect_after {
+            self.send_to_remote(RemoteMessage::ServerShuttingDown {
+                reconnect_after_ms: reconnect_after.as_millis() as u64,
+            })
+            .await;
+        }
+        if let Err(err) = self.remote.close(CloseReason::Restart).await {
+            warn!("{}: Failed to close connection: {:?}", self.id, err);
         }
     }
 
-    async fn setup(&mut self) {
+    async fn setup(&mut self) -> bool {
+        let Some(protocol) = self.remote.protocol().filter(|v| is_supported_protocol(*v)) else {
+            debug!(
+                "{}: Unsupported protocol {:?}",
+                self.id,
+                self.remote.protocol()
+            );
+            self.send_to_remote(RemoteMessage::error_with_context(
+                ErrorCode::UnsupportedProtocol,
+                [
+                    ("min_protocol", MIN_PROTOCOL_VERSION.to_string()),
+                    ("max_protocol", PROTOCOL_VERSION.to_string()),
+                ],
+            ))
+            .await;
+            if let Err(err) = self.remote.close(CloseReason::Outdated).await {
+                warn!("{}: Failed to close connection: {:?}", self.id, err);
+            }
+            return false;
+        };
+
         let welcome = RemoteMessage::Welcome {
             player_id: self.id().to_string(),
+            protocol,
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
         };
         self.send_to_remote(welcome).await;
         let _ = self.remote.ping().await;
+        true
     }
 
     async fn tear_down(&mut self) {
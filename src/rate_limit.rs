@@ -0,0 +1,295 @@
+//! Token bucket rate limiting of messages sent by clients
+//!
+//! Every player gets one bucket per kind of message. A message is throttled
+//! when its bucket is empty. Throttled messages are counted in another
+//! bucket, so a client that keeps flooding gets disconnected.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use tokio::time::{Duration, Instant};
+
+use crate::remote::RemoteMessage;
+
+/// Allows `messages` within `per`, bursts up to `messages`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub messages: u32,
+    pub per: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidRateLimit;
+
+impl Display for InvalidRateLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("rate limit should look like `<messages>/<seconds>`")
+    }
+}
+
+impl std::error::Error for InvalidRateLimit {}
+
+impl RateLimit {
+    pub const fn new(messages: u32, per_secs: u64) -> Self {
+        Self {
+            messages,
+            per: Duration::from_secs(per_secs),
+        }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = InvalidRateLimit;
+
+    /// Parse limits like `10/5` for 10 messages per 5 seconds
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (messages, secs) = s.trim().split_once('/').ok_or(InvalidRateLimit)?;
+        let messages: u32 = messages.trim().parse().map_err(|_| InvalidRateLimit)?;
+        let secs: f64 = secs.trim().parse().map_err(|_| InvalidRateLimit)?;
+        if messages == 0 || !secs.is_finite() || secs <= 0.0 {
+            return Err(InvalidRateLimit);
+        }
+        Ok(Self {
+            messages,
+            per: Duration::from_secs_f64(secs),
+        })
+    }
+}
+
+/// Kind of client message with its own limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Vote,
+    UpdatePlayer,
+    /// `ForceOpen` and `Restart`
+    Control,
+    /// `JoinRoom` and `CreateRoom`
+    Join,
+}
+
+impl MessageKind {
+    /// Kind of a client message, `None` for messages that are not limited
+    pub fn of(msg: &RemoteMessage) -> Option<Self> {
+        match msg {
+            RemoteMessage::Vote { .. } => Some(Self::Vote),
+            RemoteMessage::UpdatePlayer { .. } | RemoteMessage::SetName { .. } => {
+                Some(Self::UpdatePlayer)
+            }
+            RemoteMessage::ForceOpen | RemoteMessage::Restart => Some(Self::Control),
+            RemoteMessage::JoinRoom { .. } | RemoteMessage::CreateRoom { .. } => Some(Self::Join),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Vote => "Vote",
+            Self::UpdatePlayer => "UpdatePlayer",
+            Self::Control => "Control",
+            Self::Join => "Join",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub vote: RateLimit,
+    pub update_player: RateLimit,
+    pub control: RateLimit,
+    pub join: RateLimit,
+    /// Throttled messages tolerated before the connection is closed
+    pub abuse: RateLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            vote: RateLimit::new(10, 5),
+            update_player: RateLimit::new(5, 5),
+            control: RateLimit::new(5, 5),
+            join: RateLimit::new(5, 10),
+            abuse: RateLimit::new(20, 60),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn limit(&self, kind: MessageKind) -> RateLimit {
+        match kind {
+            MessageKind::Vote => self.vote,
+            MessageKind::UpdatePlayer => self.update_player,
+            MessageKind::Control => self.control,
+            MessageKind::Join => self.join,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    /// tokens per second
+    refill: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        let capacity = limit.messages as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            refill: capacity / limit.per.as_secs_f64(),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Take a token or return the time until the next one is available
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.refill))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Throttle { retry_after: Duration },
+    Disconnect,
+}
+
+/// Rate limits of a single connection
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<MessageKind, TokenBucket>,
+    abuse: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+            abuse: None,
+        }
+    }
+
+    pub fn check(&mut self, kind: MessageKind) -> Verdict {
+        self.check_at(kind, Instant::now())
+    }
+
+    fn check_at(&mut self, kind: MessageKind, now: Instant) -> Verdict {
+        let limit = self.config.limit(kind);
+        let bucket = self
+            .buckets
+            .entry(kind)
+            .or_insert_with(|| TokenBucket::new(limit, now));
+        let Err(retry_after) = bucket.take(now) else {
+            return Verdict::Allow;
+        };
+
+        let abuse_limit = self.config.abuse;
+        let abuse = self
+            .abuse
+            .get_or_insert_with(|| TokenBucket::new(abuse_limit, now));
+        match abuse.take(now) {
+            Ok(()) => Verdict::Throttle { retry_after },
+            Err(_) => Verdict::Disconnect,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            vote: RateLimit::new(2, 1),
+            abuse: RateLimit::new(2, 10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_rate_limits() {
+        assert_eq!("10/5".parse(), Ok(RateLimit::new(10, 5)));
+        assert_eq!(
+            " 3 / 0.5 ".parse(),
+            Ok(RateLimit {
+                messages: 3,
+                per: Duration::from_millis(500)
+            })
+        );
+        assert_eq!("10".parse::<RateLimit>(), Err(InvalidRateLimit));
+        assert_eq!("0/5".parse::<RateLimit>(), Err(InvalidRateLimit));
+        assert_eq!("10/0".parse::<RateLimit>(), Err(InvalidRateLimit));
+        assert_eq!("10/-1".parse::<RateLimit>(), Err(InvalidRateLimit));
+    }
+
+    #[test]
+    fn throttle_burst() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(config());
+
+        assert_eq!(limiter.check_at(MessageKind::Vote, now), Verdict::Allow);
+        assert_eq!(limiter.check_at(MessageKind::Vote, now), Verdict::Allow);
+        assert_eq!(
+            limiter.check_at(MessageKind::Vote, now),
+            Verdict::Throttle {
+                retry_after: Duration::from_millis(500)
+            }
+        );
+        // other kinds have their own bucket
+        assert_eq!(limiter.check_at(MessageKind::Control, now), Verdict::Allow);
+    }
+
+    #[test]
+    fn refill_over_time() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(config());
+        limiter.check_at(MessageKind::Vote, now);
+        limiter.check_at(MessageKind::Vote, now);
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check_at(MessageKind::Vote, later), Verdict::Allow);
+        assert!(matches!(
+            limiter.check_at(MessageKind::Vote, later),
+            Verdict::Throttle { .. }
+        ));
+    }
+
+    #[test]
+    fn disconnect_on_repeated_abuse() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(config());
+        limiter.check_at(MessageKind::Vote, now);
+        limiter.check_at(MessageKind::Vote, now);
+
+        assert!(matches!(
+            limiter.check_at(MessageKind::Vote, now),
+            Verdict::Throttle { .. }
+        ));
+        assert!(matches!(
+            limiter.check_at(MessageKind::Vote, now),
+            Verdict::Throttle { .. }
+        ));
+        assert_eq!(
+            limiter.check_at(MessageKind::Vote, now),
+            Verdict::Disconnect
+        );
+    }
+}
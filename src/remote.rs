@@ -1,10 +1,126 @@
-use crate::room::{GameState, PlayerState};
-use axum::extract::ws::{Message, WebSocket};
+use crate::room::{DropReason, GameState, PlayerState, RejectReason};
+use crate::room_id::RoomId;
+use crate::story::Story;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
 use quick_error::quick_error;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use tokio::time::{Duration, Instant};
 
+/// Current version of the WebSocket protocol
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version the server still speaks
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Optional protocol features, announced in `Welcome`
+pub const FEATURES: &[&str] = &[
+    "room-aliases",
+    "structured-errors",
+    "rate-limits",
+    "server-shutdown",
+    "notices",
+    "msgpack",
+    "cbor",
+    "stories",
+];
+
+const SUBPROTOCOL_PREFIX: &str = "goe.v";
+
+/// Wire format of `RemoteMessage`s, negotiated per connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// JSON in text frames
+    #[default]
+    Json,
+    /// MessagePack in binary frames
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// CBOR in binary frames
+    Cbor,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::MessagePack => "msgpack",
+            Self::Cbor => "cbor",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Json, Self::MessagePack, Self::Cbor]
+            .into_iter()
+            .find(|encoding| encoding.name() == name)
+    }
+
+    pub fn encode(&self, msg: &RemoteMessage) -> ConnResult<Message> {
+        Ok(match self {
+            Self::Json => Message::text(serde_json::to_string(msg)?),
+            // structs as maps, because internally tagged enums need field names
+            Self::MessagePack => Message::binary(rmp_serde::to_vec_named(msg)?),
+            Self::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(msg, &mut buf)?;
+                Message::binary(buf)
+            }
+        })
+    }
+
+    pub fn decode(&self, msg: Message) -> ConnResult<RemoteMessage> {
+        match (self, msg) {
+            (Self::Json, Message::Text(text)) => Ok(serde_json::from_str(&text)?),
+            (Self::MessagePack, Message::Binary(data)) => Ok(rmp_serde::from_slice(&data)?),
+            (Self::Cbor, Message::Binary(data)) => Ok(ciborium::from_reader(data.as_ref())?),
+            (_, msg) => Err(ConnError::UnsupportedMessageFormat(msg)),
+        }
+    }
+}
+
+/// WebSocket subprotocol for a protocol version, like `goe.v2` or
+/// `goe.v2.msgpack`
+pub fn subprotocol(version: u32, encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Json => format!("{SUBPROTOCOL_PREFIX}{version}"),
+        encoding => format!("{SUBPROTOCOL_PREFIX}{version}.{}", encoding.name()),
+    }
+}
+
+/// Protocol version and encoding of a WebSocket subprotocol
+pub fn parse_subprotocol(protocol: &str) -> Option<(u32, Encoding)> {
+    let protocol = protocol.trim().strip_prefix(SUBPROTOCOL_PREFIX)?;
+    let (version, encoding) = match protocol.split_once('.') {
+        Some((version, encoding)) => (version, Encoding::from_name(encoding)?),
+        None => (protocol, Encoding::Json),
+    };
+    Some((version.parse().ok()?, encoding))
+}
+
+pub fn is_supported_protocol(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+/// Pick protocol version and encoding from the ones declared by a client
+///
+/// Prefers the newest supported version, and for it the encoding declared
+/// first. Returns the newest declared version when none is supported, so
+/// the client can be told to reload.
+pub fn negotiate_protocol(
+    declared: impl IntoIterator<Item = (u32, Encoding)>,
+) -> Option<(u32, Encoding)> {
+    let rank = |version: u32| (is_supported_protocol(version), version);
+    let mut best: Option<(u32, Encoding)> = None;
+    for (version, encoding) in declared {
+        if best.map_or(true, |(best_version, _)| rank(version) > rank(best_version)) {
+            best = Some((version, encoding));
+        }
+    }
+    best
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum RemoteMessage {
@@ -26,6 +142,8 @@ pub enum RemoteMessage {
     },
     CreateRoom {
         deck: String,
+        #[serde(default)]
+        slug: Option<String>,
     },
     // pseudo
     Ping(Duration),
@@ -34,10 +152,17 @@ pub enum RemoteMessage {
     // downstream
     Welcome {
         player_id: String,
+        protocol: u32,
+        features: Vec<String>,
     },
     Rejected,
+    RoomCreated {
+        room: RoomId,
+        code: Option<String>,
+        slug: Option<String>,
+    },
     Joined {
-        room: String,
+        room: RoomId,
         state: GameState,
         players: Vec<PlayerState>,
     },
@@ -53,6 +178,112 @@ pub enum RemoteMessage {
     GameChanged {
         game_state: GameState,
     },
+    StoriesChanged {
+        stories: Vec<Story>,
+    },
+    Notice {
+        message: String,
+    },
+    ServerShuttingDown {
+        reconnect_after_ms: u64,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        context: BTreeMap<String, String>,
+    },
+}
+
+impl RemoteMessage {
+    pub fn error(code: ErrorCode) -> Self {
+        Self::Error {
+            code,
+            message: code.message().to_string(),
+            context: BTreeMap::new(),
+        }
+    }
+
+    pub fn error_with_context<const N: usize>(
+        code: ErrorCode,
+        context: [(&str, String); N],
+    ) -> Self {
+        Self::Error {
+            code,
+            message: code.message().to_string(),
+            context: context
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        }
+    }
+}
+
+/// Stable error codes sent to clients
+///
+/// Codes are part of the protocol: never rename or reuse them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    // connection
+    UnsupportedProtocol,
+
+    // join rejections
+    RoomDoesNotExist,
+    CreateRoomFailed,
+    JoinRoomFailed,
+    ServerShuttingDown,
+    RoomClosed,
+    InvalidSlug,
+    SlugTaken,
+
+    // dropped messages
+    NotInRoom,
+    VoteWhileOpen,
+    NonVoterVote,
+    RateLimited,
+}
+
+impl ErrorCode {
+    /// Human readable description in English
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::UnsupportedProtocol => "Client is outdated, please reload the page",
+            Self::RoomDoesNotExist => "Room does not exist",
+            Self::CreateRoomFailed => "Room could not be created",
+            Self::JoinRoomFailed => "Room could not be joined",
+            Self::ServerShuttingDown => "Server is shutting down",
+            Self::RoomClosed => "Room was closed",
+            Self::InvalidSlug => "Room name is invalid",
+            Self::SlugTaken => "Room name is already taken",
+            Self::NotInRoom => "You have not joined a room",
+            Self::VoteWhileOpen => "Cards are already open",
+            Self::NonVoterVote => "Only voters can vote",
+            Self::RateLimited => "Too many messages",
+        }
+    }
+}
+
+impl From<RejectReason> for ErrorCode {
+    fn from(reason: RejectReason) -> Self {
+        match reason {
+            RejectReason::RoomDoesNotExist => Self::RoomDoesNotExist,
+            RejectReason::CreateGameError => Self::CreateRoomFailed,
+            RejectReason::JoinGameError => Self::JoinRoomFailed,
+            RejectReason::ServerShuttingDown => Self::ServerShuttingDown,
+            RejectReason::InvalidSlug => Self::InvalidSlug,
+            RejectReason::SlugTaken => Self::SlugTaken,
+        }
+    }
+}
+
+impl From<DropReason> for ErrorCode {
+    fn from(reason: DropReason) -> Self {
+        match reason {
+            DropReason::VoteWhileOpen => Self::VoteWhileOpen,
+            DropReason::NonVoterVote => Self::NonVoterVote,
+        }
+    }
 }
 
 quick_error! {
@@ -66,40 +297,120 @@ quick_error! {
             display("JSON error: {}", err)
             from()
         }
+        MessagePackEncode(err: rmp_serde::encode::Error) {
+            display("MessagePack error: {}", err)
+            from()
+        }
+        MessagePackDecode(err: rmp_serde::decode::Error) {
+            display("MessagePack error: {}", err)
+            from()
+        }
+        CborEncode(err: ciborium::ser::Error<std::io::Error>) {
+            display("CBOR error: {}", err)
+            from()
+        }
+        CborDecode(err: ciborium::de::Error<std::io::Error>) {
+            display("CBOR error: {}", err)
+            from()
+        }
         UnsupportedMessageFormat(msg: Message) {
             display("Unsupported web socket message: {:?}", msg)
         }
     }
 }
 
-type ConnResult<T> = Result<T, ConnError>;
+pub type ConnResult<T> = Result<T, ConnError>;
+
+/// Why the server ends a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Server restarts, client should reconnect
+    Restart,
+    /// Client speaks an unsupported protocol
+    Outdated,
+    /// Client keeps exceeding rate limits
+    Abuse,
+    /// Client does not keep up with the messages of the server
+    Lagging,
+}
+
+impl CloseReason {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::Restart => "Server is shutting down",
+            Self::Outdated => "Please reload the page",
+            Self::Abuse => "Too many messages",
+            Self::Lagging => "Connection is too slow",
+        }
+    }
+
+    fn close_code(&self) -> u16 {
+        match self {
+            Self::Restart => close_code::RESTART,
+            Self::Outdated | Self::Abuse => close_code::POLICY,
+            Self::Lagging => close_code::AGAIN,
+        }
+    }
+}
+
+/// Connection to a client, independent of the underlying protocol
+#[async_trait::async_trait]
+pub trait Transport: Send {
+    /// Negotiated protocol version, `None` if the client did not declare one
+    fn protocol(&self) -> Option<u32>;
+
+    async fn send(&mut self, message: RemoteMessage) -> ConnResult<()>;
+
+    /// Next message of the client, `RemoteMessage::Close` when it is gone
+    ///
+    /// Must be cancel safe.
+    async fn recv(&mut self) -> ConnResult<RemoteMessage>;
+
+    async fn ping(&mut self) -> ConnResult<()>;
+
+    async fn close(&mut self, reason: CloseReason) -> ConnResult<()>;
+}
 
 pub struct RemoteConnection {
     socket: WebSocket,
+    protocol: Option<u32>,
+    encoding: Encoding,
 
     last_ping_start: Instant,
     last_ping_id: u16,
 }
 
 impl RemoteConnection {
-    pub fn new(socket: WebSocket) -> Self {
+    /// Connection speaking the negotiated `protocol`, `None` for clients
+    /// that did not declare a version
+    pub fn new(socket: WebSocket, protocol: Option<u32>, encoding: Encoding) -> Self {
         let now = Instant::now();
         Self {
             socket,
+            protocol,
+            encoding,
 
             last_ping_start: now,
             last_ping_id: 0,
         }
     }
+}
 
-    pub async fn send(&mut self, message: RemoteMessage) -> ConnResult<()> {
+/// WebSocket transport
+#[async_trait::async_trait]
+impl Transport for RemoteConnection {
+    fn protocol(&self) -> Option<u32> {
+        self.protocol
+    }
+
+    async fn send(&mut self, message: RemoteMessage) -> ConnResult<()> {
         self.socket
-            .send(Message::text(serde_json::to_string(&message)?))
+            .send(self.encoding.encode(&message)?)
             .await
             .map_err(|err| err.into())
     }
 
-    pub async fn ping(&mut self) -> ConnResult<()> {
+    async fn ping(&mut self) -> ConnResult<()> {
         let now = Instant::now();
         self.last_ping_id = self.last_ping_id.overflowing_add(1).0;
         self.last_ping_start = now;
@@ -111,10 +422,20 @@ impl RemoteConnection {
             .map_err(|err| err.into())
     }
 
-    pub async fn recv(&mut self) -> ConnResult<RemoteMessage> {
+    async fn close(&mut self, reason: CloseReason) -> ConnResult<()> {
+        self.socket
+            .send(Message::Close(Some(CloseFrame {
+                code: reason.close_code(),
+                reason: reason.message().into(),
+            })))
+            .await
+            .map_err(|err| err.into())
+    }
+
+    async fn recv(&mut self) -> ConnResult<RemoteMessage> {
         while let Some(msg) = self.socket.recv().await {
             match msg? {
-                Message::Text(msg) => return Ok(serde_json::from_str(&msg)?),
+                msg @ (Message::Text(_) | Message::Binary(_)) => return self.encoding.decode(msg),
                 Message::Close(_) => return Ok(RemoteMessage::Close),
                 Message::Pong(pong) => {
                     if pong.as_ref().try_into().map(u16::from_le_bytes).ok()
@@ -129,10 +450,175 @@ impl RemoteConnection {
                 Message::Ping(ping) => {
                     let _ = self.socket.send(Message::Pong(ping)).await;
                 }
-                msg => return Err(ConnError::UnsupportedMessageFormat(msg)),
             }
         }
 
         Ok(RemoteMessage::Close)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_errors() {
+        let msg = RemoteMessage::error(RejectReason::RoomDoesNotExist.into());
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"type":"Error","code":"ROOM_DOES_NOT_EXIST","message":"Room does not exist"}"#
+        );
+
+        let msg = RemoteMessage::error_with_context(
+            ErrorCode::RateLimited,
+            [("message_type", "Vote".to_string())],
+        );
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"Error","code":"RATE_LIMITED","message":"Too many messages","context":{"message_type":"Vote"}}"#
+        );
+        assert_eq!(serde_json::from_str::<RemoteMessage>(&json).unwrap(), msg);
+    }
+
+    fn sample_messages() -> Vec<RemoteMessage> {
+        let state: GameState = serde_json::from_str(
+            r#"{"deck":"fibonacci","open":true,"votes":{"P1":"5","P2":null}}"#,
+        )
+        .unwrap();
+        let player: PlayerState =
+            serde_json::from_str(r#"{"id":"P1","name":"Ünïcode","voter":true}"#).unwrap();
+
+        vec![
+            RemoteMessage::Vote {
+                vote: Some("13".to_string()),
+            },
+            RemoteMessage::Vote { vote: None },
+            RemoteMessage::UpdatePlayer {
+                voter: false,
+                name: None,
+            },
+            RemoteMessage::ForceOpen,
+            RemoteMessage::JoinRoom {
+                room: "blue-otter-42".to_string(),
+            },
+            RemoteMessage::Welcome {
+                player_id: "P1".to_string(),
+                protocol: PROTOCOL_VERSION,
+                features: FEATURES.iter().map(|f| f.to_string()).collect(),
+            },
+            RemoteMessage::CreateRoom {
+                deck: "custom:1,2,3".to_string(),
+                slug: None,
+            },
+            RemoteMessage::Rejected,
+            RemoteMessage::RoomCreated {
+                room: RoomId::generate(),
+                code: Some("blue-otter-42".to_string()),
+                slug: None,
+            },
+            RemoteMessage::Joined {
+                room: RoomId::generate(),
+                state: state.clone(),
+                players: vec![player.clone()],
+            },
+            RemoteMessage::PlayerJoined { player },
+            RemoteMessage::GameChanged { game_state: state },
+            RemoteMessage::StoriesChanged {
+                stories: vec![Story {
+                    key: Some("GOE-1".to_string()),
+                    title: "Login".to_string(),
+                    link: None,
+                    description: Some("Users log in".to_string()),
+                }],
+            },
+            RemoteMessage::ServerShuttingDown {
+                reconnect_after_ms: u64::MAX,
+            },
+            RemoteMessage::error(ErrorCode::NotInRoom),
+            RemoteMessage::error_with_context(
+                ErrorCode::RateLimited,
+                [("retry_after_ms", "500".to_string())],
+            ),
+        ]
+    }
+
+    fn assert_roundtrip(encoding: Encoding) {
+        for msg in sample_messages() {
+            let frame = encoding.encode(&msg).unwrap();
+            assert_eq!(
+                matches!(frame, Message::Binary(_)),
+                encoding != Encoding::Json
+            );
+            assert_eq!(encoding.decode(frame).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        assert_roundtrip(Encoding::Json);
+    }
+
+    #[test]
+    fn msgpack_roundtrip() {
+        assert_roundtrip(Encoding::MessagePack);
+    }
+
+    #[test]
+    fn cbor_roundtrip() {
+        assert_roundtrip(Encoding::Cbor);
+    }
+
+    #[test]
+    fn reject_frames_of_other_encoding() {
+        let frame = Encoding::Json.encode(&RemoteMessage::ForceOpen).unwrap();
+        assert!(matches!(
+            Encoding::Cbor.decode(frame),
+            Err(ConnError::UnsupportedMessageFormat(_))
+        ));
+        let frame = Encoding::MessagePack
+            .encode(&RemoteMessage::ForceOpen)
+            .unwrap();
+        assert!(Encoding::Cbor.decode(frame).is_err());
+    }
+
+    #[test]
+    fn parse_subprotocols() {
+        assert_eq!(
+            parse_subprotocol(&subprotocol(PROTOCOL_VERSION, Encoding::Json)),
+            Some((2, Encoding::Json))
+        );
+        assert_eq!(
+            parse_subprotocol(&subprotocol(PROTOCOL_VERSION, Encoding::Cbor)),
+            Some((2, Encoding::Cbor))
+        );
+        assert_eq!(
+            parse_subprotocol(" goe.v12.msgpack"),
+            Some((12, Encoding::MessagePack))
+        );
+        assert_eq!(parse_subprotocol("goe.v"), None);
+        assert_eq!(parse_subprotocol("goe.v2.xml"), None);
+        assert_eq!(parse_subprotocol("graphql-ws"), None);
+    }
+
+    #[test]
+    fn negotiate_versions() {
+        use Encoding::*;
+
+        assert_eq!(negotiate_protocol([]), None);
+        assert_eq!(negotiate_protocol([(2, Json)]), Some((2, Json)));
+        assert_eq!(negotiate_protocol([(1, Json)]), Some((1, Json)));
+        assert_eq!(
+            negotiate_protocol([(1, Json), (2, Cbor), (99, Json)]),
+            Some((2, Cbor))
+        );
+        assert_eq!(
+            negotiate_protocol([(2, MessagePack), (2, Json)]),
+            Some((2, MessagePack))
+        );
+        assert_eq!(
+            negotiate_protocol([(99, Json), (1, Json)]),
+            Some((99, Json))
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Deletion of rooms that were not used for a long time
+//!
+//! Stored events contain player IDs, so they should not be kept forever. A
+//! background task purges the events and aliases of rooms without events for
+//! the configured time. Rooms hosted by this instance are always kept, even
+//! when nobody joined them for a while.
+
+use log::{info, warn};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+use time::OffsetDateTime;
+use tokio::sync::oneshot;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::game_server::{GameServerAddr, GameServerMessage};
+use crate::ports::{DbResult, PurgedRooms, RoomRetentionRepositoryRef};
+
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Time without events after which a room is purged
+    pub max_inactivity: Duration,
+    /// Time between purges
+    pub interval: Duration,
+    /// Only count and log the rooms that would be purged
+    pub dry_run: bool,
+}
+
+#[derive(Clone)]
+struct RetentionMetrics {
+    purged_rooms: Counter,
+    purged_events: Counter,
+    purgeable_rooms: Gauge,
+    purgeable_events: Gauge,
+}
+
+impl RetentionMetrics {
+    fn new(registry: &mut Registry) -> Self {
+        let metrics = Self {
+            purged_rooms: Counter::default(),
+            purged_events: Counter::default(),
+            purgeable_rooms: Gauge::default(),
+            purgeable_events: Gauge::default(),
+        };
+        registry.register(
+            "retention_purged_rooms",
+            "Number of rooms deleted because they were inactive",
+            metrics.purged_rooms.clone(),
+        );
+        registry.register(
+            "retention_purged_events",
+            "Number of events of deleted rooms",
+            metrics.purged_events.clone(),
+        );
+        registry.register(
+            "retention_purgeable_rooms",
+            "Number of rooms the last dry run would have deleted",
+            metrics.purgeable_rooms.clone(),
+        );
+        registry.register(
+            "retention_purgeable_events",
+            "Number of events the last dry run would have deleted",
+            metrics.purgeable_events.clone(),
+        );
+        metrics
+    }
+
+    fn record(&self, purged: PurgedRooms, dry_run: bool) {
+        if dry_run {
+            self.purgeable_rooms.set(purged.rooms as i64);
+            self.purgeable_events.set(purged.events as i64);
+        } else {
+            self.purged_rooms.inc_by(purged.rooms);
+            self.purged_events.inc_by(purged.events);
+        }
+    }
+}
+
+pub struct Retention {
+    repo: RoomRetentionRepositoryRef,
+    game_server: GameServerAddr,
+    config: RetentionConfig,
+}
+
+impl Retention {
+    pub fn new(
+        repo: RoomRetentionRepositoryRef,
+        game_server: GameServerAddr,
+        config: RetentionConfig,
+    ) -> Self {
+        Self {
+            repo,
+            game_server,
+            config,
+        }
+    }
+
+    /// Purge periodically in the background, starting now
+    pub fn start(self, registry: &mut Registry) {
+        let metrics = RetentionMetrics::new(registry);
+        tokio::spawn(async move {
+            let mut interval = interval(self.config.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match self.purge().await {
+                    Ok(purged) => metrics.record(purged, self.config.dry_run),
+                    Err(err) => warn!("Failed to purge inactive rooms: {}", err),
+                }
+            }
+        });
+    }
+
+    /// Purge inactive rooms, except the live rooms of this instance
+    pub async fn purge(&self) -> DbResult<PurgedRooms> {
+        let (reply, live_rooms) = oneshot::channel();
+        let message = GameServerMessage::GetLiveRoomIds { reply };
+        if self.game_server.send(message).await.is_err() {
+            // game server is shutting down, live rooms are unknown
+            return Ok(PurgedRooms::default());
+        }
+        let Ok(live_rooms) = live_rooms.await else {
+            return Ok(PurgedRooms::default());
+        };
+
+        let inactive_since = OffsetDateTime::now_utc() - self.config.max_inactivity;
+        let purged = self
+            .repo
+            .purge_inactive_rooms(inactive_since, &live_rooms, self.config.dry_run)
+            .await?;
+        if self.config.dry_run {
+            info!(
+                "Dry run: would purge {} inactive rooms with {} events",
+                purged.rooms, purged.events
+            );
+        } else if purged.rooms > 0 {
+            info!(
+                "Purged {} inactive rooms with {} events",
+                purged.rooms, purged.events
+            );
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::adapters::memory::MemoryRepository;
+    use crate::ports::RoomRepository;
+    use crate::room::RoomEvent;
+    use crate::room_id::RoomId;
+
+    /// Game server that only answers with `live_rooms`
+    fn game_server(live_rooms: Vec<RoomId>) -> GameServerAddr {
+        let (tx, mut rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                if let GameServerMessage::GetLiveRoomIds { reply } = message {
+                    let _ = reply.send(live_rooms.clone());
+                }
+            }
+        });
+        tx
+    }
+
+    #[tokio::test]
+    async fn purge_inactive_rooms_except_live_rooms() {
+        let repo = Arc::new(MemoryRepository::default());
+        let (inactive, live) = (RoomId::generate(), RoomId::generate());
+        for room in [inactive, live] {
+            let event = RoomEvent::Created {
+                deck: "fibonacci".to_string(),
+            };
+            repo.append_room_event(&room, event).await.unwrap();
+        }
+        let config = RetentionConfig {
+            max_inactivity: Duration::ZERO,
+            interval: Duration::from_secs(60),
+            dry_run: false,
+        };
+        let retention = Retention::new(repo.clone(), game_server(vec![live]), config);
+
+        // ACT
+        let purged = retention.purge().await.unwrap();
+
+        // ASSERT
+        assert_eq!(
+            purged,
+            PurgedRooms {
+                rooms: 1,
+                events: 1
+            }
+        );
+        assert_eq!(repo.get_room_events(&inactive).await.unwrap(), []);
+        assert_eq!(repo.get_room_events(&live).await.unwrap().len(), 1);
+    }
+}
@@ -1,18 +1,21 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-use base64::Engine;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
 use tokio::time::{sleep, Duration};
-use uuid::Uuid;
 
 use uactor::blocking::{Actor, ActorContext, Addr};
 use uactor::tokio::blocking::Context;
 
+use crate::game_server::CreatedRoom;
 use crate::player::{PlayerAddr, PlayerInformation};
 use crate::ports::{DbError, RoomRepositoryRef};
+use crate::room_id::RoomId;
+use crate::story::Story;
+use crate::webhook::{Subscription, Webhook, WebhookEvent, Webhooks};
 
 #[derive(Debug)]
 pub enum RoomMessage {
@@ -26,17 +29,43 @@ pub enum RoomMessage {
     },
     ForceOpen,
     Restart,
+    SetStories(Vec<Story>),
     Close,
 
+    // admin
+    GetInfo(oneshot::Sender<RoomInfo>),
+    GetHistory(oneshot::Sender<Vec<Round>>),
+    Notice(String),
+    /// Send the events of the room to a webhook, replies `false` when it
+    /// could not be stored
+    Subscribe(Webhook, oneshot::Sender<bool>),
+    /// Remove all webhooks of the room
+    Unsubscribe(oneshot::Sender<bool>),
+    Shutdown,
+
+    // cluster
+    /// Another instance hosts the room now, its players join there again
+    HandOver,
+
     // internal
     CloseWhenEmpty,
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
 pub enum RejectReason {
     RoomDoesNotExist,
     CreateGameError,
     JoinGameError,
+    ServerShuttingDown,
+    InvalidSlug,
+    SlugTaken,
+}
+
+/// Why a message of a player was ignored
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum DropReason {
+    VoteWhileOpen,
+    NonVoterVote,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -44,6 +73,27 @@ pub struct GameState {
     deck: String,
     open: bool,
     votes: HashMap<String, Option<String>>,
+    /// Story that is estimated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    story: Option<Story>,
+}
+
+impl GameState {
+    pub fn deck(&self) -> &str {
+        &self.deck
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn votes(&self) -> &HashMap<String, Option<String>> {
+        &self.votes
+    }
+
+    pub fn story(&self) -> Option<&Story> {
+        self.story.as_ref()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -53,17 +103,161 @@ pub struct PlayerState {
     voter: bool,
 }
 
+impl PlayerState {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn is_voter(&self) -> bool {
+        self.voter
+    }
+}
+
+/// Revealed votes of a finished round
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Round {
+    number: u32,
+    /// Unix timestamp in seconds
+    revealed_at: u64,
+    state: GameState,
+    players: Vec<PlayerState>,
+}
+
+impl Round {
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn revealed_at(&self) -> u64 {
+        self.revealed_at
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    pub fn players(&self) -> &[PlayerState] {
+        &self.players
+    }
+
+    pub fn stats(&self) -> VoteStats {
+        VoteStats::of(&self.state)
+    }
+}
+
+/// Numeric value of a card
+fn parse_vote(vote: &str) -> Option<f64> {
+    match vote {
+        "½" => Some(0.5),
+        vote => vote.parse().ok(),
+    }
+}
+
+/// Statistics of the votes of a round
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VoteStats {
+    pub votes: usize,
+    /// All votes are the same
+    pub consensus: bool,
+    /// Statistics of votes that are numbers
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    /// Consensus or most common number, the larger one on ties
+    pub estimate: Option<String>,
+}
+
+impl VoteStats {
+    pub fn of(state: &GameState) -> Self {
+        let votes: Vec<&str> = state
+            .votes()
+            .values()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        let mut numbers: Vec<f64> = votes.iter().filter_map(|vote| parse_vote(vote)).collect();
+        numbers.sort_by(f64::total_cmp);
+
+        let median = match numbers.len() {
+            0 => None,
+            n if n % 2 == 1 => Some(numbers[n / 2]),
+            n => Some((numbers[n / 2 - 1] + numbers[n / 2]) / 2.0),
+        };
+        let consensus = !votes.is_empty() && votes.iter().all(|vote| *vote == votes[0]);
+        let mut counts: Vec<(&str, f64, usize)> = vec![];
+        for vote in &votes {
+            let Some(number) = parse_vote(vote) else {
+                continue;
+            };
+            match counts.iter_mut().find(|(other, _, _)| other == vote) {
+                Some((_, _, count)) => *count += 1,
+                None => counts.push((vote, number, 1)),
+            }
+        }
+        let estimate = if consensus {
+            Some(votes[0].to_string())
+        } else {
+            counts
+                .into_iter()
+                .max_by(|a, b| a.2.cmp(&b.2).then(a.1.total_cmp(&b.1)))
+                .map(|(vote, _, _)| vote.to_string())
+        };
+
+        Self {
+            votes: votes.len(),
+            consensus,
+            min: numbers.first().copied(),
+            max: numbers.last().copied(),
+            mean: (!numbers.is_empty()).then(|| numbers.iter().sum::<f64>() / numbers.len() as f64),
+            median,
+            estimate,
+        }
+    }
+}
+
+/// Rounds a room keeps in its history
+const MAX_ROUNDS: usize = 100;
+
+/// Snapshot of a live room for administration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub id: RoomId,
+    pub state: GameState,
+    pub players: Vec<PlayerState>,
+    pub stories: Vec<Story>,
+    pub age: Duration,
+    pub webhooks: Vec<Subscription>,
+}
+
 #[derive(Debug, Clone)]
 pub enum GamePlayerMessage {
     // join mgmt
-    Welcome(String, RoomAddr, GameState, Vec<PlayerState>),
+    AliasResolved {
+        alias: String,
+        room: RoomId,
+    },
+    Welcome(RoomId, RoomAddr, GameState, Vec<PlayerState>),
+    RoomCreated(CreatedRoom),
     Rejected(RejectReason),
+    Dropped(DropReason),
 
     // room state sync
     PlayerJoined(PlayerState),
     PlayerChanged(PlayerState),
     PlayerLeft(String),
     GameStateChanged(GameState),
+    StoriesChanged(Vec<Story>),
+
+    // server
+    Notice(String),
+    RoomClosed,
+    /// Room is hosted by another instance now and has to be joined again
+    RoomMoved,
 }
 
 #[derive(Clone)]
@@ -93,17 +287,30 @@ impl GamePlayer {
 }
 
 pub struct Room {
-    id: String,
+    id: RoomId,
     deck: String,
     players: HashMap<String, GamePlayer>,
     open: bool,
+    rounds: Vec<Round>,
+    stories: Vec<Story>,
+    /// Index of the story that is estimated
+    current_story: usize,
     repo: RoomRepositoryRef,
+    webhooks: Webhooks,
+    /// Webhooks for the events of this room
+    subscriptions: Vec<Webhook>,
+    created_at: Instant,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RoomEvent {
     Created { deck: String },
     PlayerJoined { player_id: String },
     PlayerLeaved { player_id: String },
+    StoriesChanged { stories: Vec<Story> },
+    RoundRevealed { round: Round },
+    // only the URLs, secrets of webhooks are not part of the event log
+    WebhooksChanged { urls: Vec<String> },
 }
 
 async fn delayed_message<T: Debug>(addr: Addr<T>, msg: T, duration: Duration) {
@@ -112,13 +319,19 @@ async fn delayed_message<T: Debug>(addr: Addr<T>, msg: T, duration: Duration) {
 }
 
 impl Room {
-    pub async fn new(id: &str, deck: String, repo: RoomRepositoryRef) -> Result<Self, DbError> {
+    pub async fn new(id: RoomId, deck: String, repo: RoomRepositoryRef) -> Result<Self, DbError> {
         let self_ = Self {
-            id: id.to_string(),
+            id,
             players: HashMap::new(),
             open: false,
+            rounds: vec![],
+            stories: vec![],
+            current_story: 0,
             deck,
             repo,
+            webhooks: Webhooks::default(),
+            subscriptions: vec![],
+            created_at: Instant::now(),
         };
 
         self_
@@ -135,7 +348,7 @@ impl Room {
         Ok(self_)
     }
 
-    pub fn restore(id: &str, events: Vec<RoomEvent>, repo: RoomRepositoryRef) -> Option<Self> {
+    pub fn restore(id: RoomId, events: Vec<RoomEvent>, repo: RoomRepositoryRef) -> Option<Self> {
         let mut iter = events.into_iter();
 
         let deck = if let Some(RoomEvent::Created { deck }) = iter.next() {
@@ -144,6 +357,10 @@ impl Room {
             return None;
         };
 
+        let mut stories = vec![];
+        let mut rounds = vec![];
+        let mut current_story = 0;
+        let mut subscriptions = vec![];
         for evt in iter {
             match evt {
                 RoomEvent::Created { .. } => {
@@ -152,20 +369,94 @@ impl Room {
                 RoomEvent::PlayerJoined { .. } | RoomEvent::PlayerLeaved { .. } => {
                     // ignored: player must join again
                 }
+                RoomEvent::StoriesChanged { stories: changed } => {
+                    stories = changed;
+                    current_story = 0;
+                }
+                RoomEvent::RoundRevealed { round } => {
+                    rounds.push(round);
+                    current_story += 1;
+                }
+                RoomEvent::WebhooksChanged { urls } => {
+                    subscriptions = urls
+                        .into_iter()
+                        .map(|url| Webhook { url, secret: None })
+                        .collect();
+                }
             }
         }
+        // restored rooms start with a new round
+        if rounds.len() > MAX_ROUNDS {
+            rounds.drain(..rounds.len() - MAX_ROUNDS);
+        }
 
         Some(Self {
-            id: id.to_string(),
+            id,
             players: HashMap::default(),
             open: false,
+            rounds,
+            stories,
+            current_story,
             deck,
             repo,
+            webhooks: Webhooks::default(),
+            subscriptions,
+            created_at: Instant::now(),
         })
     }
 
-    pub fn gen_id() -> String {
-        URL_SAFE_NO_PAD.encode(Uuid::now_v7().as_bytes())
+    /// Revealed rounds in an event log, `None` if it is not one of a room
+    pub fn persisted_rounds(events: Vec<RoomEvent>) -> Option<Vec<Round>> {
+        let mut iter = events.into_iter();
+        if !matches!(iter.next(), Some(RoomEvent::Created { .. })) {
+            return None;
+        }
+        Some(
+            iter.filter_map(|evt| match evt {
+                RoomEvent::RoundRevealed { round } => Some(round),
+                _ => None,
+            })
+            .collect(),
+        )
+    }
+
+    /// History of a room that is not live, as it would be after a restore
+    pub fn persisted_history(events: Vec<RoomEvent>) -> Option<Vec<Round>> {
+        let mut rounds = Self::persisted_rounds(events)?;
+        if rounds.len() > MAX_ROUNDS {
+            rounds.drain(..rounds.len() - MAX_ROUNDS);
+        }
+        Some(rounds)
+    }
+
+    /// URLs of the webhooks in an event log, `None` if it is not one of a
+    /// room
+    pub fn persisted_webhooks(events: Vec<RoomEvent>) -> Option<Vec<String>> {
+        let mut iter = events.into_iter();
+        if !matches!(iter.next(), Some(RoomEvent::Created { .. })) {
+            return None;
+        }
+        Some(
+            iter.filter_map(|evt| match evt {
+                RoomEvent::WebhooksChanged { urls } => Some(urls),
+                _ => None,
+            })
+            .last()
+            .unwrap_or_default(),
+        )
+    }
+
+    pub fn with_webhooks(mut self, webhooks: Webhooks) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Add the secrets of the restored webhooks, by URL
+    pub fn with_webhook_secrets(mut self, mut secrets: HashMap<String, String>) -> Self {
+        for webhook in &mut self.subscriptions {
+            webhook.secret = secrets.remove(&webhook.url);
+        }
+        self
     }
 
     async fn send_to_player(&mut self, player: &GamePlayer, msg: GamePlayerMessage) {
@@ -220,10 +511,18 @@ impl Room {
         let players_state = self.players.values().map(|p| p.to_state()).collect();
         self.send_to_player(
             &game_player,
-            GamePlayerMessage::Welcome(self.id.clone(), ctx.addr(), self.to_state(), players_state),
+            GamePlayerMessage::Welcome(self.id, ctx.addr(), self.to_state(), players_state),
         )
         .await;
 
+        if !self.stories.is_empty() {
+            self.send_to_player(
+                &game_player,
+                GamePlayerMessage::StoriesChanged(self.stories.clone()),
+            )
+            .await;
+        }
+
         // introduce
         self.send_to_players(GamePlayerMessage::PlayerJoined(game_player_state.clone()))
             .await;
@@ -260,20 +559,25 @@ impl Room {
         }
     }
 
-    async fn set_vote(&mut self, player_id: &str, vote: Option<String>) {
-        if self.open {
-            warn!(
-                "{}: Discared vote of {} because cards are open",
-                self.id, player_id
-            );
-            return;
-        }
+    async fn drop_message(player: &GamePlayer, reason: DropReason) {
+        let _ = player.addr.send(GamePlayerMessage::Dropped(reason)).await;
+    }
 
+    async fn set_vote(&mut self, player_id: &str, vote: Option<String>) {
         if let Some(player) = self.players.get_mut(player_id) {
+            if self.open {
+                warn!(
+                    "{}: Discared vote of {} because cards are open",
+                    self.id, player_id
+                );
+                Self::drop_message(player, DropReason::VoteWhileOpen).await;
+                return;
+            }
             if player.info.voter {
                 player.vote = vote;
             } else {
                 warn!("{}: Non-voter {} voted", self.id, player_id);
+                Self::drop_message(player, DropReason::NonVoterVote).await;
                 return;
             }
         } else {
@@ -284,11 +588,11 @@ impl Room {
             return;
         }
 
-        self.update_state();
+        self.update_state().await;
         self.send_game_state().await;
     }
 
-    fn update_state(&mut self) -> bool {
+    async fn update_state(&mut self) -> bool {
         let mut change = false;
 
         let all_voted = self
@@ -303,7 +607,9 @@ impl Room {
                 .filter(|player| player.info.voter)
                 .count();
             if voters > 1 {
-                self.open = true;
+                if !self.open {
+                    self.reveal().await;
+                }
                 change = true
             }
         }
@@ -312,7 +618,7 @@ impl Room {
     }
 
     async fn update_state_and_send(&mut self) {
-        if self.update_state() {
+        if self.update_state().await {
             self.send_game_state().await;
         }
     }
@@ -324,12 +630,64 @@ impl Room {
 
     async fn force_open(&mut self) {
         if !self.open {
-            self.open = true;
+            self.reveal().await;
             self.send_game_state().await;
         }
     }
 
+    /// Open the cards and remember the votes
+    async fn reveal(&mut self) {
+        self.open = true;
+        if self.rounds.len() >= MAX_ROUNDS {
+            self.rounds.remove(0);
+        }
+        let round = Round {
+            number: self.rounds.last().map_or(1, |round| round.number + 1),
+            revealed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            state: self.to_state(),
+            players: self.players.values().map(|p| p.to_state()).collect(),
+        };
+        if let Err(err) = self
+            .repo
+            .append_room_event(
+                &self.id,
+                RoomEvent::RoundRevealed {
+                    round: round.clone(),
+                },
+            )
+            .await
+        {
+            warn!("Suppressed database error: {}", err);
+        }
+
+        self.webhooks.emit(
+            self.id,
+            &self.subscriptions,
+            WebhookEvent::RoundRevealed {
+                stats: round.stats(),
+                round: Box::new(round.clone()),
+            },
+        );
+        self.rounds.push(round);
+    }
+
+    fn end_session(&self) {
+        self.webhooks.emit(
+            self.id,
+            &self.subscriptions,
+            WebhookEvent::SessionEnded {
+                rounds: self.rounds.last().map_or(0, |round| round.number),
+            },
+        );
+    }
+
     async fn restart(&mut self) {
+        if self.open && self.current_story < self.stories.len() {
+            // continue with the next story
+            self.current_story += 1;
+        }
         self.open = false;
         for player in self.players.values_mut() {
             player.vote = None;
@@ -337,6 +695,49 @@ impl Room {
         self.send_game_state().await;
     }
 
+    async fn set_stories(&mut self, stories: Vec<Story>) {
+        if let Err(err) = self
+            .repo
+            .append_room_event(
+                &self.id,
+                RoomEvent::StoriesChanged {
+                    stories: stories.clone(),
+                },
+            )
+            .await
+        {
+            warn!("Suppressed database error: {}", err);
+        }
+
+        info!(
+            "{}: Replaced stories with {} stories",
+            self.id,
+            stories.len()
+        );
+        // start with the first of the new stories, like a restored room
+        self.stories = stories;
+        self.current_story = 0;
+        self.send_to_players(GamePlayerMessage::StoriesChanged(self.stories.clone()))
+            .await;
+        self.send_game_state().await;
+    }
+
+    /// Store the webhooks with the room, so they are restored with it
+    async fn set_subscriptions(&mut self, subscriptions: Vec<Webhook>) -> bool {
+        let event = RoomEvent::WebhooksChanged {
+            urls: subscriptions
+                .iter()
+                .map(|webhook| webhook.url.clone())
+                .collect(),
+        };
+        if let Err(err) = self.repo.append_room_event(&self.id, event).await {
+            warn!("{}: Failed to store webhooks: {}", self.id, err);
+            return false;
+        }
+        self.subscriptions = subscriptions;
+        true
+    }
+
     async fn update_player(&mut self, id: &str, name: Option<String>, voter: bool) {
         if let Some(player) = self.players.get_mut(id) {
             player.info.voter = voter;
@@ -351,6 +752,17 @@ impl Room {
         }
     }
 
+    fn to_info(&self) -> RoomInfo {
+        RoomInfo {
+            id: self.id,
+            state: self.to_state(),
+            players: self.players.values().map(|p| p.to_state()).collect(),
+            stories: self.stories.clone(),
+            age: self.created_at.elapsed(),
+            webhooks: self.subscriptions.iter().map(Subscription::from).collect(),
+        }
+    }
+
     fn to_state(&self) -> GameState {
         GameState {
             deck: self.deck.clone(),
@@ -369,6 +781,7 @@ impl Room {
                     (p.info.id.clone(), vote)
                 })
                 .collect(),
+            story: self.stories.get(self.current_story).cloned(),
         }
     }
 }
@@ -389,16 +802,51 @@ impl Actor for Room {
             RoomMessage::PlayerVoted(player_id, vote) => self.set_vote(&player_id, vote).await,
             RoomMessage::ForceOpen => self.force_open().await,
             RoomMessage::Restart => self.restart().await,
+            RoomMessage::SetStories(stories) => self.set_stories(stories).await,
             RoomMessage::UpdatePlayer { id, name, voter } => {
                 self.update_player(&id, name, voter).await
             }
             RoomMessage::Close => {
                 info!("{}: Forced close", self.id);
+                self.send_to_players(GamePlayerMessage::RoomClosed).await;
+                self.end_session();
+                ctx.force_quit()
+            }
+            RoomMessage::GetInfo(reply) => {
+                let _ = reply.send(self.to_info());
+            }
+            RoomMessage::GetHistory(reply) => {
+                let _ = reply.send(self.rounds.clone());
+            }
+            RoomMessage::Subscribe(webhook, reply) => {
+                let mut subscriptions = self.subscriptions.clone();
+                subscriptions.retain(|existing| existing.url != webhook.url);
+                subscriptions.push(webhook);
+                let _ = reply.send(self.set_subscriptions(subscriptions).await);
+            }
+            RoomMessage::Unsubscribe(reply) => {
+                let _ = reply.send(self.set_subscriptions(vec![]).await);
+            }
+            RoomMessage::Notice(message) => {
+                self.send_to_players(GamePlayerMessage::Notice(message))
+                    .await
+            }
+            RoomMessage::Shutdown => {
+                // events of previous messages are queued, the server writes
+                // them before it exits
+                info!("{}: Shut down", self.id);
+                ctx.force_quit()
+            }
+            RoomMessage::HandOver => {
+                // the session goes on at the new owner
+                info!("{}: Hand over to other instance", self.id);
+                self.send_to_players(GamePlayerMessage::RoomMoved).await;
                 ctx.force_quit()
             }
             RoomMessage::CloseWhenEmpty => {
                 if self.players.is_empty() {
                     info!("{}: closed because it's empty", self.id);
+                    self.end_session();
                     ctx.force_quit()
                 }
             }
@@ -410,7 +858,7 @@ impl Actor for Room {
         let game_state = self.to_state();
 
         self.send_to_players(GamePlayerMessage::Welcome(
-            self.id.clone(),
+            self.id,
             ctx.addr(),
             game_state.clone(),
             players_state.clone(),
@@ -425,12 +873,27 @@ mod tests {
 
     use tokio::sync::mpsc;
 
+    use crate::adapters::memory::MemoryRepository;
     use crate::ports::{DbResult, RoomRepository};
     use crate::room::GamePlayerMessage::GameStateChanged;
     use crate::room::RoomMessage::*;
 
     use super::*;
 
+    fn game_state(votes: &[Option<&str>]) -> GameState {
+        let votes: HashMap<String, Option<String>> = votes
+            .iter()
+            .enumerate()
+            .map(|(i, vote)| (i.to_string(), vote.map(str::to_string)))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "deck": "fibonacci",
+            "open": true,
+            "votes": votes,
+        }))
+        .unwrap()
+    }
+
     struct RoomTester {
         players: Vec<mpsc::Receiver<GamePlayerMessage>>,
         room_addr: Addr<RoomMessage>,
@@ -440,11 +903,11 @@ mod tests {
 
     #[async_trait::async_trait]
     impl RoomRepository for FakeRoomRepository {
-        async fn append_room_event(&self, _id: &str, _evt: RoomEvent) -> DbResult<()> {
+        async fn append_room_event(&self, _id: &RoomId, _evt: RoomEvent) -> DbResult<()> {
             Ok(())
         }
 
-        async fn get_room_events(&self, _id: &str) -> DbResult<Vec<RoomEvent>> {
+        async fn get_room_events(&self, _id: &RoomId) -> DbResult<Vec<RoomEvent>> {
             Ok(vec![])
         }
     }
@@ -452,7 +915,7 @@ mod tests {
     impl RoomTester {
         pub async fn new_room() -> Self {
             let repo: RoomRepositoryRef = Arc::new(FakeRoomRepository);
-            let room = Room::new("TEST-ROOM", "TEST-DECK".to_string(), repo).await;
+            let room = Room::new(RoomId::generate(), "TEST-DECK".to_string(), repo).await;
             let room_addr = room.unwrap().start();
             Self {
                 players: vec![],
@@ -594,4 +1057,259 @@ mod tests {
         assert_no_message!(
             rxs[0], GameStateChanged(ref state) if state.votes.get("p1").cloned().flatten().is_some());
     }
+
+    #[tokio::test]
+    async fn check_dropped_votes_are_reported() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("v1", true).await;
+        tester.join_player("n1", false).await;
+
+        // ACT
+        tester.send_vote("n1", Some("VOTE")).await;
+        tester.force_open().await;
+        tester.send_vote("v1", Some("VOTE")).await;
+        let mut rxs = tester.close().await;
+
+        // ASSERT
+        test_for_message!(
+            rxs[0],
+            GamePlayerMessage::Dropped(DropReason::VoteWhileOpen)
+        );
+        test_for_message!(rxs[1], GamePlayerMessage::Dropped(DropReason::NonVoterVote));
+    }
+
+    #[test]
+    fn compute_vote_stats() {
+        let stats = VoteStats::of(&game_state(&[
+            Some("1"),
+            Some("½"),
+            Some("8"),
+            Some("?"),
+            None,
+        ]));
+        assert_eq!(stats.votes, 4);
+        assert!(!stats.consensus);
+        assert_eq!(stats.min, Some(0.5));
+        assert_eq!(stats.max, Some(8.0));
+        assert_eq!(stats.mean, Some(9.5 / 3.0));
+        assert_eq!(stats.median, Some(1.0));
+        assert_eq!(stats.estimate.as_deref(), Some("8"));
+
+        let stats = VoteStats::of(&game_state(&[Some("3"), Some("5"), Some("?"), Some("5")]));
+        assert_eq!(stats.estimate.as_deref(), Some("5"));
+
+        let stats = VoteStats::of(&game_state(&[Some("?"), Some("?")]));
+        assert!(stats.consensus);
+        assert_eq!(stats.mean, None);
+        assert_eq!(stats.estimate.as_deref(), Some("?"));
+    }
+
+    #[tokio::test]
+    async fn check_revealed_rounds_are_recorded() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+        tester.join_player("2", true).await;
+
+        // ACT
+        tester.send_vote("1", Some("3")).await;
+        tester.send_vote("2", Some("5")).await;
+        tester.send(Restart).await;
+        tester.send_vote("1", Some("8")).await;
+        tester.force_open().await;
+        tester.force_open().await;
+        let (tx, rx) = oneshot::channel();
+        tester.send(GetHistory(tx)).await;
+        let rounds = rx.await.unwrap();
+
+        // ASSERT
+        assert_eq!(rounds.len(), 2);
+        assert_eq!(rounds[0].number(), 1);
+        assert_eq!(rounds[0].state().votes()["2"].as_deref(), Some("5"));
+        assert_eq!(rounds[0].players().len(), 2);
+        assert_eq!(rounds[1].number(), 2);
+        assert_eq!(rounds[1].state().votes()["1"].as_deref(), Some("8"));
+        assert_eq!(rounds[1].state().votes()["2"], None);
+    }
+
+    #[tokio::test]
+    async fn check_stories_are_sent_to_players() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+        let stories = vec![Story {
+            key: Some("GOE-1".to_string()),
+            title: "Login".to_string(),
+            link: None,
+            description: None,
+        }];
+
+        // ACT
+        tester.send(SetStories(stories.clone())).await;
+        tester.join_player("2", true).await;
+        let mut rxs = tester.close().await;
+
+        // ASSERT
+        test_for_message!(rxs[0], GamePlayerMessage::StoriesChanged(ref s) if s == &stories);
+        test_for_message!(rxs[1], GamePlayerMessage::StoriesChanged(ref s) if s == &stories);
+    }
+
+    #[tokio::test]
+    async fn check_rounds_follow_stories() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+        let story = |title: &str| Story {
+            key: None,
+            title: title.to_string(),
+            link: None,
+            description: None,
+        };
+        tester
+            .send(SetStories(vec![story("First"), story("Second")]))
+            .await;
+
+        // ACT
+        tester.force_open().await;
+        tester.send(Restart).await;
+        tester.force_open().await;
+        tester.send(Restart).await;
+        tester.force_open().await;
+        let (tx, rx) = oneshot::channel();
+        tester.send(GetHistory(tx)).await;
+        let rounds = rx.await.unwrap();
+
+        // ASSERT
+        let titles: Vec<Option<&str>> = rounds
+            .iter()
+            .map(|round| round.state().story().map(|story| story.title.as_str()))
+            .collect();
+        assert_eq!(titles, vec![Some("First"), Some("Second"), None]);
+    }
+
+    #[tokio::test]
+    async fn check_imported_stories_start_with_the_first() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+        let story = |title: &str| Story {
+            key: None,
+            title: title.to_string(),
+            link: None,
+            description: None,
+        };
+        tester
+            .send(SetStories(vec![story("First"), story("Second")]))
+            .await;
+        tester.force_open().await;
+        tester.send(Restart).await;
+
+        // ACT
+        tester.send(SetStories(vec![story("Imported")])).await;
+        tester.force_open().await;
+        let (tx, rx) = oneshot::channel();
+        tester.send(GetHistory(tx)).await;
+        let rounds = rx.await.unwrap();
+
+        // ASSERT
+        let titles: Vec<Option<&str>> = rounds
+            .iter()
+            .map(|round| round.state().story().map(|story| story.title.as_str()))
+            .collect();
+        assert_eq!(titles, vec![Some("First"), Some("Imported")]);
+    }
+
+    #[tokio::test]
+    async fn check_shutdown_does_not_kick_players() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+
+        // ACT
+        tester.send(Shutdown).await;
+        tester.room_addr.closed().await;
+
+        // ASSERT
+        assert_no_message!(tester.players[0], GamePlayerMessage::RoomClosed);
+    }
+
+    #[tokio::test]
+    async fn check_info_of_room() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+        tester.join_player("2", false).await;
+        tester.send_vote("1", Some("5")).await;
+
+        // ACT
+        let (tx, rx) = oneshot::channel();
+        tester.send(GetInfo(tx)).await;
+        let info = rx.await.unwrap();
+
+        // ASSERT
+        assert_eq!(info.state.deck(), "TEST-DECK");
+        assert!(!info.state.is_open());
+        assert_eq!(info.state.votes().len(), 1);
+        assert_eq!(info.players.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn check_notice_is_sent_to_players() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+        tester.join_player("2", false).await;
+
+        // ACT
+        tester.send(Notice("Maintenance".to_string())).await;
+        let mut rxs = tester.close().await;
+
+        // ASSERT
+        test_for_message!(rxs[0], GamePlayerMessage::Notice(ref m) if m == "Maintenance");
+        test_for_message!(rxs[1], GamePlayerMessage::Notice(ref m) if m == "Maintenance");
+    }
+
+    #[tokio::test]
+    async fn check_events_are_sent_to_restored_subscriptions() {
+        let repo = Arc::new(MemoryRepository::default());
+        let id = RoomId::generate();
+        let room = Room::new(id, "TEST-DECK".to_string(), repo.clone())
+            .await
+            .unwrap()
+            .start();
+        let webhook = Webhook {
+            url: "http://localhost/hook".to_string(),
+            secret: Some("s3cr3t".to_string()),
+        };
+        let (tx, rx) = oneshot::channel();
+        room.send(Subscribe(webhook.clone(), tx)).await.unwrap();
+        assert!(rx.await.unwrap());
+        room.send(Close).await.unwrap();
+        room.closed().await;
+        let (webhooks, mut events) = Webhooks::recording();
+        let stored = repo.get_room_events(&id).await.unwrap();
+        let secrets = HashMap::from([(webhook.url.clone(), "s3cr3t".to_string())]);
+        let restored = Room::restore(id, stored.clone(), repo)
+            .unwrap()
+            .with_webhooks(webhooks)
+            .with_webhook_secrets(secrets)
+            .start();
+
+        // ACT
+        restored.send(ForceOpen).await.unwrap();
+
+        // ASSERT
+        let (room_id, subscriptions, event) = events.recv().await.unwrap();
+        assert_eq!(room_id, id);
+        assert_eq!(subscriptions, [webhook.clone()]);
+        assert!(matches!(event, WebhookEvent::RoundRevealed { .. }));
+        // the secret is not stored with the room
+        let urls = vec![webhook.url];
+        assert!(stored.contains(&RoomEvent::WebhooksChanged { urls }));
+    }
+
+    #[tokio::test]
+    async fn check_close_kicks_players() {
+        let mut tester = RoomTester::new_room().await;
+        tester.join_player("1", true).await;
+
+        // ACT
+        let mut rxs = tester.close().await;
+
+        // ASSERT
+        test_for_message!(rxs[0], GamePlayerMessage::RoomClosed);
+    }
 }
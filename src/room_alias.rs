@@ -0,0 +1,97 @@
+//! Human-friendly aliases for room IDs
+//!
+//! Rooms get a generated join code like `blue-otter-42` and optionally a
+//! custom slug chosen at creation. Both resolve to the canonical room ID.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "bold", "brave", "bright", "calm", "clever", "cool", "cosy", "crisp", "curly",
+    "daring", "eager", "early", "fancy", "fast", "fluffy", "fresh", "funny", "gentle", "giant",
+    "golden", "green", "happy", "humble", "jolly", "kind", "lazy", "lively", "lucky", "mellow",
+    "merry", "mighty", "misty", "noble", "odd", "polite", "proud", "purple", "quick", "quiet",
+    "rapid", "red", "rosy", "royal", "rusty", "shiny", "silent", "silver", "sleepy", "smart",
+    "snowy", "solid", "sunny", "swift", "tidy", "tiny", "vivid", "warm", "wild", "wise", "witty",
+    "yellow", "young", "zesty",
+];
+
+const ANIMALS: &[&str] = &[
+    "badger", "beaver", "bison", "camel", "cat", "cobra", "crane", "crow", "deer", "dingo", "dog",
+    "dolphin", "donkey", "eagle", "falcon", "ferret", "finch", "fox", "gecko", "goat", "goose",
+    "gopher", "heron", "horse", "hyena", "ibis", "jaguar", "koala", "lemur", "lion", "llama",
+    "lynx", "marten", "mole", "moose", "mouse", "newt", "ocelot", "otter", "owl", "panda",
+    "parrot", "pelican", "penguin", "pony", "puffin", "rabbit", "raven", "robin", "salmon", "seal",
+    "shark", "sloth", "snail", "swan", "tiger", "toad", "turtle", "walrus", "whale", "wolf",
+    "wombat", "yak", "zebra",
+];
+
+pub const MIN_SLUG_LEN: usize = 3;
+pub const MAX_SLUG_LEN: usize = 40;
+
+/// Generate a pronounceable join code like `blue-otter-42`
+pub fn gen_room_code() -> String {
+    let mut rng = rand::thread_rng();
+    format!(
+        "{}-{}-{}",
+        ADJECTIVES.choose(&mut rng).unwrap(),
+        ANIMALS.choose(&mut rng).unwrap(),
+        rng.gen_range(10..100)
+    )
+}
+
+/// Whether `alias` could be a room code or slug
+pub fn is_valid_alias(alias: &str) -> bool {
+    (MIN_SLUG_LEN..=MAX_SLUG_LEN).contains(&alias.len())
+        && alias
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-')
+        && !alias.starts_with('-')
+        && !alias.ends_with('-')
+        && !alias.contains("--")
+}
+
+/// Normalize custom slug chosen by a user
+///
+/// Returns `None` when the slug contains unsupported characters or has a
+/// wrong length.
+pub fn normalize_slug(slug: &str) -> Option<String> {
+    let slug: String = slug
+        .trim()
+        .chars()
+        .map(|c| match c {
+            ' ' | '_' => '-',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect();
+    if is_valid_alias(&slug) {
+        Some(slug)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_codes_are_valid_aliases() {
+        for _ in 0..100 {
+            let code = gen_room_code();
+            assert!(is_valid_alias(&code), "{code}");
+            assert_eq!(code.split('-').count(), 3);
+        }
+    }
+
+    #[test]
+    fn normalize_custom_slugs() {
+        assert_eq!(normalize_slug("Sprint 42"), Some("sprint-42".to_string()));
+        assert_eq!(normalize_slug(" team_a "), Some("team-a".to_string()));
+        assert_eq!(normalize_slug("ab"), None);
+        assert_eq!(normalize_slug("-team"), None);
+        assert_eq!(normalize_slug("team--a"), None);
+        assert_eq!(normalize_slug("tëam"), None);
+        assert_eq!(normalize_slug(&"a".repeat(MAX_SLUG_LEN + 1)), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+/// Canonical room ID: a UUID encoded as URL-safe Base64 without padding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RoomId(Uuid);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidRoomId;
+
+impl Display for InvalidRoomId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("room ID is not a Base64 encoded UUID")
+    }
+}
+
+impl std::error::Error for InvalidRoomId {}
+
+impl RoomId {
+    pub fn generate() -> Self {
+        Self(Uuid::now_v7())
+    }
+
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+
+    pub fn as_uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+impl Display for RoomId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&URL_SAFE_NO_PAD.encode(self.0.as_bytes()))
+    }
+}
+
+impl FromStr for RoomId {
+    type Err = InvalidRoomId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // one more byte than needed to detect longer input
+        let mut buf: [u8; 18] = [0; 18];
+        match URL_SAFE_NO_PAD.decode_slice(s, &mut buf) {
+            Ok(16) => Uuid::from_slice(&buf[..16])
+                .map(RoomId)
+                .map_err(|_| InvalidRoomId),
+            _ => Err(InvalidRoomId),
+        }
+    }
+}
+
+impl Serialize for RoomId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RoomId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let id = RoomId::generate();
+        assert_eq!(id.to_string().len(), 22);
+        assert_eq!(id.to_string().parse(), Ok(id));
+    }
+
+    #[test]
+    fn reject_invalid_ids() {
+        assert_eq!("".parse::<RoomId>(), Err(InvalidRoomId));
+        assert_eq!("garbage".parse::<RoomId>(), Err(InvalidRoomId));
+        assert_eq!("blue-otter-42".parse::<RoomId>(), Err(InvalidRoomId));
+        assert_eq!(
+            "AAAAAAAAAAAAAAAAAAAAAA==".parse::<RoomId>(),
+            Err(InvalidRoomId)
+        );
+        assert_eq!(
+            "AAAAAAAAAAAAAAAAAAAAAAAA".parse::<RoomId>(),
+            Err(InvalidRoomId)
+        );
+        assert_eq!(
+            "AAAAAAAAAAAAAAAAAAAA+/".parse::<RoomId>(),
+            Err(InvalidRoomId)
+        );
+        assert_eq!(
+            "AAAAAAAAAAAAAAAAAAAAAA".parse::<RoomId>(),
+            Ok(RoomId::from_uuid(Uuid::nil()))
+        );
+    }
+}
@@ -0,0 +1,488 @@
+//! Stories of a room and their import from issue tracker exports
+//!
+//! Exports are CSV or JSON files with a row per story. The columns with key,
+//! title, link and description are found by name, the names can be
+//! configured. Invalid and duplicate rows are skipped and reported with
+//! their row number, so the other rows can still be imported.
+
+use std::collections::HashMap;
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use quick_error::quick_error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::char_len;
+
+/// Stories a room can hold
+pub const MAX_STORIES: usize = 500;
+
+const MAX_KEY_CHARS: usize = 64;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_LINK_CHARS: usize = 2000;
+const MAX_DESCRIPTION_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Story {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Csv,
+    Json,
+}
+
+impl ImportFormat {
+    /// Format from the media type, or guessed from the content
+    pub fn detect(media_type: Option<&str>, text: &str) -> Self {
+        match media_type {
+            Some(media_type) if media_type.ends_with("json") => Self::Json,
+            Some("text/csv") => Self::Csv,
+            _ => match text.trim_start().chars().next() {
+                Some('[') | Some('{') => Self::Json,
+                _ => Self::Csv,
+            },
+        }
+    }
+}
+
+/// Names of the columns, compared case-insensitive
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ColumnMapping {
+    pub key: String,
+    pub title: String,
+    pub link: String,
+    pub description: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            key: "key".to_string(),
+            title: "title".to_string(),
+            link: "link".to_string(),
+            description: "description".to_string(),
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ImportError {
+        UnknownEncoding(label: String) {
+            display("Unknown encoding {}", label)
+        }
+        Csv(err: csv::Error) {
+            display("CSV error: {}", err)
+            from()
+        }
+        Json(err: serde_json::Error) {
+            display("JSON error: {}", err)
+            from()
+        }
+        MissingColumn(name: String) {
+            display("Column {} is missing", name)
+        }
+        NotAList {
+            display("JSON should be a list of stories")
+        }
+    }
+}
+
+/// Problem with a single row
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowError {
+    /// Line in CSV files, position in JSON lists, starting at 1
+    pub row: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Import {
+    pub stories: Vec<Story>,
+    pub errors: Vec<RowError>,
+}
+
+/// Decode text with a byte order mark, the given charset or as UTF-8
+///
+/// Falls back to Windows-1252, which spreadsheet programs often use, when
+/// the text is not valid UTF-8.
+pub fn decode(bytes: &[u8], charset: Option<&str>) -> Result<String, ImportError> {
+    let encoding = match (Encoding::for_bom(bytes), charset) {
+        (Some((encoding, _)), _) => encoding,
+        (None, Some(label)) => Encoding::for_label(label.trim().as_bytes())
+            .ok_or_else(|| ImportError::UnknownEncoding(label.to_string()))?,
+        (None, None) if std::str::from_utf8(bytes).is_ok() => UTF_8,
+        (None, None) => WINDOWS_1252,
+    };
+    let (text, _, _) = encoding.decode(bytes);
+    Ok(text.into_owned())
+}
+
+pub fn parse_stories(
+    text: &str,
+    format: ImportFormat,
+    mapping: &ColumnMapping,
+) -> Result<Import, ImportError> {
+    let mut builder = ImportBuilder::default();
+    match format {
+        ImportFormat::Csv => parse_csv(text, mapping, &mut builder)?,
+        ImportFormat::Json => parse_json(text, mapping, &mut builder)?,
+    }
+    Ok(builder.import)
+}
+
+#[derive(Debug, Default)]
+struct RawStory {
+    key: Option<String>,
+    title: Option<String>,
+    link: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Default)]
+struct ImportBuilder {
+    import: Import,
+    /// Rows of the imported stories by key, or title for stories without key
+    seen: HashMap<String, u64>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn check_length(name: &str, value: &Option<String>, max: usize) -> Result<(), String> {
+    match value {
+        Some(value) if char_len(value) > max => {
+            Err(format!("{name} is longer than {max} characters"))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl ImportBuilder {
+    fn error(&mut self, row: u64, message: String) {
+        self.import.errors.push(RowError { row, message });
+    }
+
+    fn add(&mut self, row: u64, raw: RawStory) {
+        match self.validate(raw) {
+            Ok(story) => {
+                let id = match &story.key {
+                    Some(key) => format!("key:{}", key.to_lowercase()),
+                    None => format!("title:{}", story.title.to_lowercase()),
+                };
+                if let Some(first) = self.seen.get(&id) {
+                    let message = format!("duplicate of row {first}");
+                    self.error(row, message);
+                } else if self.import.stories.len() >= MAX_STORIES {
+                    self.error(row, format!("more than {MAX_STORIES} stories"));
+                } else {
+                    self.seen.insert(id, row);
+                    self.import.stories.push(story);
+                }
+            }
+            Err(message) => self.error(row, message),
+        }
+    }
+
+    fn validate(&self, raw: RawStory) -> Result<Story, String> {
+        let key = non_empty(raw.key);
+        let title = non_empty(raw.title).ok_or_else(|| "title is missing".to_string())?;
+        let link = non_empty(raw.link);
+        let description = non_empty(raw.description);
+
+        check_length("key", &key, MAX_KEY_CHARS)?;
+        check_length("title", &Some(title.clone()), MAX_TITLE_CHARS)?;
+        check_length("link", &link, MAX_LINK_CHARS)?;
+        check_length("description", &description, MAX_DESCRIPTION_CHARS)?;
+        if let Some(link) = &link {
+            if !link.starts_with("http://") && !link.starts_with("https://") {
+                return Err("link is not an HTTP URL".to_string());
+            }
+        }
+
+        Ok(Story {
+            key,
+            title,
+            link,
+            description,
+        })
+    }
+}
+
+/// Separator that occurs most often in the header line, comma on ties
+fn detect_delimiter(text: &str) -> u8 {
+    let header = text.lines().next().unwrap_or_default();
+    [b'\t', b';', b',']
+        .into_iter()
+        .max_by_key(|delimiter| header.bytes().filter(|b| b == delimiter).count())
+        .unwrap_or(b',')
+}
+
+fn parse_csv(
+    text: &str,
+    mapping: &ColumnMapping,
+    builder: &mut ImportBuilder,
+) -> Result<(), ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(detect_delimiter(text))
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(name.trim()))
+    };
+    let title =
+        column(&mapping.title).ok_or_else(|| ImportError::MissingColumn(mapping.title.clone()))?;
+    let key = column(&mapping.key);
+    let link = column(&mapping.link);
+    let description = column(&mapping.description);
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                let row = err.position().map_or(0, |pos| pos.line());
+                builder.error(row, err.to_string());
+                continue;
+            }
+        };
+        let row = record.position().map_or(0, |pos| pos.line());
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let field = |index: Option<usize>| index.and_then(|i| record.get(i)).map(str::to_string);
+        builder.add(
+            row,
+            RawStory {
+                key: field(key),
+                title: field(Some(title)),
+                link: field(link),
+                description: field(description),
+            },
+        );
+    }
+    Ok(())
+}
+
+fn parse_json(
+    text: &str,
+    mapping: &ColumnMapping,
+    builder: &mut ImportBuilder,
+) -> Result<(), ImportError> {
+    let rows = match serde_json::from_str(text)? {
+        Value::Array(rows) => rows,
+        Value::Object(mut object) => {
+            match object.remove("stories").or_else(|| object.remove("issues")) {
+                Some(Value::Array(rows)) => rows,
+                _ => return Err(ImportError::NotAList),
+            }
+        }
+        _ => return Err(ImportError::NotAList),
+    };
+
+    for (i, value) in rows.into_iter().enumerate() {
+        let row = i as u64 + 1;
+        let Value::Object(object) = value else {
+            builder.error(row, "story is not an object".to_string());
+            continue;
+        };
+        let field = |name: &str| -> Result<Option<String>, String> {
+            let value = object
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name.trim()))
+                .map(|(_, value)| value);
+            match value {
+                None | Some(Value::Null) => Ok(None),
+                Some(Value::String(value)) => Ok(Some(value.clone())),
+                Some(Value::Number(value)) => Ok(Some(value.to_string())),
+                Some(Value::Bool(value)) => Ok(Some(value.to_string())),
+                Some(_) => Err(format!("{name} is not a text")),
+            }
+        };
+        let raw = (|| {
+            Ok(RawStory {
+                key: field(&mapping.key)?,
+                title: field(&mapping.title)?,
+                link: field(&mapping.link)?,
+                description: field(&mapping.description)?,
+            })
+        })();
+        match raw {
+            Ok(raw) => builder.add(row, raw),
+            Err(message) => builder.error(row, message),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, format: ImportFormat) -> Import {
+        parse_stories(text, format, &ColumnMapping::default()).unwrap()
+    }
+
+    #[test]
+    fn import_csv() {
+        let import = parse(
+            "Key,Title,Link,Description\n\
+             GOE-1,Login,https://example.com/GOE-1,\"Users log in,\nwith passwords\"\n\
+             GOE-2,Logout,,\n",
+            ImportFormat::Csv,
+        );
+
+        assert_eq!(import.errors, vec![]);
+        assert_eq!(
+            import.stories,
+            vec![
+                Story {
+                    key: Some("GOE-1".to_string()),
+                    title: "Login".to_string(),
+                    link: Some("https://example.com/GOE-1".to_string()),
+                    description: Some("Users log in,\nwith passwords".to_string()),
+                },
+                Story {
+                    key: Some("GOE-2".to_string()),
+                    title: "Logout".to_string(),
+                    link: None,
+                    description: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn map_columns() {
+        let mapping = ColumnMapping {
+            key: "Issue key".to_string(),
+            title: "Summary".to_string(),
+            ..Default::default()
+        };
+        let import = parse_stories(
+            "Issue key;Summary;Status\nGOE-1;Login;Open\n",
+            ImportFormat::Csv,
+            &mapping,
+        )
+        .unwrap();
+
+        assert_eq!(import.stories[0].key.as_deref(), Some("GOE-1"));
+        assert_eq!(import.stories[0].title, "Login");
+
+        let err = parse_stories("Key;Name\n", ImportFormat::Csv, &mapping).unwrap_err();
+        assert!(matches!(err, ImportError::MissingColumn(name) if name == "Summary"));
+    }
+
+    #[test]
+    fn report_invalid_rows() {
+        let import = parse(
+            "key,title,link\n\
+             A-1,First,\n\
+             A-2,,\n\
+             a-1,Again,\n\
+             ,Second,ftp://example.com\n\
+             ,,\n\
+             ,Third,\n\
+             ,third,\n",
+            ImportFormat::Csv,
+        );
+
+        assert_eq!(import.stories.len(), 2);
+        assert_eq!(
+            import.errors,
+            vec![
+                RowError {
+                    row: 3,
+                    message: "title is missing".to_string()
+                },
+                RowError {
+                    row: 4,
+                    message: "duplicate of row 2".to_string()
+                },
+                RowError {
+                    row: 5,
+                    message: "link is not an HTTP URL".to_string()
+                },
+                RowError {
+                    row: 8,
+                    message: "duplicate of row 7".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn import_json() {
+        let import = parse(
+            r#"{"issues": [
+                {"key": 17, "title": "Login", "link": null},
+                {"title": ["Logout"]},
+                "Register"
+            ]}"#,
+            ImportFormat::Json,
+        );
+
+        assert_eq!(import.stories.len(), 1);
+        assert_eq!(import.stories[0].key.as_deref(), Some("17"));
+        assert_eq!(
+            import.errors,
+            vec![
+                RowError {
+                    row: 2,
+                    message: "title is not a text".to_string()
+                },
+                RowError {
+                    row: 3,
+                    message: "story is not an object".to_string()
+                },
+            ]
+        );
+        assert!(matches!(
+            parse_stories("42", ImportFormat::Json, &ColumnMapping::default()),
+            Err(ImportError::NotAList)
+        ));
+    }
+
+    #[test]
+    fn decode_encodings() {
+        assert_eq!(decode("Größe".as_bytes(), None).unwrap(), "Größe");
+        assert_eq!(decode(b"\xEF\xBB\xBFkey", None).unwrap(), "key");
+        assert_eq!(decode(b"Gr\xF6\xDFe", None).unwrap(), "Größe");
+        assert_eq!(decode(b"\xFF\xFEk\0e\0y\0", None).unwrap(), "key");
+        assert_eq!(decode(b"Gr\xF6\xDFe", Some("iso-8859-1")).unwrap(), "Größe");
+        assert!(matches!(
+            decode(b"key", Some("klingon")),
+            Err(ImportError::UnknownEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(
+            ImportFormat::detect(Some("application/json"), ""),
+            ImportFormat::Json
+        );
+        assert_eq!(
+            ImportFormat::detect(Some("text/csv"), "[]"),
+            ImportFormat::Csv
+        );
+        assert_eq!(ImportFormat::detect(None, " [{}]"), ImportFormat::Json);
+        assert_eq!(ImportFormat::detect(None, "key,title"), ImportFormat::Csv);
+    }
+}
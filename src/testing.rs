@@ -0,0 +1,334 @@
+//! In-process clients for integration tests and bots
+//!
+//! [`TestServer`] runs a [`GameServer`] with in-memory repositories.
+//! Every [`TestClient`] is served by a real [`Player`], connected through
+//! an in-memory transport, so scenarios exercise the same code as clients
+//! connected over WebSockets.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{mpsc, watch};
+use tokio::time::{timeout, Duration};
+use uactor::blocking::Actor;
+
+use crate::adapters::memory::MemoryRepository;
+use crate::game_server::{GameServer, GameServerAddr};
+use crate::player::Player;
+use crate::rate_limit::RateLimitConfig;
+use crate::remote::{CloseReason, ConnResult, RemoteMessage, Transport, PROTOCOL_VERSION};
+use crate::room_id::RoomId;
+
+/// How long a test client waits for an expected message
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server side of an in-memory connection
+pub struct MemoryTransport {
+    protocol: Option<u32>,
+    to_client: Option<mpsc::UnboundedSender<RemoteMessage>>,
+    from_client: mpsc::UnboundedReceiver<RemoteMessage>,
+    close_reason: Arc<Mutex<Option<CloseReason>>>,
+}
+
+/// Client side of an in-memory connection
+pub struct TestClient {
+    to_server: mpsc::UnboundedSender<RemoteMessage>,
+    from_server: mpsc::UnboundedReceiver<RemoteMessage>,
+    close_reason: Arc<Mutex<Option<CloseReason>>>,
+}
+
+/// Connected pair of in-memory transport and client
+pub fn memory_transport(protocol: Option<u32>) -> (MemoryTransport, TestClient) {
+    let (to_client, from_server) = mpsc::unbounded_channel();
+    let (to_server, from_client) = mpsc::unbounded_channel();
+    let close_reason = Arc::new(Mutex::new(None));
+    (
+        MemoryTransport {
+            protocol,
+            to_client: Some(to_client),
+            from_client,
+            close_reason: close_reason.clone(),
+        },
+        TestClient {
+            to_server,
+            from_server,
+            close_reason,
+        },
+    )
+}
+
+#[async_trait::async_trait]
+impl Transport for MemoryTransport {
+    fn protocol(&self) -> Option<u32> {
+        self.protocol
+    }
+
+    async fn send(&mut self, message: RemoteMessage) -> ConnResult<()> {
+        if let Some(to_client) = &self.to_client {
+            // client might be gone, like a closed socket
+            let _ = to_client.send(message);
+        }
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> ConnResult<RemoteMessage> {
+        Ok(self
+            .from_client
+            .recv()
+            .await
+            .unwrap_or(RemoteMessage::Close))
+    }
+
+    async fn ping(&mut self) -> ConnResult<()> {
+        Ok(())
+    }
+
+    async fn close(&mut self, reason: CloseReason) -> ConnResult<()> {
+        *self.close_reason.lock().unwrap() = Some(reason);
+        self.to_client = None;
+        Ok(())
+    }
+}
+
+impl TestClient {
+    pub fn send(&self, message: RemoteMessage) {
+        let _ = self.to_server.send(message);
+    }
+
+    /// Next message, `None` when the server closed the connection
+    ///
+    /// Panics when no message arrives in time.
+    pub async fn recv(&mut self) -> Option<RemoteMessage> {
+        timeout(RECV_TIMEOUT, self.from_server.recv())
+            .await
+            .expect("no message from server")
+    }
+
+    /// Skip messages until `f` matches one
+    ///
+    /// Panics when the connection is closed or no message matches in time.
+    pub async fn expect<T>(&mut self, mut f: impl FnMut(RemoteMessage) -> Option<T>) -> T {
+        loop {
+            match self.recv().await {
+                Some(msg) => {
+                    if let Some(value) = f(msg) {
+                        return value;
+                    }
+                }
+                None => panic!("connection closed before expected message"),
+            }
+        }
+    }
+
+    /// Wait for the `Welcome` message, returns the own player ID
+    pub async fn welcome(&mut self) -> String {
+        self.expect(|msg| match msg {
+            RemoteMessage::Welcome { player_id, .. } => Some(player_id),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait until the player joined a room, returns its ID
+    pub async fn joined(&mut self) -> RoomId {
+        self.expect(|msg| match msg {
+            RemoteMessage::Joined { room, .. } => Some(room),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Why the server closed the connection
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        *self.close_reason.lock().unwrap()
+    }
+}
+
+/// Game server with in-memory repositories
+pub struct TestServer {
+    game_server: GameServerAddr,
+    shutdown: watch::Sender<Option<Duration>>,
+    rate_limits: RateLimitConfig,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        let repo = Arc::new(MemoryRepository::default());
+        let (shutdown, _) = watch::channel(None);
+        Self {
+            game_server: GameServer::new(repo.clone(), repo).start(),
+            shutdown,
+            rate_limits: RateLimitConfig::default(),
+        }
+    }
+
+    /// Limits for clients connected afterwards
+    pub fn with_rate_limits(mut self, rate_limits: RateLimitConfig) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn game_server(&self) -> GameServerAddr {
+        self.game_server.clone()
+    }
+
+    /// Connect a client speaking the current protocol version
+    pub fn connect(&self) -> TestClient {
+        self.connect_with_protocol(Some(PROTOCOL_VERSION))
+    }
+
+    pub fn connect_with_protocol(&self, protocol: Option<u32>) -> TestClient {
+        let (transport, client) = memory_transport(protocol);
+        let mut player = Player::new(
+            transport,
+            self.game_server.clone(),
+            self.shutdown.subscribe(),
+            self.rate_limits.clone(),
+        );
+        tokio::spawn(async move { player.run().await });
+        client
+    }
+
+    /// Notify connected players like on a server shutdown
+    pub fn shut_down(&self, reconnect_after: Duration) {
+        let _ = self.shutdown.send(Some(reconnect_after));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::ErrorCode;
+    use crate::room::GameState;
+
+    async fn game_changed(client: &mut TestClient) -> GameState {
+        client
+            .expect(|msg| match msg {
+                RemoteMessage::GameChanged { game_state } => Some(game_state),
+                _ => None,
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn join_vote_and_reveal() {
+        let server = TestServer::start();
+        let mut alice = server.connect();
+        let mut bob = server.connect();
+        let alice_id = alice.welcome().await;
+        let bob_id = bob.welcome().await;
+
+        // ACT
+        alice.send(RemoteMessage::CreateRoom {
+            deck: "fibonacci".to_string(),
+            slug: Some("planning".to_string()),
+        });
+        let room = alice.joined().await;
+        bob.send(RemoteMessage::JoinRoom {
+            room: "planning".to_string(),
+        });
+        assert_eq!(bob.joined().await, room);
+
+        alice.send(RemoteMessage::Vote {
+            vote: Some("3".to_string()),
+        });
+        let state = game_changed(&mut bob).await;
+        assert!(!state.is_open());
+        bob.send(RemoteMessage::Vote {
+            vote: Some("5".to_string()),
+        });
+
+        // ASSERT
+        let state = loop {
+            let state = game_changed(&mut alice).await;
+            if state.is_open() {
+                break state;
+            }
+        };
+        assert_eq!(state.votes()[&alice_id].as_deref(), Some("3"));
+        assert_eq!(state.votes()[&bob_id].as_deref(), Some("5"));
+    }
+
+    #[tokio::test]
+    async fn force_open_and_restart() {
+        let server = TestServer::start();
+        let mut alice = server.connect();
+        alice.welcome().await;
+        alice.send(RemoteMessage::CreateRoom {
+            deck: "fibonacci".to_string(),
+            slug: None,
+        });
+        alice.joined().await;
+
+        // ACT
+        alice.send(RemoteMessage::ForceOpen);
+        assert!(game_changed(&mut alice).await.is_open());
+        alice.send(RemoteMessage::Restart);
+
+        // ASSERT
+        let state = game_changed(&mut alice).await;
+        assert!(!state.is_open());
+        assert!(state.votes().values().all(Option::is_none));
+    }
+
+    #[tokio::test]
+    async fn report_unknown_room() {
+        let server = TestServer::start();
+        let mut client = server.connect();
+        client.welcome().await;
+
+        // ACT
+        client.send(RemoteMessage::JoinRoom {
+            room: "no-such-room".to_string(),
+        });
+
+        // ASSERT
+        let code = client
+            .expect(|msg| match msg {
+                RemoteMessage::Error { code, .. } => Some(code),
+                _ => None,
+            })
+            .await;
+        assert_eq!(code, ErrorCode::RoomDoesNotExist);
+    }
+
+    #[tokio::test]
+    async fn disconnect_outdated_clients() {
+        let server = TestServer::start();
+        let mut client = server.connect_with_protocol(None);
+
+        // ACT
+        let code = client
+            .expect(|msg| match msg {
+                RemoteMessage::Error { code, .. } => Some(code),
+                _ => None,
+            })
+            .await;
+
+        // ASSERT
+        assert_eq!(code, ErrorCode::UnsupportedProtocol);
+        assert_eq!(client.recv().await, None);
+        assert_eq!(client.close_reason(), Some(CloseReason::Outdated));
+    }
+
+    #[tokio::test]
+    async fn notify_players_on_shutdown() {
+        let server = TestServer::start();
+        let mut client = server.connect();
+        client.welcome().await;
+
+        // ACT
+        server.shut_down(Duration::from_secs(3));
+
+        // ASSERT
+        let reconnect_after_ms = client
+            .expect(|msg| match msg {
+                RemoteMessage::ServerShuttingDown { reconnect_after_ms } => {
+                    Some(reconnect_after_ms)
+                }
+                _ => None,
+            })
+            .await;
+        assert_eq!(reconnect_after_ms, 3000);
+        assert_eq!(client.close_reason(), Some(CloseReason::Restart));
+    }
+}
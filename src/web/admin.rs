@@ -0,0 +1,233 @@
+use crate::web::{ask, AppState};
+use axum::extract::{Path, Request, State};
+use axum::middleware::{from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use game_of_estimates::game_server::GameServerMessage;
+use game_of_estimates::room::{GameState, PlayerState, RoomInfo};
+use game_of_estimates::room_id::RoomId;
+use game_of_estimates::webhook::{Delivery, Subscription, Webhook};
+use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Token that has to be sent as bearer token to access the admin API
+#[derive(Clone)]
+pub struct AdminToken(pub String);
+
+#[derive(Serialize)]
+struct RoomSummary {
+    id: RoomId,
+    deck: String,
+    players: usize,
+    voters: usize,
+    age_secs: u64,
+}
+
+impl From<RoomInfo> for RoomSummary {
+    fn from(info: RoomInfo) -> Self {
+        Self {
+            deck: info.state.deck().to_string(),
+            players: info.players.len(),
+            voters: info.players.iter().filter(|p| p.is_voter()).count(),
+            age_secs: info.age.as_secs(),
+            id: info.id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RoomDetails {
+    id: RoomId,
+    age_secs: u64,
+    state: GameState,
+    players: Vec<PlayerState>,
+}
+
+impl From<RoomInfo> for RoomDetails {
+    fn from(info: RoomInfo) -> Self {
+        Self {
+            id: info.id,
+            age_secs: info.age.as_secs(),
+            state: info.state,
+            players: info.players,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    url: String,
+    secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct BroadcastResponse {
+    rooms: usize,
+}
+
+async fn list_rooms(State(state): State<Arc<AppState>>) -> Result<Response, StatusCode> {
+    let rooms = ask(&state, |reply| GameServerMessage::ListRooms { reply }).await?;
+    let rooms: Vec<RoomSummary> = rooms.into_iter().map(RoomSummary::from).collect();
+    Ok(Json(rooms).into_response())
+}
+
+async fn get_room(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+) -> Result<Response, StatusCode> {
+    let room: RoomId = room.parse().map_err(|_| StatusCode::NOT_FOUND)?;
+    match ask(&state, |reply| GameServerMessage::GetRoom { room, reply }).await? {
+        Some(info) => Ok(Json(RoomDetails::from(info)).into_response()),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn close_room(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let room: RoomId = room.parse().map_err(|_| StatusCode::NOT_FOUND)?;
+    if ask(&state, |reply| GameServerMessage::CloseRoom { room, reply }).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn list_webhooks(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+) -> Result<Response, StatusCode> {
+    let room: RoomId = room.parse().map_err(|_| StatusCode::NOT_FOUND)?;
+    let subscriptions: Vec<Subscription> = ask(&state, |reply| GameServerMessage::GetWebhooks {
+        room,
+        reply,
+    })
+    .await?
+    .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(subscriptions).into_response())
+}
+
+async fn subscribe_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+    Json(request): Json<SubscribeRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let url = request.url.trim();
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let room: RoomId = room.parse().map_err(|_| StatusCode::NOT_FOUND)?;
+    let webhook = Webhook {
+        url: url.to_string(),
+        secret: request.secret.filter(|secret| !secret.is_empty()),
+    };
+    if ask(&state, |reply| GameServerMessage::Subscribe {
+        room,
+        webhook,
+        reply,
+    })
+    .await?
+    {
+        Ok(StatusCode::CREATED)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn unsubscribe_webhooks(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let room: RoomId = room.parse().map_err(|_| StatusCode::NOT_FOUND)?;
+    if ask(&state, |reply| GameServerMessage::Unsubscribe {
+        room,
+        reply,
+    })
+    .await?
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn list_deliveries(State(state): State<Arc<AppState>>) -> Json<Vec<Delivery>> {
+    Json(state.webhooks.deliveries())
+}
+
+async fn broadcast(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<BroadcastRequest>,
+) -> Result<Response, StatusCode> {
+    let message = request.message.trim().to_string();
+    if message.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let rooms = ask(&state, |reply| GameServerMessage::Broadcast {
+        message,
+        reply,
+    })
+    .await?;
+    Ok(Json(BroadcastResponse { rooms }).into_response())
+}
+
+/// Compare the digests without short-circuit, so timing leaks neither the
+/// token nor its length
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn require_token(State(token): State<AdminToken>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| constant_time_eq(value.trim().as_bytes(), token.0.as_bytes()));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response()
+    }
+}
+
+pub fn router(token: AdminToken) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/rooms", get(list_rooms))
+        .route("/admin/rooms/{id}", get(get_room).delete(close_room))
+        .route(
+            "/admin/rooms/{id}/webhooks",
+            get(list_webhooks)
+                .post(subscribe_webhook)
+                .delete(unsubscribe_webhooks),
+        )
+        .route("/admin/webhooks/deliveries", get(list_deliveries))
+        .route("/admin/broadcast", post(broadcast))
+        .route_layer(from_fn_with_state(token, require_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_tokens() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::web::headers::Accept;
+use crate::web::{ask, AppState};
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use game_of_estimates::export::{Export, ExportFormat};
+use game_of_estimates::game_server::GameServerMessage;
+use game_of_estimates::room::{GameState, PlayerState, RoomInfo, Round};
+use game_of_estimates::room_id::RoomId;
+use game_of_estimates::story::{
+    decode, parse_stories, ColumnMapping, ImportFormat, RowError, Story,
+};
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use http::{HeaderMap, StatusCode};
+use log::error;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct RoomView {
+    id: RoomId,
+    state: GameState,
+    players: Vec<PlayerState>,
+    stories: Vec<Story>,
+}
+
+impl From<RoomInfo> for RoomView {
+    fn from(info: RoomInfo) -> Self {
+        Self {
+            id: info.id,
+            state: info.state,
+            players: info.players,
+            stories: info.stories,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RoomHistory {
+    id: RoomId,
+    rounds: Vec<Round>,
+}
+
+#[derive(Deserialize)]
+struct ImportParams {
+    format: Option<ImportFormat>,
+    /// Overrides the charset of the content type
+    charset: Option<String>,
+    key: Option<String>,
+    title: Option<String>,
+    link: Option<String>,
+    description: Option<String>,
+}
+
+impl ImportParams {
+    fn mapping(self) -> ColumnMapping {
+        let defaults = ColumnMapping::default();
+        ColumnMapping {
+            key: self.key.unwrap_or(defaults.key),
+            title: self.title.unwrap_or(defaults.title),
+            link: self.link.unwrap_or(defaults.link),
+            description: self.description.unwrap_or(defaults.description),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    /// Overrides the accept header
+    format: Option<ExportFormat>,
+}
+
+#[derive(Serialize)]
+struct ImportResult {
+    imported: usize,
+    errors: Vec<RowError>,
+}
+
+#[derive(Serialize)]
+struct ImportFailure {
+    error: String,
+    errors: Vec<RowError>,
+}
+
+fn import_failure(error: impl ToString, errors: Vec<RowError>) -> Response {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(ImportFailure {
+            error: error.to_string(),
+            errors,
+        }),
+    )
+        .into_response()
+}
+
+/// Room ID for the room ID, code or slug in the path
+async fn resolve(state: &AppState, room: String) -> Result<RoomId, StatusCode> {
+    ask(state, |reply| GameServerMessage::ResolveRoom {
+        room,
+        reply,
+    })
+    .await?
+    .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_room(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+) -> Result<Response, StatusCode> {
+    let room = resolve(&state, room).await?;
+    match ask(&state, |reply| GameServerMessage::GetRoom { room, reply }).await? {
+        Some(info) => Ok(Json(RoomView::from(info)).into_response()),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_history(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+) -> Result<Response, StatusCode> {
+    let id = resolve(&state, room).await?;
+    match ask(&state, |reply| GameServerMessage::GetRoomHistory {
+        room: id,
+        reply,
+    })
+    .await?
+    {
+        Some(rounds) => Ok(Json(RoomHistory { id, rounds }).into_response()),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Replace the stories of a room with the rows of a CSV or JSON file
+async fn import_stories(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+    Query(params): Query<ImportParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, StatusCode> {
+    let room = resolve(&state, room).await?;
+
+    let content_type: Option<Mime> = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let charset = params.charset.clone().or_else(|| {
+        content_type
+            .as_ref()
+            .and_then(|mime| mime.get_param(mime::CHARSET))
+            .map(|charset| charset.to_string())
+    });
+    let text = match decode(&body, charset.as_deref()) {
+        Ok(text) => text,
+        Err(err) => return Ok(import_failure(err, vec![])),
+    };
+    let format = params.format.unwrap_or_else(|| {
+        ImportFormat::detect(content_type.as_ref().map(|mime| mime.essence_str()), &text)
+    });
+    let import = match parse_stories(&text, format, &params.mapping()) {
+        Ok(import) => import,
+        Err(err) => return Ok(import_failure(err, vec![])),
+    };
+    if import.stories.is_empty() {
+        return Ok(import_failure("No stories found", import.errors));
+    }
+
+    let imported = import.stories.len();
+    let stories = import.stories;
+    if ask(&state, |reply| GameServerMessage::SetStories {
+        room,
+        stories,
+        reply,
+    })
+    .await?
+    {
+        Ok(Json(ImportResult {
+            imported,
+            errors: import.errors,
+        })
+        .into_response())
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Revealed rounds of a room from its event log as a file
+async fn export_rounds(
+    State(state): State<Arc<AppState>>,
+    Path(room): Path<String>,
+    Query(params): Query<ExportParams>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    // the accept header is only parsed when no format is given
+    let format = match params.format {
+        Some(format) => format,
+        None => match Accept::from_headers(&headers).map_err(|_| StatusCode::BAD_REQUEST)? {
+            None => ExportFormat::Json,
+            Some(accept) => {
+                let available = ExportFormat::ALL.map(ExportFormat::media_type);
+                accept
+                    .negotiate(&available)
+                    .and_then(ExportFormat::from_media_type)
+                    .ok_or(StatusCode::NOT_ACCEPTABLE)?
+            }
+        },
+    };
+
+    let id = resolve(&state, room).await?;
+    let rounds = ask(&state, |reply| GameServerMessage::GetPersistedRounds {
+        room: id,
+        reply,
+    })
+    .await?
+    .map_err(|err| {
+        error!("Failed to read rounds of room {}: {}", id, err);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let body = Export::new(id, &rounds).render(format).map_err(|err| {
+        error!("Failed to export room {}: {}", id, err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        [
+            (
+                CONTENT_TYPE,
+                format!("{}; charset=utf-8", format.media_type()),
+            ),
+            (
+                CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"estimates-{}.{}\"",
+                    id,
+                    format.extension()
+                ),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/rooms/{id}", get(get_room))
+        .route("/api/rooms/{id}/history", get(get_history))
+        .route(
+            "/api/rooms/{id}/stories",
+            axum::routing::post(import_stories),
+        )
+        .route("/rooms/{id}/export", get(export_rounds))
+}
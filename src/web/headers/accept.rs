@@ -0,0 +1,172 @@
+use crate::web::headers::common::{QValue, Weighted};
+use axum::extract::OptionalFromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use http::header::ACCEPT;
+use http::HeaderMap;
+use std::str::FromStr;
+
+/// Media range like `text/*` in an accept header
+#[derive(PartialEq, Debug)]
+pub struct MediaRange {
+    type_: String,
+    subtype: String,
+}
+
+impl MediaRange {
+    fn matches(&self, media_type: &str) -> bool {
+        let Some((type_, subtype)) = media_type.split_once('/') else {
+            return false;
+        };
+        (self.type_ == "*" || self.type_.eq_ignore_ascii_case(type_))
+            && (self.subtype == "*" || self.subtype.eq_ignore_ascii_case(subtype))
+    }
+
+    /// More specific ranges take precedence
+    fn specificity(&self) -> u8 {
+        match (self.type_.as_str(), self.subtype.as_str()) {
+            ("*", _) => 0,
+            (_, "*") => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// Accept header
+///
+/// Standard: https://httpwg.org/specs/rfc9110.html#field.accept
+pub struct Accept {
+    ranges: Vec<Weighted<MediaRange>>,
+}
+
+impl Accept {
+    /// Accept header of a request, `Err` if it is malformed
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, AcceptRejection> {
+        match headers.get(ACCEPT).map(|value| value.to_str().ok()) {
+            None => Ok(None),
+            Some(None) => Err(AcceptRejection),
+            Some(Some(value)) => match Accept::from_str(value) {
+                Ok(res) => Ok(Some(res)),
+                Err(_) => Err(AcceptRejection),
+            },
+        }
+    }
+
+    /// Media type out of `available` the client prefers
+    ///
+    /// Ties are resolved by the order of `available`, `None` means that the
+    /// client accepts none of them.
+    pub fn negotiate(&self, available: &[&'static str]) -> Option<&'static str> {
+        let mut best: Option<(&'static str, QValue)> = None;
+        for &media_type in available {
+            let weight = self
+                .ranges
+                .iter()
+                .filter(|range| range.value().matches(media_type))
+                .max_by_key(|range| range.value().specificity())
+                .map(|range| range.weight());
+            if let Some(weight) = weight.filter(QValue::is_acceptable) {
+                if best.map_or(true, |(_, best)| weight > best) {
+                    best = Some((media_type, weight));
+                }
+            }
+        }
+        best.map(|(media_type, _)| media_type)
+    }
+}
+
+pub struct AcceptRejection;
+
+impl IntoResponse for AcceptRejection {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, "Failed to parse accept header").into_response()
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for Accept
+where
+    S: Send + Sync,
+{
+    type Rejection = AcceptRejection;
+
+    async fn from_request_parts(
+        request: &mut Parts,
+        _: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Accept::from_headers(&request.headers)
+    }
+}
+
+impl FromStr for Accept {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges = s
+            .split(',')
+            .map(|elem| elem.trim_matches(|c: char| matches!(c, ' ' | '\x09')))
+            .filter(|elem| !elem.is_empty())
+            .map(|elem| {
+                let mut params = elem.split(';');
+                let range = params.next().unwrap_or_default().trim();
+                let (type_, subtype) = range.split_once('/').ok_or(())?;
+                if type_.is_empty() || subtype.is_empty() || (type_ == "*" && subtype != "*") {
+                    return Err(());
+                }
+
+                let mut weight = QValue::max();
+                for param in params {
+                    if let Some((name, value)) = param.trim().split_once('=') {
+                        if name.eq_ignore_ascii_case("q") {
+                            weight = QValue::from_str(value)?;
+                        }
+                    }
+                }
+                Ok(Weighted::new(
+                    MediaRange {
+                        type_: type_.to_string(),
+                        subtype: subtype.to_string(),
+                    },
+                    weight,
+                ))
+            })
+            .collect::<Result<Vec<_>, Self::Err>>()?;
+
+        Ok(Accept { ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AVAILABLE: &[&str] = &["application/json", "text/csv", "text/markdown"];
+
+    fn negotiate(header: &str) -> Option<&'static str> {
+        Accept::from_str(header).unwrap().negotiate(AVAILABLE)
+    }
+
+    #[test]
+    fn negotiate_media_type() {
+        assert_eq!(negotiate("*/*"), Some("application/json"));
+        assert_eq!(negotiate("text/csv"), Some("text/csv"));
+        assert_eq!(negotiate("text/*"), Some("text/csv"));
+        assert_eq!(
+            negotiate("text/csv;q=0.5, text/markdown; charset=utf-8"),
+            Some("text/markdown")
+        );
+        assert_eq!(
+            negotiate("text/*;q=0.9, */*;q=0.1, text/csv;q=0"),
+            Some("text/markdown")
+        );
+        assert_eq!(negotiate("image/png"), None);
+        assert_eq!(negotiate("*/*;q=0"), None);
+    }
+
+    #[test]
+    fn reject_invalid_header() {
+        assert!(Accept::from_str("json").is_err());
+        assert!(Accept::from_str("*/json").is_err());
+        assert!(Accept::from_str("text/csv;q=2").is_err());
+    }
+}
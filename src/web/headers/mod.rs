@@ -1,4 +1,6 @@
+mod accept;
 mod accept_language;
 mod common;
 
+pub use accept::Accept;
 pub use accept_language::AcceptLanguage;
@@ -0,0 +1,96 @@
+use crate::web::AppState;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use game_of_estimates::long_poll::Envelope;
+use game_of_estimates::remote::{negotiate_protocol, Encoding, RemoteMessage};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+/// Poll requests are answered after this time without messages
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+#[derive(Deserialize)]
+struct OpenParams {
+    protocol: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct OpenResponse {
+    session: String,
+}
+
+#[derive(Deserialize)]
+struct PollParams {
+    #[serde(default)]
+    ack: u64,
+}
+
+#[derive(Deserialize)]
+struct SendRequest {
+    messages: Vec<Envelope<RemoteMessage>>,
+}
+
+#[derive(Serialize)]
+struct SendResponse {
+    ack: u64,
+}
+
+async fn open(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(params): Query<OpenParams>,
+) -> Response {
+    let protocol = negotiate_protocol(params.protocol.map(|v| (v, Encoding::Json))).map(|(v, _)| v);
+    let Some((session, transport)) = state.long_poll.open(protocol, client.ip()) else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let mut player = state.player(transport);
+    tokio::spawn(async move { player.run().await });
+
+    (StatusCode::CREATED, Json(OpenResponse { session })).into_response()
+}
+
+async fn poll(
+    State(state): State<Arc<AppState>>,
+    Path(session): Path<String>,
+    Query(params): Query<PollParams>,
+) -> Result<Response, StatusCode> {
+    match state
+        .long_poll
+        .poll(&session, params.ack, POLL_TIMEOUT)
+        .await
+    {
+        Some(poll) => Ok(Json(poll).into_response()),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn send(
+    State(state): State<Arc<AppState>>,
+    Path(session): Path<String>,
+    Json(request): Json<SendRequest>,
+) -> Result<Response, StatusCode> {
+    match state.long_poll.deliver(&session, request.messages).await {
+        Some(ack) => Ok(Json(SendResponse { ack }).into_response()),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn close(State(state): State<Arc<AppState>>, Path(session): Path<String>) -> StatusCode {
+    if state.long_poll.close(&session).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/poll", post(open))
+        .route("/poll/{session}", get(poll).post(send).delete(close))
+}
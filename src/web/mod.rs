@@ -1,22 +1,31 @@
+use crate::web::admin::AdminToken;
 use crate::web::metrics::handler::serve_metrics;
 use crate::web::metrics::prometheus::RequestMetrics;
 use crate::web::metrics::service::RequestMetricsLayer;
+use crate::web::shutdown::ShutdownConfig;
 use crate::ListenAddr;
 use axum::extract::ws::WebSocket;
-use axum::extract::{State, WebSocketUpgrade};
+use axum::extract::{Query, State, WebSocketUpgrade};
 use axum::response::{ErrorResponse, IntoResponse, Response};
 use axum::routing::{any, post};
 use axum::{routing::get, Form, Router};
-use game_of_estimates::game_server::{GameServerAddr, GameServerMessage};
-use game_of_estimates::player::Player;
-use game_of_estimates::remote::RemoteConnection;
-use http::header::LOCATION;
-use http::{HeaderValue, StatusCode};
+use game_of_estimates::game_server::{CreateRoomError, GameServerAddr, GameServerMessage};
+use game_of_estimates::long_poll::LongPollSessions;
+use game_of_estimates::player::{Player, ShutdownReceiver};
+use game_of_estimates::rate_limit::RateLimitConfig;
+use game_of_estimates::remote::{
+    negotiate_protocol, parse_subprotocol, subprotocol, Encoding, RemoteConnection, Transport,
+};
+use game_of_estimates::webhook::Webhooks;
+use http::header::{LOCATION, SEC_WEBSOCKET_PROTOCOL};
+use http::{HeaderMap, HeaderValue, StatusCode};
 use log::error;
 use prometheus_client::registry::Registry;
 use rust_embed::Embed;
 use serde::Deserialize;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::sync::{oneshot, watch};
 #[cfg(debug_assertions)]
 use tower::layer::util::Stack;
 use tower::ServiceBuilder;
@@ -26,9 +35,13 @@ use tower_http::cors::CorsLayer;
 use tower_serve_assets::embed::EmbedCatalog;
 use tower_serve_assets::ServeAssets;
 
+pub mod admin;
+mod api;
 pub mod headers;
 pub mod i18n;
+mod long_poll;
 mod metrics;
+pub mod shutdown;
 
 #[derive(Embed)]
 #[folder = "frontend/build/"]
@@ -36,12 +49,44 @@ pub struct MyAssetCatalog;
 
 pub struct AppState {
     game_server: GameServerAddr,
+    shutdown: ShutdownReceiver,
+    rate_limits: RateLimitConfig,
+    long_poll: LongPollSessions,
+    webhooks: Webhooks,
+}
+
+impl AppState {
+    fn player(&self, transport: impl Transport + 'static) -> Player {
+        Player::new(
+            transport,
+            self.game_server.clone(),
+            self.shutdown.clone(),
+            self.rate_limits.clone(),
+        )
+    }
+}
+
+/// Send a query to the game server and wait for its reply
+async fn ask<T>(
+    state: &AppState,
+    msg: impl FnOnce(oneshot::Sender<T>) -> GameServerMessage,
+) -> Result<T, StatusCode> {
+    let (tx, rx) = oneshot::channel();
+    if state.game_server.send(msg(tx)).await.is_err() {
+        error!("Failed to query game server: game service is offline");
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    rx.await.map_err(|_| {
+        error!("Failed to query game server: game service dropped message");
+        StatusCode::SERVICE_UNAVAILABLE
+    })
 }
 
 #[derive(Deserialize)]
 struct CreateRoomFormData {
     deck: String,
     custom_deck: Option<String>,
+    slug: Option<String>,
 }
 
 async fn create_room(
@@ -60,9 +105,14 @@ async fn create_room(
     } else {
         data.deck.to_string()
     };
+    let slug = data.slug.filter(|slug| !slug.trim().is_empty());
     let res = state
         .game_server
-        .send(GameServerMessage::Create { deck, reply: tx })
+        .send(GameServerMessage::Create {
+            deck,
+            slug,
+            reply: tx,
+        })
         .await;
     if res.is_err() {
         error!("Failed to create room: game service is offline");
@@ -71,15 +121,17 @@ async fn create_room(
 
     // Can not use 303 SEE OTHER because of CORS
     match rx.await {
-        Ok(Some(room_id)) => Ok((
+        Ok(Ok(room)) => Ok((
             StatusCode::OK,
             [(
                 LOCATION,
-                HeaderValue::from_str(&format!("/room?id={room_id}")).unwrap(),
+                HeaderValue::from_str(&format!("/room?id={}", room.join_name())).unwrap(),
             )],
         )
             .into_response()),
-        Ok(None) => Err(StatusCode::SERVICE_UNAVAILABLE.into()),
+        Ok(Err(CreateRoomError::InvalidSlug)) => Err(StatusCode::UNPROCESSABLE_ENTITY.into()),
+        Ok(Err(CreateRoomError::SlugTaken)) => Err(StatusCode::CONFLICT.into()),
+        Ok(Err(CreateRoomError::Unavailable)) => Err(StatusCode::SERVICE_UNAVAILABLE.into()),
         Err(_) => {
             error!("Failed to create room: game service dropped message");
             Err(StatusCode::SERVICE_UNAVAILABLE.into())
@@ -87,10 +139,42 @@ async fn create_room(
     }
 }
 
-async fn websocket(State(state): State<Arc<AppState>>, ws: WebSocketUpgrade) -> Response {
-    let game_server = state.game_server.clone();
-    ws.on_upgrade(|socket: WebSocket| async {
-        Player::new(RemoteConnection::new(socket), game_server)
+#[derive(Deserialize)]
+struct WebSocketParams {
+    protocol: Option<u32>,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+async fn websocket(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WebSocketParams>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    // protocol version can be declared as subprotocol or query parameters
+    let requested: Vec<(u32, Encoding)> = headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_subprotocol)
+        .collect();
+    let query = params.protocol.map(|version| (version, params.encoding));
+    let negotiated = negotiate_protocol(query.into_iter().chain(requested.clone()));
+    let ws = match negotiated {
+        // confirm subprotocol even if unsupported to be able to explain the error
+        Some((version, encoding)) if requested.contains(&(version, encoding)) => {
+            ws.protocols([subprotocol(version, encoding)])
+        }
+        _ => ws,
+    };
+    let protocol = negotiated.map(|(version, _)| version);
+    let encoding = negotiated.map(|(_, encoding)| encoding).unwrap_or_default();
+
+    ws.on_upgrade(move |socket: WebSocket| async move {
+        state
+            .player(RemoteConnection::new(socket, protocol, encoding))
             .run()
             .await
     })
@@ -106,9 +190,18 @@ fn apply_cors<T>(svc_builder: ServiceBuilder<T>) -> ServiceBuilder<T> {
     svc_builder
 }
 
-pub async fn main(game_server: GameServerAddr, listen_addr: ListenAddr) {
+pub async fn main(
+    mut registry: Registry,
+    game_server: GameServerAddr,
+    listen_addr: ListenAddr,
+    admin_token: Option<AdminToken>,
+    shutdown_config: ShutdownConfig,
+    rate_limits: RateLimitConfig,
+    webhooks: Webhooks,
+) {
+    let (shutdown_tx, shutdown_rx) = watch::channel(None);
+
     // i18n
-    let mut registry = Registry::default();
     let req_metrics = RequestMetrics::new(&mut registry);
 
     let svc_builder = ServiceBuilder::new()
@@ -116,16 +209,38 @@ pub async fn main(game_server: GameServerAddr, listen_addr: ListenAddr) {
         .layer(RequestMetricsLayer::new(req_metrics));
     let layers = apply_cors(svc_builder);
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/mkroom", post(create_room))
         .route("/ws", any(websocket))
-        .route("/metrics", get(serve_metrics(Arc::new(registry))))
+        .merge(long_poll::router())
+        .merge(api::router())
+        .route("/metrics", get(serve_metrics(Arc::new(registry))));
+    if let Some(admin_token) = admin_token {
+        app = app.merge(admin::router(admin_token));
+    }
+
+    let app = app
         .fallback_service(ServeAssets::builder(EmbedCatalog::<MyAssetCatalog>::default()).build())
-        .with_state(Arc::new(AppState { game_server }))
+        .with_state(Arc::new(AppState {
+            game_server: game_server.clone(),
+            shutdown: shutdown_rx,
+            rate_limits,
+            long_poll: LongPollSessions::default(),
+            webhooks,
+        }))
         .layer(layers);
 
     let listener = tokio::net::TcpListener::bind(listen_addr.0)
         .await
         .expect("should bind to listen address");
-    axum::serve(listener, app).await.unwrap();
+    // peer addresses limit the long-polling sessions of a client
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown::signal())
+    .await
+    .unwrap();
+
+    shutdown::shutdown(game_server, shutdown_tx, shutdown_config).await;
 }
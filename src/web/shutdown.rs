@@ -0,0 +1,75 @@
+use game_of_estimates::game_server::{GameServerAddr, GameServerMessage};
+use log::{info, warn};
+use tokio::signal;
+use tokio::sync::{oneshot, watch};
+use tokio::time::{timeout, Duration};
+
+#[derive(Clone)]
+pub struct ShutdownConfig {
+    /// Maximum time to wait for players and rooms to finish
+    pub timeout: Duration,
+    /// Time clients should wait before they reconnect
+    pub reconnect_after: Duration,
+}
+
+/// Resolves on SIGINT or SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("should install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("should install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Shut down game server after web server stopped accepting connections
+pub async fn shutdown(
+    game_server: GameServerAddr,
+    notifier: watch::Sender<Option<Duration>>,
+    config: ShutdownConfig,
+) {
+    let result = timeout(config.timeout, async {
+        info!("Stop accepting new rooms");
+        let _ = game_server.send(GameServerMessage::StopAccepting).await;
+
+        info!("Notify players about shutdown");
+        let _ = notifier.send(Some(config.reconnect_after));
+        // players leave their rooms before they drop their receiver
+        notifier.closed().await;
+
+        info!("Shut down game server");
+        let (tx, rx) = oneshot::channel();
+        if game_server
+            .send(GameServerMessage::Shutdown { reply: tx })
+            .await
+            .is_ok()
+        {
+            let _ = rx.await;
+        }
+    })
+    .await;
+
+    if result.is_err() {
+        warn!(
+            "Graceful shutdown did not finish in {}s",
+            config.timeout.as_secs()
+        );
+    } else {
+        info!("Graceful shutdown finished");
+    }
+}
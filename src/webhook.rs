@@ -0,0 +1,621 @@
+//! Outgoing webhooks on room events
+//!
+//! Rooms hand events to a [`Webhooks`] handle, which only queues them. A
+//! background task sends them as signed JSON to all server-wide
+//! subscriptions and to the subscriptions of the room, so slow receivers
+//! never block a room. The URLs of the subscriptions of a room are stored
+//! with the room and passed along with its events, their secrets are kept
+//! in a [`WebhookSecretRepository`](crate::ports::WebhookSecretRepository)
+//! outside of the event log. Every endpoint has its own queue, so
+//! it gets the events of a room in order, one at a time. Failed deliveries
+//! are retried with exponential backoff and the outcome of recent
+//! deliveries is kept in a log.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{sleep, timeout, Duration};
+use uuid::Uuid;
+
+use crate::retry::RetryPolicy;
+use crate::room::{Round, VoteStats};
+use crate::room_id::RoomId;
+
+/// Events waiting for dispatch, newer events are dropped when it is full
+const QUEUE_SIZE: usize = 1024;
+
+/// Events waiting for delivery to one endpoint, newer events are dropped
+/// when it is full
+const ENDPOINT_QUEUE_SIZE: usize = 256;
+
+/// Endpoints without events for this long stop their delivery task
+const ENDPOINT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Deliveries kept in the log
+const LOG_SIZE: usize = 200;
+
+/// Timeout of a single delivery attempt
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Receiver of webhook events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    /// Key for the `X-Goe-Signature` header, no signature without it
+    pub secret: Option<String>,
+}
+
+/// Webhook of a room without its secret, which is never stored with the
+/// room or sent to other instances
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub url: String,
+    pub signed: bool,
+}
+
+impl From<&Webhook> for Subscription {
+    fn from(webhook: &Webhook) -> Self {
+        Self {
+            url: webhook.url.clone(),
+            signed: webhook.secret.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    RoomCreated {
+        deck: String,
+        code: Option<String>,
+        slug: Option<String>,
+    },
+    RoundRevealed {
+        round: Box<Round>,
+        stats: VoteStats,
+    },
+    /// Room was closed, it will not send any more events
+    SessionEnded {
+        rounds: u32,
+    },
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RoomCreated { .. } => "room_created",
+            Self::RoundRevealed { .. } => "round_revealed",
+            Self::SessionEnded { .. } => "session_ended",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    id: Uuid,
+    /// Unix timestamp in seconds
+    timestamp: u64,
+    room: RoomId,
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered { code: u16 },
+    Failed { error: String },
+}
+
+/// Entry of the delivery log
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub id: Uuid,
+    pub url: String,
+    pub event: &'static str,
+    pub room: RoomId,
+    pub attempts: u32,
+    #[serde(flatten)]
+    pub status: DeliveryStatus,
+    /// Unix timestamp in seconds of the last change
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Receivers of the events of all rooms
+    pub server_wide: Vec<Webhook>,
+    pub retry: RetryPolicy,
+}
+
+/// Event of a room with the subscriptions of the room
+type Queued = (RoomId, Vec<Webhook>, WebhookEvent);
+
+/// Event for one endpoint
+type Job = (Webhook, RoomId, WebhookEvent);
+
+struct Dispatcher {
+    queue: mpsc::Sender<Queued>,
+    server_wide: Vec<Webhook>,
+    log: Mutex<VecDeque<Delivery>>,
+    retry: RetryPolicy,
+    client: reqwest::Client,
+}
+
+/// Handle to queue webhook events, does nothing when not started
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    dispatcher: Option<Arc<Dispatcher>>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Hex encoded HMAC-SHA256 of the payload
+pub fn sign(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+impl Webhooks {
+    /// Start the dispatcher task
+    pub fn start(config: WebhookConfig) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let dispatcher = Arc::new(Dispatcher {
+            queue: tx,
+            server_wide: config.server_wide,
+            log: Default::default(),
+            retry: config.retry,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("HTTP client should be configurable"),
+        });
+        tokio::spawn(Self::dispatch(Arc::downgrade(&dispatcher), rx));
+        Self {
+            dispatcher: Some(dispatcher),
+        }
+    }
+
+    /// Handle that records queued events instead of sending them
+    #[cfg(test)]
+    pub(crate) fn recording() -> (Self, mpsc::Receiver<Queued>) {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let dispatcher = Arc::new(Dispatcher {
+            queue: tx,
+            server_wide: vec![],
+            log: Default::default(),
+            retry: RetryPolicy::default(),
+            client: reqwest::Client::new(),
+        });
+        let webhooks = Self {
+            dispatcher: Some(dispatcher),
+        };
+        (webhooks, rx)
+    }
+
+    /// Queue an event for the server-wide receivers and `subscriptions`
+    /// without waiting
+    pub fn emit(&self, room: RoomId, subscriptions: &[Webhook], event: WebhookEvent) {
+        let Some(dispatcher) = &self.dispatcher else {
+            return;
+        };
+        if dispatcher
+            .queue
+            .try_send((room, subscriptions.to_vec(), event))
+            .is_err()
+        {
+            warn!("{}: Dropped webhook event, queue is full", room);
+        }
+    }
+
+    /// Recent deliveries, newest first
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.dispatcher
+            .as_ref()
+            .map(|dispatcher| {
+                dispatcher
+                    .log
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    async fn dispatch(dispatcher: std::sync::Weak<Dispatcher>, mut rx: mpsc::Receiver<Queued>) {
+        let mut endpoints: HashMap<String, mpsc::Sender<Job>> = HashMap::new();
+        while let Some((room, subscriptions, event)) = rx.recv().await {
+            // handles are gone, so no receiver is left
+            let Some(dispatcher) = dispatcher.upgrade() else {
+                break;
+            };
+
+            let webhooks = dispatcher.server_wide.iter().cloned().chain(subscriptions);
+            for webhook in webhooks {
+                let mut job = (webhook, room, event.clone());
+                loop {
+                    let endpoint = endpoints
+                        .entry(job.0.url.clone())
+                        .or_insert_with(|| Self::start_endpoint(dispatcher.clone()));
+                    match endpoint.try_send(job) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full((webhook, ..))) => {
+                            warn!(
+                                "{}: Dropped webhook event for {}, queue is full",
+                                room, webhook.url
+                            );
+                            break;
+                        }
+                        Err(TrySendError::Closed(closed)) => {
+                            // task of the endpoint stopped while it was idle
+                            endpoints.remove(&closed.0.url);
+                            job = closed;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Task delivering the events of one endpoint one after another
+    fn start_endpoint(dispatcher: Arc<Dispatcher>) -> mpsc::Sender<Job> {
+        let (tx, mut rx) = mpsc::channel::<Job>(ENDPOINT_QUEUE_SIZE);
+        tokio::spawn(async move {
+            loop {
+                match timeout(ENDPOINT_IDLE_TIMEOUT, rx.recv()).await {
+                    Ok(Some((webhook, room, event))) => {
+                        dispatcher.deliver(webhook, room, event).await
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        // events queued before closing are still delivered
+                        rx.close();
+                        while let Some((webhook, room, event)) = rx.recv().await {
+                            dispatcher.deliver(webhook, room, event).await;
+                        }
+                        break;
+                    }
+                }
+            }
+        });
+        tx
+    }
+}
+
+impl Dispatcher {
+    fn update_log(&self, id: Uuid, attempts: u32, status: DeliveryStatus) {
+        let mut log = self.log.lock().unwrap();
+        if let Some(delivery) = log.iter_mut().find(|delivery| delivery.id == id) {
+            delivery.attempts = attempts;
+            delivery.status = status;
+            delivery.updated_at = unix_now();
+        }
+    }
+
+    async fn deliver(&self, webhook: Webhook, room: RoomId, event: WebhookEvent) {
+        let id = Uuid::now_v7();
+        let payload = Payload {
+            id,
+            timestamp: unix_now(),
+            room,
+            event: &event,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("{}: Failed to serialize webhook event: {}", room, err);
+                return;
+            }
+        };
+
+        {
+            let mut log = self.log.lock().unwrap();
+            if log.len() >= LOG_SIZE {
+                log.pop_front();
+            }
+            log.push_back(Delivery {
+                id,
+                url: webhook.url.clone(),
+                event: event.name(),
+                room,
+                attempts: 0,
+                status: DeliveryStatus::Pending,
+                updated_at: unix_now(),
+            });
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut request = self
+                .client
+                .post(&webhook.url)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .header("X-Goe-Event", event.name())
+                .header("X-Goe-Delivery", id.to_string())
+                .body(body.clone());
+            if let Some(secret) = &webhook.secret {
+                request =
+                    request.header("X-Goe-Signature", format!("sha256={}", sign(secret, &body)));
+            }
+
+            let (error, retryable) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("{}: Delivered {} to {}", room, event.name(), webhook.url);
+                    let code = response.status().as_u16();
+                    self.update_log(id, attempt, DeliveryStatus::Delivered { code });
+                    return;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    // other client errors will not go away by trying again
+                    let retryable = status.is_server_error()
+                        || status == http::StatusCode::TOO_MANY_REQUESTS
+                        || status == http::StatusCode::REQUEST_TIMEOUT;
+                    (format!("HTTP status {status}"), retryable)
+                }
+                Err(err) => (err.to_string(), true),
+            };
+
+            if !retryable || attempt >= self.retry.attempts {
+                warn!(
+                    "{}: Failed to deliver {} to {}: {}",
+                    room,
+                    event.name(),
+                    webhook.url,
+                    error
+                );
+                self.update_log(id, attempt, DeliveryStatus::Failed { error });
+                return;
+            }
+            self.update_log(id, attempt, DeliveryStatus::Pending);
+            sleep(self.retry.backoff(attempt)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::Router;
+    use http::{HeaderMap, StatusCode};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Received {
+        headers: HeaderMap,
+        body: serde_json::Value,
+        raw: Vec<u8>,
+    }
+
+    #[derive(Clone)]
+    struct Receiver {
+        tx: mpsc::UnboundedSender<Received>,
+        /// Requests that fail before one succeeds
+        failures: Arc<AtomicUsize>,
+        failure: StatusCode,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: axum::body::Bytes,
+    ) -> StatusCode {
+        let _ = receiver.tx.send(Received {
+            headers,
+            body: serde_json::from_slice(&body).unwrap(),
+            raw: body.to_vec(),
+        });
+        let failed = receiver
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failed {
+            receiver.failure
+        } else {
+            StatusCode::NO_CONTENT
+        }
+    }
+
+    /// Local HTTP server recording all requests
+    async fn start_receiver(
+        failures: usize,
+        failure: StatusCode,
+    ) -> (String, mpsc::UnboundedReceiver<Received>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let receiver = Receiver {
+            tx,
+            failures: Arc::new(AtomicUsize::new(failures)),
+            failure,
+        };
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, rx)
+    }
+
+    fn webhooks(server_wide: Vec<Webhook>) -> Webhooks {
+        Webhooks::start(WebhookConfig {
+            server_wide,
+            retry: RetryPolicy {
+                attempts: 3,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(50),
+            },
+        })
+    }
+
+    async fn settled(webhooks: &Webhooks) -> Vec<Delivery> {
+        for _ in 0..200 {
+            let deliveries = webhooks.deliveries();
+            if !deliveries.is_empty()
+                && deliveries
+                    .iter()
+                    .all(|delivery| delivery.status != DeliveryStatus::Pending)
+            {
+                return deliveries;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("deliveries did not settle: {:?}", webhooks.deliveries());
+    }
+
+    #[tokio::test]
+    async fn send_signed_payload() {
+        let (url, mut rx) = start_receiver(0, StatusCode::OK).await;
+        let webhooks = webhooks(vec![Webhook {
+            url,
+            secret: Some("secret".to_string()),
+        }]);
+        let room = RoomId::generate();
+
+        // ACT
+        webhooks.emit(room, &[], WebhookEvent::SessionEnded { rounds: 3 });
+
+        // ASSERT
+        let received = rx.recv().await.unwrap();
+        let signature = received.headers["X-Goe-Signature"].to_str().unwrap();
+        assert_eq!(
+            signature,
+            format!("sha256={}", sign("secret", &received.raw))
+        );
+        assert_eq!(received.headers["X-Goe-Event"], "session_ended");
+        assert_eq!(received.body["event"], "session_ended");
+        assert_eq!(received.body["room"], room.to_string());
+        assert_eq!(received.body["data"]["rounds"], 3);
+
+        let deliveries = settled(&webhooks).await;
+        assert_eq!(
+            deliveries[0].status,
+            DeliveryStatus::Delivered { code: 204 }
+        );
+        assert_eq!(deliveries[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn retry_failed_deliveries() {
+        let (url, mut rx) = start_receiver(2, StatusCode::SERVICE_UNAVAILABLE).await;
+        let webhooks = webhooks(vec![Webhook { url, secret: None }]);
+
+        // ACT
+        webhooks.emit(
+            RoomId::generate(),
+            &[],
+            WebhookEvent::SessionEnded { rounds: 0 },
+        );
+
+        // ASSERT
+        let deliveries = settled(&webhooks).await;
+        assert_eq!(
+            deliveries[0].status,
+            DeliveryStatus::Delivered { code: 204 }
+        );
+        assert_eq!(deliveries[0].attempts, 3);
+        for _ in 0..3 {
+            let received = rx.recv().await.unwrap();
+            assert!(!received.headers.contains_key("X-Goe-Signature"));
+        }
+    }
+
+    #[tokio::test]
+    async fn give_up_on_client_errors() {
+        let (url, _rx) = start_receiver(usize::MAX, StatusCode::NOT_FOUND).await;
+        let webhooks = webhooks(vec![Webhook { url, secret: None }]);
+
+        // ACT
+        webhooks.emit(
+            RoomId::generate(),
+            &[],
+            WebhookEvent::SessionEnded { rounds: 0 },
+        );
+
+        // ASSERT
+        let deliveries = settled(&webhooks).await;
+        assert_eq!(
+            deliveries[0].status,
+            DeliveryStatus::Failed {
+                error: "HTTP status 404 Not Found".to_string()
+            }
+        );
+        assert_eq!(deliveries[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn send_room_events_to_room_subscriptions() {
+        let (url, mut rx) = start_receiver(0, StatusCode::OK).await;
+        let webhooks = webhooks(vec![]);
+        let room = RoomId::generate();
+        let subscriptions = [Webhook { url, secret: None }];
+
+        // ACT
+        webhooks.emit(
+            RoomId::generate(),
+            &[],
+            WebhookEvent::SessionEnded { rounds: 1 },
+        );
+        webhooks.emit(
+            room,
+            &subscriptions,
+            WebhookEvent::SessionEnded { rounds: 2 },
+        );
+
+        // ASSERT
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.body["room"], room.to_string());
+        assert_eq!(webhooks.deliveries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deliver_events_of_an_endpoint_in_order() {
+        let (url, mut rx) = start_receiver(2, StatusCode::SERVICE_UNAVAILABLE).await;
+        let webhooks = webhooks(vec![Webhook { url, secret: None }]);
+        let room = RoomId::generate();
+
+        // ACT
+        let event = WebhookEvent::RoomCreated {
+            deck: "fibonacci".to_string(),
+            code: None,
+            slug: None,
+        };
+        webhooks.emit(room, &[], event);
+        webhooks.emit(room, &[], WebhookEvent::SessionEnded { rounds: 0 });
+
+        // ASSERT
+        let mut events = vec![];
+        for _ in 0..4 {
+            let received = rx.recv().await.unwrap();
+            events.push(received.body["event"].as_str().unwrap().to_string());
+        }
+        // the second event waits until the first one was retried successfully
+        assert_eq!(
+            events,
+            [
+                "room_created",
+                "room_created",
+                "room_created",
+                "session_ended"
+            ]
+        );
+    }
+}
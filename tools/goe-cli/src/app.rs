@@ -0,0 +1,292 @@
+use game_of_estimates::remote::RemoteMessage;
+use game_of_estimates::room::{GameState, PlayerState};
+use game_of_estimates::room_id::RoomId;
+use ratatui::crossterm::event::KeyCode;
+
+use crate::deck;
+
+/// Room to enter after connecting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Join(String),
+    Create { deck: String, slug: Option<String> },
+}
+
+/// State of the terminal client, updated by server messages and keystrokes
+pub struct App {
+    target: Target,
+    name: Option<String>,
+    voter: bool,
+    player_id: Option<String>,
+    room: Option<RoomId>,
+    /// Code or slug to share with others
+    join_name: Option<String>,
+    state: Option<GameState>,
+    players: Vec<PlayerState>,
+    cards: Vec<String>,
+    /// Index of the highlighted card
+    selected: usize,
+    status: Option<String>,
+    quit: bool,
+}
+
+impl App {
+    pub fn new(target: Target, name: Option<String>, voter: bool) -> Self {
+        let join_name = match &target {
+            Target::Join(room) => Some(room.clone()),
+            Target::Create { .. } => None,
+        };
+        Self {
+            target,
+            name,
+            voter,
+            player_id: None,
+            room: None,
+            join_name,
+            state: None,
+            players: vec![],
+            cards: vec![],
+            selected: 0,
+            status: None,
+            quit: false,
+        }
+    }
+
+    pub fn room(&self) -> Option<RoomId> {
+        self.room
+    }
+
+    pub fn join_name(&self) -> Option<&str> {
+        self.join_name.as_deref()
+    }
+
+    pub fn state(&self) -> Option<&GameState> {
+        self.state.as_ref()
+    }
+
+    pub fn players(&self) -> &[PlayerState] {
+        &self.players
+    }
+
+    pub fn cards(&self) -> &[String] {
+        &self.cards
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    pub fn is_voter(&self) -> bool {
+        self.voter
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn is_me(&self, player: &PlayerState) -> bool {
+        self.player_id.as_deref() == Some(player.id())
+    }
+
+    /// Own vote in the current round
+    pub fn own_vote(&self) -> Option<&str> {
+        let id = self.player_id.as_ref()?;
+        self.state.as_ref()?.votes().get(id)?.as_deref()
+    }
+
+    /// Messages to send in reaction to a server message
+    pub fn on_message(&mut self, msg: RemoteMessage) -> Vec<RemoteMessage> {
+        match msg {
+            RemoteMessage::Welcome { player_id, .. } => {
+                self.player_id = Some(player_id);
+                let enter = match &self.target {
+                    Target::Join(room) => RemoteMessage::JoinRoom { room: room.clone() },
+                    Target::Create { deck, slug } => RemoteMessage::CreateRoom {
+                        deck: deck.clone(),
+                        slug: slug.clone(),
+                    },
+                };
+                return vec![
+                    RemoteMessage::UpdatePlayer {
+                        voter: self.voter,
+                        name: self.name.clone(),
+                    },
+                    enter,
+                ];
+            }
+            RemoteMessage::RoomCreated { code, slug, .. } => {
+                self.join_name = slug.or(code);
+            }
+            RemoteMessage::Joined {
+                room,
+                state,
+                players,
+            } => {
+                self.room = Some(room);
+                self.set_state(state);
+                self.players = players;
+                self.status = None;
+            }
+            RemoteMessage::PlayerJoined { player } => self.players.push(player),
+            RemoteMessage::PlayerChanged { player } => {
+                match self.players.iter_mut().find(|p| p.id() == player.id()) {
+                    Some(existing) => *existing = player,
+                    None => self.players.push(player),
+                }
+            }
+            RemoteMessage::PlayerLeft { player_id } => {
+                self.players.retain(|player| player.id() != player_id)
+            }
+            RemoteMessage::GameChanged { game_state } => self.set_state(game_state),
+            RemoteMessage::Rejected => {
+                self.status = Some("Could not join the room".to_string());
+            }
+            RemoteMessage::Notice { message } | RemoteMessage::Error { message, .. } => {
+                self.status = Some(message);
+            }
+            RemoteMessage::ServerShuttingDown { .. } => {
+                self.status = Some("Server is restarting".to_string());
+            }
+            _ => {}
+        }
+        vec![]
+    }
+
+    fn set_state(&mut self, state: GameState) {
+        if self.state.as_ref().map(GameState::deck) != Some(state.deck()) {
+            self.cards = deck::cards(state.deck());
+            self.selected = 0;
+            if !deck::is_known(state.deck()) {
+                self.status = Some(format!("Unknown deck {}", state.deck()));
+            }
+        }
+        self.state = Some(state);
+    }
+
+    /// Message to send for a keystroke
+    pub fn on_key(&mut self, key: KeyCode) -> Option<RemoteMessage> {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.quit = true;
+                None
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                self.selected = self.selected.saturating_sub(1);
+                None
+            }
+            KeyCode::Right | KeyCode::Char('l') => {
+                if self.selected + 1 < self.cards.len() {
+                    self.selected += 1;
+                }
+                None
+            }
+            _ if self.room.is_none() => None,
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                let card = self.cards.get(self.selected)?;
+                // voting for the own card again takes the vote back
+                let vote = Some(card.clone()).filter(|card| self.own_vote() != Some(card));
+                Some(RemoteMessage::Vote { vote })
+            }
+            KeyCode::Backspace | KeyCode::Delete => Some(RemoteMessage::Vote { vote: None }),
+            KeyCode::Char('o') => Some(RemoteMessage::ForceOpen),
+            KeyCode::Char('r') => Some(RemoteMessage::Restart),
+            KeyCode::Char('v') => {
+                self.voter = !self.voter;
+                Some(RemoteMessage::UpdatePlayer {
+                    voter: self.voter,
+                    name: self.name.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(value: serde_json::Value) -> RemoteMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn joined_app() -> App {
+        let mut app = App::new(Target::Join("planning".to_string()), None, true);
+        app.on_message(message(json!({
+            "type": "Welcome", "player_id": "me", "protocol": 2, "features": [],
+        })));
+        app.on_message(message(json!({
+            "type": "Joined",
+            "room": "AAAAAAAAAAAAAAAAAAAAAA",
+            "state": {"deck": "t-shirt-sizes", "open": false, "votes": {"me": null}},
+            "players": [{"id": "me", "name": null, "voter": true}],
+        })));
+        app
+    }
+
+    #[test]
+    fn join_room_after_welcome() {
+        let mut app = App::new(Target::Join("planning".to_string()), None, false);
+
+        // ACT
+        let replies = app.on_message(message(json!({
+            "type": "Welcome", "player_id": "me", "protocol": 2, "features": [],
+        })));
+
+        // ASSERT
+        assert_eq!(
+            replies,
+            vec![
+                RemoteMessage::UpdatePlayer {
+                    voter: false,
+                    name: None
+                },
+                RemoteMessage::JoinRoom {
+                    room: "planning".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn vote_for_selected_card() {
+        let mut app = joined_app();
+        assert_eq!(app.cards()[0], "XS");
+
+        // ACT
+        app.on_key(KeyCode::Right);
+        let vote = app.on_key(KeyCode::Enter);
+
+        // ASSERT
+        assert_eq!(
+            vote,
+            Some(RemoteMessage::Vote {
+                vote: Some("S".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn track_players() {
+        let mut app = joined_app();
+
+        // ACT
+        app.on_message(message(json!({
+            "type": "PlayerJoined", "player": {"id": "p2", "name": "Bob", "voter": true},
+        })));
+        app.on_message(message(json!({
+            "type": "PlayerChanged", "player": {"id": "p2", "name": "Bobby", "voter": false},
+        })));
+        app.on_message(message(json!({"type": "PlayerLeft", "player_id": "me"})));
+
+        // ASSERT
+        assert_eq!(app.players().len(), 1);
+        assert_eq!(app.players()[0].name(), Some("Bobby"));
+        assert!(!app.players()[0].is_voter());
+    }
+}
@@ -0,0 +1,51 @@
+//! Cards of the decks, same as in the web frontend
+
+const DECKS: &[(&str, &[&str])] = &[
+    (
+        "mod-fibonacci",
+        &[
+            "0", "½", "1", "2", "3", "5", "8", "13", "20", "40", "100", "?", "☕",
+        ],
+    ),
+    (
+        "fibonacci",
+        &[
+            "0", "1", "2", "3", "5", "8", "13", "21", "34", "55", "89", "?", "☕",
+        ],
+    ),
+    (
+        "t-shirt-sizes",
+        &["XS", "S", "M", "L", "XL", "XXL", "?", "☕"],
+    ),
+    (
+        "power-of-2",
+        &["0", "1", "2", "4", "8", "16", "32", "64", "?", "☕"],
+    ),
+    (
+        "sequential",
+        &[
+            "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "?", "☕",
+        ],
+    ),
+];
+
+/// Cards of a deck, empty for unknown decks
+pub fn cards(deck: &str) -> Vec<String> {
+    if let Some(cards) = deck.strip_prefix("custom:") {
+        return cards
+            .split(',')
+            .map(str::trim)
+            .filter(|card| !card.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    DECKS
+        .iter()
+        .find(|(id, _)| *id == deck)
+        .map(|(_, cards)| cards.iter().map(|card| card.to_string()).collect())
+        .unwrap_or_default()
+}
+
+pub fn is_known(deck: &str) -> bool {
+    deck.starts_with("custom:") || DECKS.iter().any(|(id, _)| *id == deck)
+}
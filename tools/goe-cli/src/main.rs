@@ -0,0 +1,227 @@
+//! Terminal client for Game Of Estimates
+//!
+//! Connects to the WebSocket of a server, creates or joins a room and shows
+//! players and votes live.
+
+use std::env;
+use std::process::ExitCode;
+
+use anyhow::{anyhow, bail, Context};
+use futures_util::{SinkExt, StreamExt};
+use game_of_estimates::remote::{RemoteMessage, PROTOCOL_VERSION};
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::app::{App, Target};
+
+mod app;
+mod deck;
+mod ui;
+
+const USAGE: &str = "\
+Usage: goe-cli [OPTIONS] <SERVER> [ROOM]
+
+Joins ROOM (ID, code or name) on SERVER, like https://example.com, or
+creates a new room when no room is given.
+
+Options:
+  --name <NAME>  Name shown to the other players
+  --observer     Join without voting
+  --deck <DECK>  Deck of a new room, like fibonacci, t-shirt-sizes or custom:1,2,3
+  --slug <SLUG>  Custom name of a new room
+  -h, --help     Show this help";
+
+struct Args {
+    server: String,
+    target: Target,
+    name: Option<String>,
+    voter: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Args>> {
+    let mut positional = vec![];
+    let mut name = None;
+    let mut voter = true;
+    let mut deck = "fibonacci".to_string();
+    let mut slug = None;
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| args.next().ok_or_else(|| anyhow!("{option} needs a value"));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--name" => name = Some(value("--name")?),
+            "--observer" => voter = false,
+            "--deck" => deck = value("--deck")?,
+            "--slug" => slug = Some(value("--slug")?),
+            option if option.starts_with('-') => bail!("unknown option {option}"),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let server = positional.next().context("server is missing")?;
+    let target = match positional.next() {
+        Some(room) => Target::Join(room),
+        None => Target::Create { deck, slug },
+    };
+    if positional.next().is_some() {
+        bail!("too many arguments");
+    }
+    Ok(Some(Args {
+        server,
+        target,
+        name,
+        voter,
+    }))
+}
+
+/// WebSocket URL for a server URL
+fn websocket_url(server: &str) -> String {
+    let server = server.trim_end_matches('/');
+    let url = if let Some(rest) = server.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else if let Some(rest) = server.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else if server.starts_with("ws://") || server.starts_with("wss://") {
+        server.to_string()
+    } else {
+        format!("ws://{server}")
+    };
+    format!("{url}/ws?protocol={PROTOCOL_VERSION}")
+}
+
+async fn run(args: Args) -> anyhow::Result<Option<String>> {
+    let url = websocket_url(&args.server);
+    let (socket, _) = tokio_tungstenite::connect_async(&url)
+        .await
+        .with_context(|| format!("failed to connect to {url}"))?;
+    let (mut sink, mut stream) = socket.split();
+
+    // crossterm reads blocking, so terminal events come from a thread
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if events_tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut app = App::new(args.target, args.name, args.voter);
+    let mut terminal = ratatui::init();
+    let result = async {
+        loop {
+            terminal.draw(|frame| ui::render(frame, &app))?;
+
+            let replies = tokio::select! {
+                event = events.recv() => match event {
+                    Some(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                        app.on_key(key.code).into_iter().collect()
+                    }
+                    Some(_) => vec![],
+                    None => return Ok(None),
+                },
+                msg = stream.next() => match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        let msg: RemoteMessage = serde_json::from_str(text.as_str())
+                            .context("invalid message from server")?;
+                        app.on_message(msg)
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        return Ok(Some(frame.map_or_else(
+                            || "Connection closed".to_string(),
+                            |frame| frame.reason.to_string(),
+                        )));
+                    }
+                    Some(Ok(_)) => vec![],
+                    Some(Err(err)) => return Err(err.into()),
+                    None => return Ok(Some("Connection closed".to_string())),
+                },
+            };
+
+            for reply in replies {
+                sink.send(Message::text(serde_json::to_string(&reply)?))
+                    .await?;
+            }
+            if app.should_quit() {
+                let _ = sink.send(Message::Close(None)).await;
+                return Ok(None);
+            }
+        }
+    }
+    .await;
+    ratatui::restore();
+    result
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("Error: {err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(args).await {
+        Ok(None) => ExitCode::SUCCESS,
+        Ok(Some(reason)) => {
+            eprintln!("{reason}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> anyhow::Result<Option<Args>> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn build_websocket_url() {
+        assert_eq!(
+            websocket_url("https://example.com/"),
+            "wss://example.com/ws?protocol=2"
+        );
+        assert_eq!(
+            websocket_url("localhost:5500"),
+            "ws://localhost:5500/ws?protocol=2"
+        );
+    }
+
+    #[test]
+    fn parse_command_line() {
+        let parsed = args(&["--name", "Alice", "http://localhost", "planning"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.name.as_deref(), Some("Alice"));
+        assert_eq!(parsed.target, Target::Join("planning".to_string()));
+
+        let parsed = args(&["--deck", "t-shirt-sizes", "--observer", "http://localhost"])
+            .unwrap()
+            .unwrap();
+        assert!(!parsed.voter);
+        assert_eq!(
+            parsed.target,
+            Target::Create {
+                deck: "t-shirt-sizes".to_string(),
+                slug: None
+            }
+        );
+
+        assert!(args(&["--name"]).is_err());
+        assert!(args(&[]).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Row, Table, Wrap};
+use ratatui::Frame;
+
+use crate::app::App;
+
+const HELP: &str = "←/→ select  ⏎ vote  ⌫ withdraw  o open  r restart  v voter/observer  q quit";
+
+pub fn render(frame: &mut Frame, app: &App) {
+    let [header, players, cards, footer] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Min(3),
+        Constraint::Length(3),
+        Constraint::Length(2),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(header_widget(app), header);
+    frame.render_widget(players_widget(app), players);
+    frame.render_widget(cards_widget(app), cards);
+
+    let mut footer_lines = vec![Line::from(HELP).dim()];
+    if let Some(status) = app.status() {
+        footer_lines.insert(0, Line::from(status).yellow());
+    }
+    frame.render_widget(Paragraph::new(footer_lines), footer);
+}
+
+fn header_widget(app: &App) -> Paragraph<'_> {
+    let Some(state) = app.state() else {
+        return Paragraph::new("Connecting …").block(Block::bordered().title("Game Of Estimates"));
+    };
+
+    let room = app
+        .join_name()
+        .map(str::to_string)
+        .or_else(|| app.room().map(|room| room.to_string()))
+        .unwrap_or_default();
+    let phase = if state.is_open() {
+        "cards are open".green().bold()
+    } else {
+        "voting".bold()
+    };
+    let story = match state.story() {
+        Some(story) => match &story.key {
+            Some(key) => format!("{key}: {}", story.title),
+            None => story.title.clone(),
+        },
+        None => "No story".to_string(),
+    };
+
+    Paragraph::new(vec![
+        Line::from(vec![Span::raw(story)]),
+        Line::from(vec![Span::raw("Round: "), phase]),
+    ])
+    .wrap(Wrap { trim: true })
+    .block(Block::bordered().title(format!("Room {room}")))
+}
+
+fn players_widget(app: &App) -> Table<'_> {
+    let open = app.state().is_some_and(|state| state.is_open());
+    let rows = app.players().iter().map(|player| {
+        let mut name = player.name().unwrap_or("Anonymous").to_string();
+        if app.is_me(player) {
+            name.push_str(" (you)");
+        }
+        let vote = app.state().and_then(|state| state.votes().get(player.id()));
+        let status = match vote {
+            None if !player.is_voter() => "observer".dim(),
+            None => "".into(),
+            Some(None) => "…".dim(),
+            Some(Some(vote)) if open => vote.clone().bold(),
+            Some(Some(_)) => "✓".green(),
+        };
+        Row::new(vec![Line::from(name), Line::from(status)])
+    });
+
+    Table::new(rows, [Constraint::Fill(1), Constraint::Length(10)])
+        .header(Row::new(["Player", "Vote"]).underlined())
+        .block(Block::bordered().title("Players"))
+}
+
+fn cards_widget(app: &App) -> Paragraph<'_> {
+    let own_vote = app.own_vote();
+    let mut spans = vec![];
+    for (i, card) in app.cards().iter().enumerate() {
+        let mut style = Style::new();
+        if Some(card.as_str()) == own_vote {
+            style = style.green().bold();
+        }
+        if i == app.selected() {
+            style = style.reversed();
+        }
+        spans.push(Span::styled(format!(" {card} "), style));
+        spans.push(Span::raw(" "));
+    }
+
+    let title = if app.is_voter() {
+        "Cards"
+    } else {
+        "Cards (observer)"
+    };
+    Paragraph::new(Line::from(spans)).block(Block::bordered().title(title))
+}
@@ -0,0 +1,204 @@
+//! Load generator for Game Of Estimates
+//!
+//! Opens WebSocket connections of simulated players across rooms of a
+//! running server. The players vote, the first player of every room opens
+//! the cards when needed and starts the next round. Reports connection
+//! failures and how long votes and restarts took to reach the other players.
+
+use std::env;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use game_of_estimates::remote::PROTOCOL_VERSION;
+use tokio::sync::watch;
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+use crate::player::{Config, Role, RoomTimings, SimulatedPlayer};
+use crate::stats::Stats;
+
+mod player;
+mod stats;
+
+const USAGE: &str = "\
+Usage: goe-load [OPTIONS] <SERVER>
+
+Simulates players on SERVER, like http://localhost:5500.
+
+Options:
+  --players <N>        Connections to open (default: 100)
+  --rooms <M>          Rooms to spread the players across (default: 10)
+  --duration <SECS>    Length of the test (default: 60)
+  --ramp-up <SECS>     Time over which connections are opened (default: 10)
+  --think-time <MS>    Maximum delay before votes and restarts (default: 2000)
+  -h, --help           Show this help";
+
+/// Time for players to close their connections at the end
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    server: String,
+    players: usize,
+    rooms: usize,
+    duration: Duration,
+    ramp_up: Duration,
+    think_time: Duration,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Args>> {
+    let mut server = None;
+    let mut parsed = Args {
+        server: String::new(),
+        players: 100,
+        rooms: 10,
+        duration: Duration::from_secs(60),
+        ramp_up: Duration::from_secs(10),
+        think_time: Duration::from_millis(2000),
+    };
+    while let Some(arg) = args.next() {
+        let mut number = |option: &str| -> anyhow::Result<u64> {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{option} needs a value"))?;
+            value
+                .parse()
+                .with_context(|| format!("invalid value {value} of {option}"))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--players" => parsed.players = number("--players")? as usize,
+            "--rooms" => parsed.rooms = number("--rooms")? as usize,
+            "--duration" => parsed.duration = Duration::from_secs(number("--duration")?),
+            "--ramp-up" => parsed.ramp_up = Duration::from_secs(number("--ramp-up")?),
+            "--think-time" => parsed.think_time = Duration::from_millis(number("--think-time")?),
+            option if option.starts_with('-') => bail!("unknown option {option}"),
+            _ if server.is_some() => bail!("too many arguments"),
+            _ => server = Some(arg),
+        }
+    }
+
+    parsed.server = server.context("server is missing")?;
+    if parsed.rooms == 0 || parsed.players < parsed.rooms {
+        bail!("every room needs at least one player");
+    }
+    Ok(Some(parsed))
+}
+
+/// WebSocket URL for a server URL
+fn websocket_url(server: &str) -> String {
+    let server = server.trim_end_matches('/');
+    let url = if let Some(rest) = server.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else if let Some(rest) = server.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else if server.starts_with("ws://") || server.starts_with("wss://") {
+        server.to_string()
+    } else {
+        format!("ws://{server}")
+    };
+    format!("{url}/ws?protocol={PROTOCOL_VERSION}")
+}
+
+async fn run(args: Args) -> stats::Report {
+    let config = Arc::new(Config {
+        url: websocket_url(&args.server),
+        think_time: args.think_time,
+        reveal_timeout: args.think_time * 2 + Duration::from_secs(1),
+    });
+    let stats = Arc::new(Stats::default());
+    let (stop_tx, stop) = watch::channel(false);
+
+    let rooms: Vec<_> = (0..args.rooms)
+        .map(|_| {
+            let (room_tx, room_rx) = watch::channel(None);
+            (room_tx, room_rx, Arc::new(RoomTimings::default()))
+        })
+        .collect();
+
+    let start = Instant::now();
+    let mut tasks = Vec::with_capacity(args.players);
+    let mut leaders = rooms.iter().map(|(room_tx, _, _)| room_tx.clone());
+    for i in 0..args.players {
+        let (_, room_rx, timings) = &rooms[i % args.rooms];
+        // first player of every room creates it
+        let role = match leaders.next() {
+            Some(room_tx) => Role::Leader(room_tx),
+            None => Role::Member(room_rx.clone()),
+        };
+        let player = SimulatedPlayer::new(
+            config.clone(),
+            role,
+            timings.clone(),
+            stats.clone(),
+            stop.clone(),
+        );
+        let connect_at = start + args.ramp_up.mul_f64(i as f64 / args.players as f64);
+        tasks.push(tokio::spawn(async move {
+            tokio::time::sleep_until(connect_at).await;
+            player.run().await
+        }));
+    }
+    // members notice when their leader gave up
+    drop(rooms);
+
+    sleep(args.duration).await;
+    let elapsed = start.elapsed();
+    let _ = stop_tx.send(true);
+    let _ = timeout(SHUTDOWN_GRACE, futures_util::future::join_all(tasks)).await;
+
+    stats.report(elapsed)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("Error: {err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    eprintln!(
+        "Simulating {} players in {} rooms for {} s",
+        args.players,
+        args.rooms,
+        args.duration.as_secs()
+    );
+    let report = run(args).await;
+    print!("{report}");
+    if report.has_failures() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> anyhow::Result<Option<Args>> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_command_line() {
+        let parsed = args(&["--players", "20", "--rooms", "4", "localhost:5500"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.server, "localhost:5500");
+        assert_eq!(parsed.players, 20);
+        assert_eq!(parsed.rooms, 4);
+        assert_eq!(parsed.duration, Duration::from_secs(60));
+
+        assert!(args(&["--players", "many", "localhost"]).is_err());
+        assert!(args(&["--players", "2", "--rooms", "3", "localhost"]).is_err());
+        assert!(args(&[]).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context};
+use futures_util::{SinkExt, StreamExt};
+use game_of_estimates::remote::RemoteMessage;
+use game_of_estimates::room::GameState;
+use game_of_estimates::room_id::RoomId;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::time::{sleep_until, timeout, Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::stats::Stats;
+
+const CARDS: &[&str] = &["1", "2", "3", "5", "8", "13", "21", "?"];
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct Config {
+    pub url: String,
+    /// Players wait up to this long before voting and restarting
+    pub think_time: Duration,
+    /// Leader opens the cards when not all players voted in time
+    pub reveal_timeout: Duration,
+}
+
+/// When the messages were sent that the players of a room should receive
+#[derive(Default)]
+pub struct RoomTimings {
+    votes: Mutex<HashMap<String, Instant>>,
+    restart: Mutex<Option<Instant>>,
+}
+
+/// Leader creates the room, the other players join it
+pub enum Role {
+    Leader(watch::Sender<Option<RoomId>>),
+    Member(watch::Receiver<Option<RoomId>>),
+}
+
+/// Simulated player, connected to one room until `stop` changes
+pub struct SimulatedPlayer {
+    config: Arc<Config>,
+    role: Role,
+    timings: Arc<RoomTimings>,
+    stats: Arc<Stats>,
+    stop: watch::Receiver<bool>,
+
+    id: String,
+    open: bool,
+    /// Players whose vote in this round was already seen
+    seen_votes: HashSet<String>,
+    vote_at: Option<Instant>,
+    restart_at: Option<Instant>,
+    force_open_at: Option<Instant>,
+}
+
+impl SimulatedPlayer {
+    pub fn new(
+        config: Arc<Config>,
+        role: Role,
+        timings: Arc<RoomTimings>,
+        stats: Arc<Stats>,
+        stop: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            config,
+            role,
+            timings,
+            stats,
+            stop,
+            id: String::new(),
+            open: false,
+            seen_votes: HashSet::new(),
+            vote_at: None,
+            restart_at: None,
+            force_open_at: None,
+        }
+    }
+
+    fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader(_))
+    }
+
+    fn think_time(&self) -> Duration {
+        self.config
+            .think_time
+            .mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    pub async fn run(mut self) {
+        let start = Instant::now();
+        let socket = match timeout(
+            CONNECT_TIMEOUT,
+            tokio_tungstenite::connect_async(&self.config.url),
+        )
+        .await
+        {
+            Ok(Ok((socket, _))) => socket,
+            _ => {
+                self.stats.connect_failed();
+                return;
+            }
+        };
+        self.stats.connected(start.elapsed());
+
+        match self.play(socket).await {
+            Ok(()) => {}
+            Err(_) if *self.stop.borrow() => {}
+            Err(_) => self.stats.dropped(),
+        }
+    }
+
+    async fn play(&mut self, mut socket: Socket) -> anyhow::Result<()> {
+        self.id = match self.recv(&mut socket).await? {
+            Some(RemoteMessage::Welcome { player_id, .. }) => player_id,
+            Some(msg) => bail!("expected welcome, got {msg:?}"),
+            None => return Ok(()),
+        };
+
+        let enter = match &mut self.role {
+            Role::Leader(_) => RemoteMessage::CreateRoom {
+                deck: "fibonacci".to_string(),
+                slug: None,
+            },
+            Role::Member(room) => {
+                let room = tokio::select! {
+                    res = room.wait_for(Option::is_some) => match res {
+                        Ok(room) => room.unwrap(),
+                        // leader could not create the room
+                        Err(_) => return Ok(()),
+                    },
+                    _ = self.stop.changed() => return Ok(()),
+                };
+                RemoteMessage::JoinRoom {
+                    room: room.to_string(),
+                }
+            }
+        };
+        send(&mut socket, &enter).await?;
+
+        loop {
+            let deadline = [self.vote_at, self.restart_at, self.force_open_at]
+                .into_iter()
+                .flatten()
+                .min();
+            tokio::select! {
+                msg = self.recv(&mut socket) => match msg? {
+                    Some(msg) => self.on_message(msg),
+                    None => return Ok(()),
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    if let Some(msg) = self.on_timer() {
+                        send(&mut socket, &msg).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Next message, `None` when the test is over
+    async fn recv(&mut self, socket: &mut Socket) -> anyhow::Result<Option<RemoteMessage>> {
+        loop {
+            tokio::select! {
+                msg = socket.next() => match msg.context("connection closed")?? {
+                    Message::Text(text) => return Ok(Some(serde_json::from_str(text.as_str())?)),
+                    Message::Close(_) => bail!("connection closed by server"),
+                    _ => {}
+                },
+                _ = self.stop.changed() => {
+                    let _ = socket.close(None).await;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn on_message(&mut self, msg: RemoteMessage) {
+        match msg {
+            RemoteMessage::Joined { room, state, .. } => {
+                if let Role::Leader(room_tx) = &self.role {
+                    let _ = room_tx.send(Some(room));
+                }
+                self.seen_votes = voted(&state).map(str::to_string).collect();
+                self.open = state.is_open();
+                self.schedule(&state);
+            }
+            RemoteMessage::GameChanged { game_state } => self.on_game_changed(game_state),
+            RemoteMessage::Error { code, .. } => self.stats.error(format!("{code:?}")),
+            _ => {}
+        }
+    }
+
+    fn on_game_changed(&mut self, state: GameState) {
+        let now = Instant::now();
+        if self.open && !state.is_open() {
+            // restarted
+            self.seen_votes.clear();
+            let sent = *self.timings.restart.lock().unwrap();
+            if let (false, Some(sent)) = (self.is_leader(), sent) {
+                self.stats.broadcast(now - sent);
+            }
+        }
+
+        let votes = self.timings.votes.lock().unwrap();
+        for id in voted(&state) {
+            if id != self.id && self.seen_votes.insert(id.to_string()) {
+                if let Some(sent) = votes.get(id) {
+                    self.stats.broadcast(now - *sent);
+                }
+            }
+        }
+        drop(votes);
+
+        if !self.open && state.is_open() && self.is_leader() {
+            self.stats.round_finished();
+        }
+        self.open = state.is_open();
+        self.schedule(&state);
+    }
+
+    fn schedule(&mut self, state: &GameState) {
+        let now = Instant::now();
+        let own_vote = state.votes().get(&self.id);
+        if !state.is_open() && own_vote == Some(&None) && self.vote_at.is_none() {
+            self.vote_at = Some(now + self.think_time());
+        }
+        if self.is_leader() {
+            if state.is_open() {
+                self.force_open_at = None;
+                if self.restart_at.is_none() {
+                    self.restart_at = Some(now + self.think_time());
+                }
+            } else if self.force_open_at.is_none() {
+                self.force_open_at = Some(now + self.config.reveal_timeout);
+            }
+        }
+    }
+
+    fn on_timer(&mut self) -> Option<RemoteMessage> {
+        let now = Instant::now();
+        let due = |at: Option<Instant>| at.is_some_and(|at| at <= now);
+
+        if due(self.vote_at) {
+            self.vote_at = None;
+            if self.open {
+                return None;
+            }
+            self.timings
+                .votes
+                .lock()
+                .unwrap()
+                .insert(self.id.clone(), now);
+            let card = CARDS.choose(&mut rand::thread_rng()).unwrap();
+            Some(RemoteMessage::Vote {
+                vote: Some(card.to_string()),
+            })
+        } else if due(self.restart_at) {
+            self.restart_at = None;
+            self.timings.votes.lock().unwrap().clear();
+            *self.timings.restart.lock().unwrap() = Some(now);
+            Some(RemoteMessage::Restart)
+        } else if due(self.force_open_at) {
+            self.force_open_at = None;
+            Some(RemoteMessage::ForceOpen)
+        } else {
+            None
+        }
+    }
+}
+
+/// Players that voted
+fn voted(state: &GameState) -> impl Iterator<Item = &str> {
+    state
+        .votes()
+        .iter()
+        .filter(|(_, vote)| vote.is_some())
+        .map(|(id, _)| id.as_str())
+}
+
+async fn send(socket: &mut Socket, msg: &RemoteMessage) -> anyhow::Result<()> {
+    socket
+        .send(Message::text(serde_json::to_string(msg)?))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Measurements of all simulated players
+#[derive(Default)]
+pub struct Stats {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    connected: u64,
+    connect_failures: u64,
+    /// Connections closed before the end of the test
+    dropped: u64,
+    rounds: u64,
+    connect_latencies: Vec<Duration>,
+    /// Time from sending a vote or restart until another player saw it
+    broadcast_latencies: Vec<Duration>,
+    /// Error messages of the server by code
+    errors: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    pub fn connected(&self, latency: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.connected += 1;
+        inner.connect_latencies.push(latency);
+    }
+
+    pub fn connect_failed(&self) {
+        self.inner.lock().unwrap().connect_failures += 1;
+    }
+
+    pub fn dropped(&self) {
+        self.inner.lock().unwrap().dropped += 1;
+    }
+
+    pub fn round_finished(&self) {
+        self.inner.lock().unwrap().rounds += 1;
+    }
+
+    pub fn broadcast(&self, latency: Duration) {
+        self.inner.lock().unwrap().broadcast_latencies.push(latency);
+    }
+
+    pub fn error(&self, code: String) {
+        *self.inner.lock().unwrap().errors.entry(code).or_default() += 1;
+    }
+
+    pub fn report(&self, elapsed: Duration) -> Report {
+        let mut inner = self.inner.lock().unwrap();
+        Report {
+            elapsed,
+            connected: inner.connected,
+            connect_failures: inner.connect_failures,
+            dropped: inner.dropped,
+            rounds: inner.rounds,
+            connect: Percentiles::of(&mut inner.connect_latencies),
+            broadcast: Percentiles::of(&mut inner.broadcast_latencies),
+            errors: inner.errors.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    /// `None` without samples
+    pub fn of(samples: &mut [Duration]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        // nearest-rank method
+        let rank = |p: f64| samples[((p * samples.len() as f64).ceil() as usize).max(1) - 1];
+        Some(Self {
+            samples: samples.len(),
+            p50: rank(0.5),
+            p90: rank(0.9),
+            p99: rank(0.99),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+impl Display for Percentiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "p50 {:.1} ms, p90 {:.1} ms, p99 {:.1} ms, max {:.1} ms ({} samples)",
+            ms(self.p50),
+            ms(self.p90),
+            ms(self.p99),
+            ms(self.max),
+            self.samples
+        )
+    }
+}
+
+pub struct Report {
+    elapsed: Duration,
+    connected: u64,
+    connect_failures: u64,
+    dropped: u64,
+    rounds: u64,
+    connect: Option<Percentiles>,
+    broadcast: Option<Percentiles>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Report {
+    /// Whether some connections failed or were dropped
+    pub fn has_failures(&self) -> bool {
+        self.connect_failures > 0 || self.dropped > 0
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let or_none = |p: &Option<Percentiles>| match p {
+            Some(p) => p.to_string(),
+            None => "no samples".to_string(),
+        };
+        writeln!(f, "Duration:     {:.1} s", self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "Connections:  {} connected, {} failed, {} dropped",
+            self.connected, self.connect_failures, self.dropped
+        )?;
+        writeln!(f, "Rounds:       {}", self.rounds)?;
+        writeln!(f, "Connect:      {}", or_none(&self.connect))?;
+        writeln!(f, "Broadcast:    {}", or_none(&self.broadcast))?;
+        if !self.errors.is_empty() {
+            writeln!(f, "Errors:")?;
+            for (code, count) in &self.errors {
+                writeln!(f, "  {code}: {count}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_percentiles() {
+        let mut samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+
+        // ACT
+        let percentiles = Percentiles::of(&mut samples).unwrap();
+
+        // ASSERT
+        assert_eq!(percentiles.samples, 100);
+        assert_eq!(percentiles.p50, Duration::from_millis(50));
+        assert_eq!(percentiles.p90, Duration::from_millis(90));
+        assert_eq!(percentiles.p99, Duration::from_millis(99));
+        assert_eq!(percentiles.max, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn compute_percentiles_of_few_samples() {
+        let mut samples = vec![Duration::from_millis(7)];
+        let percentiles = Percentiles::of(&mut samples).unwrap();
+        assert_eq!(percentiles.p50, Duration::from_millis(7));
+        assert_eq!(percentiles.p99, Duration::from_millis(7));
+
+        assert_eq!(Percentiles::of(&mut []), None);
+    }
+}